@@ -1,13 +1,19 @@
+pub mod protocol;
+
 use bincode::{Decode, Encode};
+use protocol::Hello;
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ServerStatus {
     pub host: String,
     pub container: ContainerStatus,
     pub online: OnlinePeople,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Encode, Decode)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum ClientOpt {
     /// Power on the server.
     On,
@@ -15,6 +21,20 @@ pub enum ClientOpt {
     Off,
     /// Reboot the server.
     Reboot,
+    /// Poll the server right away instead of waiting for the next poll.
+    ///
+    /// Only to a sentinel with the [`protocol::REFRESH`] capability.
+    RefreshNow,
+    /// `docker compose up -d` the minecraft project.
+    ///
+    /// The compose ones only to a sentinel with the [`protocol::COMPOSE`] capability.
+    ComposeUp,
+    /// `docker compose down` the minecraft project.
+    ComposeDown,
+    /// `docker compose restart` the minecraft project.
+    ComposeRestart,
+    /// `docker compose pull` the minecraft project's images, only for admins.
+    ComposePull,
 }
 
 // reference output of `sudo docker-compose up -d`
@@ -23,24 +43,332 @@ pub enum ClientOpt {
 // 93b4bc8169e5   itzg/minecraft-server   "/start"   3 hours ago   Up 6 seconds (health: starting)   0.0.0.0:25565->25565/tcp, :::25565->25565/tcp, 25575/tcp   mc_mc_1
 
 #[derive(Clone, Encode, Decode, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum ContainerStatus {
     Unknown,
     NotUp,
     Up(String),
 }
 
+/// The minecraft container as docker sees it, apart from the host's ec2 state.
+#[derive(Clone, Encode, Decode, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Container {
+    pub name: String,
+    pub image: String,
+    /// The image's repo digest, `None` if it has none or it couldn't be read.
+    pub digest: Option<String>,
+    /// e.g. `running` or `exited`.
+    pub state: String,
+    /// `healthy`, `unhealthy` or `starting`, `None` without a health check.
+    pub health: Option<String>,
+    /// e.g. `Up 2 hours (healthy)`.
+    pub status: String,
+}
+
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum OnlinePeople {
     Unknown,
     Known(String),
 }
 
 /// server talks in newspeak
+///
+/// Append only, see [`protocol`].
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub enum Newspeak {
     AuthResult(AuthResult),
     ServerStatus(ServerStatus),
     Feedback(String),
+    /// Answer to the client's [`Oldspeak::Hello`].
+    Hello(Hello),
+    /// Only for clients with the [`protocol::RESPONSES`] capability, they get no `Feedback`.
+    Response(Response),
+    /// Sent after [`AuthResult::Goob`] to clients with the [`protocol::ADMIN`] capability.
+    Clearance(Role),
+    /// Only for admins with the [`protocol::ADMIN`] capability, sent whenever it changes.
+    Lockouts(Vec<Lockout>),
+    /// Answer to [`Oldspeak::Heartbeat`].
+    Heartbeat,
+    /// Only for admins with the [`protocol::SESSIONS`] capability, sent whenever someone comes,
+    /// goes or authenticates.
+    Sessions(Vec<Session>),
+    /// Only for authenticated clients with the [`protocol::CRASHES`] capability, sent when the
+    /// server crashes and, right after authenticating, about the last crash if there was one.
+    Crash(CrashReport),
+    /// Only for clients with the [`protocol::COMPOSE`] capability, sent after every
+    /// [`Newspeak::ServerStatus`]. `None` if the host is down or the container doesn't exist.
+    Container(Option<Container>),
+    /// Answer to [`Oldspeak::ReadConfig`], and sent again after a config was applied.
+    Config(Vec<ConfigEntry>),
+    /// Answer to [`Oldspeak::ReviewConfig`].
+    ConfigReview(ConfigReview),
+    /// Answer to [`Oldspeak::ReadRoster`], and sent again after every [`Oldspeak::EditRoster`].
+    Roster(Roster),
+    /// Answer to [`Oldspeak::EditRoster`], either completed or why not.
+    RosterEdited(Outcome),
+    /// Only for clients with the [`protocol::MODPACK`] capability, sent with the first
+    /// [`Newspeak::ServerStatus`] and whenever it changes. `None` if the server isn't running or
+    /// its pack has no manifest.
+    Modpack(Option<Modpack>),
+}
+
+/// What a caller is allowed to do.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Role {
+    /// Can look at the status but not touch anything.
+    Viewer,
+    /// Can power the server on, off and reboot it.
+    Member,
+    /// Can do everything.
+    Admin,
+}
+
+impl Role {
+    pub fn may(&self, opt: ClientOpt) -> bool {
+        match opt {
+            ClientOpt::On
+            | ClientOpt::Off
+            | ClientOpt::Reboot
+            | ClientOpt::ComposeUp
+            | ClientOpt::ComposeDown
+            | ClientOpt::ComposeRestart => *self >= Role::Member,
+            ClientOpt::ComposePull => *self >= Role::Admin,
+            ClientOpt::RefreshNow => true,
+        }
+    }
+
+    /// Whether this role may add players to and remove them from `list`.
+    pub fn may_edit(&self, list: PlayerList) -> bool {
+        match list {
+            PlayerList::Whitelist => *self >= Role::Member,
+            PlayerList::Ops | PlayerList::Bans => *self >= Role::Admin,
+        }
+    }
+}
+
+/// Someone who guessed the password wrong too many times.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct Lockout {
+    /// An ip address, or `everyone` when too many wrong passwords came in overall.
+    pub who: String,
+    pub failures: u32,
+    /// How much longer they are locked out for, 0 if they merely have failures on record.
+    pub remaining_secs: u64,
+}
+
+/// A live websocket connection to sentinel.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct Session {
+    pub id: u64,
+    /// Usually the user agent.
+    pub who: String,
+    pub ip: String,
+    /// `None` until it authenticates.
+    pub role: Option<Role>,
+    /// How long it had been connected when this was sent.
+    pub connected_secs: u64,
+}
+
+/// The minecraft server went down or unhealthy without anyone asking it to.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct CrashReport {
+    /// When sentinel noticed, in seconds since the unix epoch.
+    pub at: u64,
+    /// What sentinel noticed, e.g. `the container exited`.
+    pub incident: String,
+    /// What sentinel did about it, e.g. `restarted the container (1 of 3 this hour)`.
+    pub action: String,
+    /// The newest file in `crash-reports` if it appeared since the last crash, cut short if long.
+    pub report: Option<String>,
+}
+
+/// Which file a setting lives in.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Encode, Decode)]
+pub enum ConfigSource {
+    /// `server.properties` in the server's data directory.
+    Properties,
+    /// The `environment` of the minecraft service in `docker-compose.yml`.
+    Environment,
+}
+
+/// What values a setting takes.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+pub enum ConfigKind {
+    /// Any single line up to `max_len` characters.
+    Text {
+        max_len: u32,
+    },
+    Integer {
+        min: i64,
+        max: i64,
+    },
+    Boolean,
+    Choice(Vec<String>),
+    /// A JVM memory size like `2G` or `1536M`.
+    Memory,
+}
+
+/// A setting that can be edited, and its current value.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+pub struct ConfigEntry {
+    pub source: ConfigSource,
+    pub key: String,
+    pub kind: ConfigKind,
+    pub description: String,
+    /// `None` if the file doesn't set it.
+    pub value: Option<String>,
+}
+
+/// A new value for a setting.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+pub struct ConfigEdit {
+    pub source: ConfigSource,
+    pub key: String,
+    pub value: String,
+}
+
+/// A setting an edit would change.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+pub struct ConfigChange {
+    pub source: ConfigSource,
+    pub key: String,
+    pub old: Option<String>,
+    pub new: String,
+}
+
+/// What a set of edits would change, or why they can't be applied.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+pub struct ConfigReview {
+    pub changes: Vec<ConfigChange>,
+    /// Nothing is applied unless this is empty.
+    pub problems: Vec<String>,
+    /// Whether the changes were written. They take effect once the container restarts.
+    pub applied: bool,
+}
+
+/// A list of players the server keeps in a json file in its data directory.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PlayerList {
+    /// `whitelist.json`
+    Whitelist,
+    /// `ops.json`
+    Ops,
+    /// `banned-players.json`
+    Bans,
+}
+
+/// A player on a [`PlayerList`].
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ListedPlayer {
+    /// With dashes, as the server writes it.
+    pub uuid: String,
+    pub name: String,
+    /// The op level for ops and the reason for bans, `None` on the whitelist.
+    pub detail: Option<String>,
+}
+
+/// Every [`PlayerList`] as the server has them.
+#[derive(Clone, PartialEq, Debug, Default, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Roster {
+    pub whitelist: Vec<ListedPlayer>,
+    pub ops: Vec<ListedPlayer>,
+    pub bans: Vec<ListedPlayer>,
+}
+
+/// Adds a player to or removes one from a [`PlayerList`].
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RosterEdit {
+    pub list: PlayerList,
+    /// The minecraft name, sentinel looks up the uuid.
+    pub name: String,
+    /// Whether to add rather than remove.
+    pub add: bool,
+    /// Only for new bans.
+    pub reason: Option<String>,
+}
+
+/// A file in a modpack.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModFile {
+    /// Relative to the pack, with `/` separators, e.g. `mods/jei-1.18.1-9.1.2.jar`.
+    pub path: String,
+    pub size: u64,
+    /// Lowercase hex.
+    pub sha256: String,
+}
+
+/// The manifest `deploy pack` puts in every modpack, and what sentinel reports the server runs.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Modpack {
+    /// Derived from the files, the same files always make the same version.
+    pub version: String,
+    /// e.g. `1.18.1`.
+    pub minecraft: String,
+    /// The forge version, `None` if the compose file leaves it to the image.
+    pub forge: Option<String>,
+    /// When it was built, in seconds since the unix epoch.
+    pub built: u64,
+    /// Where players download this very version of the pack.
+    pub url: String,
+    pub files: Vec<ModFile>,
+}
+
+/// What became of a client's request.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct Response {
+    /// The id of the envelope the request came in.
+    pub request: u64,
+    pub outcome: Outcome,
+}
+
+/// A request is first either acknowledged or rejected, and an acknowledged one later either
+/// completes or fails.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub enum Outcome {
+    Ack,
+    Rejected { reason: String },
+    Completed,
+    Failed { reason: String },
+}
+
+/// client talks in oldspeak
+///
+/// Only framed (version 1 and later) clients speak it, see [`protocol`].
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub enum Oldspeak {
+    /// Must be the first thing a framed client says.
+    Hello(Hello),
+    Password(String),
+    Opt(ClientOpt),
+    /// Only to a sentinel with the [`protocol::HEARTBEAT`] capability.
+    Heartbeat,
+    /// Asks for [`Newspeak::Config`], only from admins to a sentinel with the
+    /// [`protocol::CONFIG`] capability, like `ReviewConfig`.
+    ReadConfig,
+    /// Validates `edits` and diffs them against the current config, and writes them too if
+    /// `apply` and they are valid.
+    ReviewConfig {
+        edits: Vec<ConfigEdit>,
+        apply: bool,
+    },
+    /// Asks for [`Newspeak::Roster`], only to a sentinel with the [`protocol::ROSTER`]
+    /// capability, like `EditRoster`.
+    ReadRoster,
+    /// Sentinel answers with [`Newspeak::RosterEdited`] and then the new [`Newspeak::Roster`].
+    EditRoster(RosterEdit),
 }
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
@@ -0,0 +1,135 @@
+//! How sentinel and its clients talk over the websocket.
+//!
+//! The console is cached by browsers and CDNs, so sentinel has to keep talking to consoles built
+//! from older commits. These are the rules that make that possible:
+//!
+//! * **Version 0** is the protocol from before the handshake existed. The password is sent as a
+//!   text message, commands are bare [`ClientOpt`](crate::ClientOpt)s and the server answers with
+//!   bare [`Newspeak`](crate::Newspeak)s. Sentinel assumes version 0 for any client that doesn't
+//!   open with a [`Hello`], and waits for the first message to tell, however long it takes.
+//! * **Version 1** clients open with an [`Envelope`] carrying [`Oldspeak::Hello`](crate::Oldspeak).
+//!   Sentinel answers with [`Newspeak::Hello`](crate::Newspeak), and from then on every binary
+//!   message either way is an [`Envelope`].
+//! * Enums on the wire are **append only**. Never remove, reorder or change a variant or a field,
+//!   bincode identifies them by position.
+//! * A new variant is only ever sent to a peer that listed the matching capability in its
+//!   [`Hello`], so older peers never see a variant they can't decode.
+//! * Anything that can't follow these rules bumps [`PROTOCOL_VERSION`], and sentinel keeps
+//!   speaking the older versions to older clients.
+//!
+//! `tests/compat.rs` decodes messages recorded from previous versions. Add fixtures, never change
+//! them.
+
+use bincode::{Decode, Encode};
+
+/// The newest version this build speaks.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// The client wants a [`Response`](crate::Response) for each request instead of free text
+/// feedback.
+pub const RESPONSES: &str = "responses";
+
+/// The client understands [`Newspeak::Clearance`](crate::Newspeak) and, if it turns out to be an
+/// admin, wants the admin only messages.
+pub const ADMIN: &str = "admin";
+
+/// The client sends [`Oldspeak::Heartbeat`](crate::Oldspeak) now and then and sentinel answers
+/// each, so both notice a connection that silently died.
+pub const HEARTBEAT: &str = "heartbeat";
+
+/// Admins want [`Newspeak::Sessions`](crate::Newspeak).
+pub const SESSIONS: &str = "sessions";
+
+/// Sentinel takes [`ClientOpt::RefreshNow`](crate::ClientOpt).
+pub const REFRESH: &str = "refresh";
+
+/// The client wants [`Newspeak::Crash`](crate::Newspeak) once authenticated.
+pub const CRASHES: &str = "crashes";
+
+/// Sentinel takes the compose [`ClientOpt`](crate::ClientOpt)s and the client wants
+/// [`Newspeak::Container`](crate::Newspeak).
+pub const COMPOSE: &str = "compose";
+
+/// Sentinel takes [`Oldspeak::ReadConfig`](crate::Oldspeak) and
+/// [`Oldspeak::ReviewConfig`](crate::Oldspeak) from admins.
+pub const CONFIG: &str = "config";
+
+/// Sentinel takes [`Oldspeak::ReadRoster`](crate::Oldspeak) and
+/// [`Oldspeak::EditRoster`](crate::Oldspeak) from authenticated clients.
+pub const ROSTER: &str = "roster";
+
+/// The client wants [`Newspeak::Modpack`](crate::Newspeak).
+pub const MODPACK: &str = "modpack";
+
+/// Optional features this build understands, advertised in the handshake.
+pub const CAPABILITIES: &[&str] = &[
+    RESPONSES, ADMIN, HEARTBEAT, SESSIONS, REFRESH, CRASHES, COMPOSE, CONFIG, ROSTER, MODPACK,
+];
+
+/// The handshake, the first message of a framed connection in both directions.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct Hello {
+    pub version: u16,
+    /// Free form names rather than an enum, so unknown capabilities from newer peers still decode.
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    /// The hello of this build.
+    pub fn ours() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// What both sides can speak, given the peer's hello.
+    pub fn agree(&self, peer: &Hello) -> Hello {
+        Self {
+            version: self.version.min(peer.version),
+            capabilities: self
+                .capabilities
+                .iter()
+                .filter(|c| peer.has(c))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Frames every binary message of a framed connection.
+///
+/// Each side numbers the messages it sends, starting from 0 with the hello.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct Envelope<T> {
+    pub id: u64,
+    pub body: T,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agree_keeps_the_common_ground() {
+        let ours = Hello {
+            version: 2,
+            capabilities: vec!["a".to_string(), "b".to_string()],
+        };
+        let theirs = Hello {
+            version: 1,
+            capabilities: vec!["b".to_string(), "c".to_string()],
+        };
+        assert_eq!(
+            ours.agree(&theirs),
+            Hello {
+                version: 1,
+                capabilities: vec!["b".to_string()]
+            }
+        );
+    }
+}
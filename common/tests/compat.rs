@@ -0,0 +1,493 @@
+//! Messages recorded from previous protocol versions must keep decoding, see `common::protocol`.
+
+use bincode::Decode;
+use common::protocol::{Envelope, Hello};
+use common::{
+    AuthResult, ClientOpt, ConfigChange, ConfigEdit, ConfigEntry, ConfigKind, ConfigReview,
+    ConfigSource, Container, ContainerStatus, CrashReport, ListedPlayer, Lockout, ModFile, Modpack,
+    Newspeak, Oldspeak, OnlinePeople, Outcome, PlayerList, Response, Role, Roster, RosterEdit,
+    ServerStatus, Session,
+};
+
+fn decode<T: Decode>(bytes: &[u8]) -> T {
+    let (decoded, read) = bincode::decode_from_slice(bytes, bincode::config::standard()).unwrap();
+    assert_eq!(read, bytes.len(), "trailing bytes left undecoded");
+    decoded
+}
+
+fn running() -> ServerStatus {
+    ServerStatus {
+        host: "running".to_string(),
+        container: ContainerStatus::Up("Up 6 seconds (health: starting)".to_string()),
+        online: OnlinePeople::Known(
+            "There are 1 of a max of 20 players online: Madoshakalaka".to_string(),
+        ),
+    }
+}
+
+#[test]
+fn test_v0_client_opts() {
+    assert_eq!(
+        decode::<ClientOpt>(include_bytes!("fixtures/v0/client_opt_on.bin")),
+        ClientOpt::On
+    );
+    assert_eq!(
+        decode::<ClientOpt>(include_bytes!("fixtures/v0/client_opt_off.bin")),
+        ClientOpt::Off
+    );
+    assert_eq!(
+        decode::<ClientOpt>(include_bytes!("fixtures/v0/client_opt_reboot.bin")),
+        ClientOpt::Reboot
+    );
+}
+
+#[test]
+fn test_v0_newspeak() {
+    assert_eq!(
+        decode::<Newspeak>(include_bytes!("fixtures/v0/auth_goob.bin")),
+        Newspeak::AuthResult(AuthResult::Goob)
+    );
+    assert_eq!(
+        decode::<Newspeak>(include_bytes!("fixtures/v0/auth_sus.bin")),
+        Newspeak::AuthResult(AuthResult::Sus)
+    );
+    assert_eq!(
+        decode::<Newspeak>(include_bytes!("fixtures/v0/server_status.bin")),
+        Newspeak::ServerStatus(running())
+    );
+    assert_eq!(
+        decode::<Newspeak>(include_bytes!("fixtures/v0/server_status_stopped.bin")),
+        Newspeak::ServerStatus(ServerStatus {
+            host: "stopped".to_string(),
+            container: ContainerStatus::NotUp,
+            online: OnlinePeople::Unknown,
+        })
+    );
+    assert_eq!(
+        decode::<Newspeak>(include_bytes!("fixtures/v0/feedback.bin")),
+        Newspeak::Feedback(
+            "sentinel acknowledged the request, will boot the host shortly".to_string()
+        )
+    );
+}
+
+#[test]
+fn test_v1_oldspeak() {
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_hello.bin")),
+        Envelope {
+            id: 0,
+            body: Oldspeak::Hello(Hello {
+                version: 1,
+                capabilities: vec![]
+            })
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_password.bin")),
+        Envelope {
+            id: 1,
+            body: Oldspeak::Password("elfiscute".to_string())
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_opt_on.bin")),
+        Envelope {
+            id: 2,
+            body: Oldspeak::Opt(ClientOpt::On)
+        }
+    );
+}
+
+#[test]
+fn test_v1_newspeak() {
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/server_hello.bin")),
+        Envelope {
+            id: 0,
+            body: Newspeak::Hello(Hello {
+                version: 1,
+                capabilities: vec![]
+            })
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/server_status.bin")),
+        Envelope {
+            id: 3,
+            body: Newspeak::ServerStatus(running())
+        }
+    );
+}
+
+#[test]
+fn test_v1_responses() {
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_hello_responses.bin")),
+        Envelope {
+            id: 0,
+            body: Oldspeak::Hello(Hello {
+                version: 1,
+                capabilities: vec!["responses".to_string()]
+            })
+        }
+    );
+
+    let response = |outcome| {
+        Newspeak::Response(Response {
+            request: 2,
+            outcome,
+        })
+    };
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/response_ack.bin")),
+        Envelope {
+            id: 4,
+            body: response(Outcome::Ack)
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/response_rejected.bin")),
+        Envelope {
+            id: 5,
+            body: response(Outcome::Rejected {
+                reason: "a viewer is not allowed to do that".to_string()
+            })
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/response_completed.bin")),
+        Envelope {
+            id: 5,
+            body: response(Outcome::Completed)
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/response_failed.bin")),
+        Envelope {
+            id: 5,
+            body: response(Outcome::Failed {
+                reason: "sentinel failed to talk to aws".to_string()
+            })
+        }
+    );
+}
+
+#[test]
+fn test_v1_admin() {
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_hello_admin.bin")),
+        Envelope {
+            id: 0,
+            body: Oldspeak::Hello(Hello {
+                version: 1,
+                capabilities: vec!["responses".to_string(), "admin".to_string()]
+            })
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/clearance_admin.bin")),
+        Envelope {
+            id: 2,
+            body: Newspeak::Clearance(Role::Admin)
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/lockouts.bin")),
+        Envelope {
+            id: 3,
+            body: Newspeak::Lockouts(vec![
+                Lockout {
+                    who: "everyone".to_string(),
+                    failures: 5,
+                    remaining_secs: 0
+                },
+                Lockout {
+                    who: "10.0.0.1".to_string(),
+                    failures: 4,
+                    remaining_secs: 30
+                }
+            ])
+        }
+    );
+}
+
+#[test]
+fn test_v1_heartbeat_and_sessions() {
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_hello_heartbeat.bin")),
+        Envelope {
+            id: 0,
+            body: Oldspeak::Hello(Hello {
+                version: 1,
+                capabilities: vec!["heartbeat".to_string(), "sessions".to_string()]
+            })
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_heartbeat.bin")),
+        Envelope {
+            id: 7,
+            body: Oldspeak::Heartbeat
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/server_heartbeat.bin")),
+        Envelope {
+            id: 9,
+            body: Newspeak::Heartbeat
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/sessions.bin")),
+        Envelope {
+            id: 4,
+            body: Newspeak::Sessions(vec![
+                Session {
+                    id: 0,
+                    who: "Mozilla/5.0".to_string(),
+                    ip: "10.0.0.1".to_string(),
+                    role: Some(Role::Admin),
+                    connected_secs: 120
+                },
+                Session {
+                    id: 3,
+                    who: "unknown client".to_string(),
+                    ip: "10.0.0.2".to_string(),
+                    role: None,
+                    connected_secs: 5
+                }
+            ])
+        }
+    );
+}
+
+#[test]
+fn test_v1_refresh_now() {
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_opt_refresh_now.bin")),
+        Envelope {
+            id: 3,
+            body: Oldspeak::Opt(ClientOpt::RefreshNow)
+        }
+    );
+}
+
+#[test]
+fn test_v1_crash() {
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/crash.bin")),
+        Envelope {
+            id: 12,
+            body: Newspeak::Crash(CrashReport {
+                at: 1_660_000_000,
+                incident: "the container exited".to_string(),
+                action: "restarted the container (1 of 3 this hour)".to_string(),
+                report: Some("---- Minecraft Crash Report ----\n// Oops.\n".to_string()),
+            })
+        }
+    );
+}
+
+#[test]
+fn test_v1_compose() {
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_opt_compose_pull.bin")),
+        Envelope {
+            id: 5,
+            body: Oldspeak::Opt(ClientOpt::ComposePull)
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/container.bin")),
+        Envelope {
+            id: 20,
+            body: Newspeak::Container(Some(Container {
+                name: "root-mc-1".to_string(),
+                image: "itzg/minecraft-server".to_string(),
+                digest: Some("itzg/minecraft-server@sha256:4f1c2e".to_string()),
+                state: "running".to_string(),
+                health: Some("healthy".to_string()),
+                status: "Up 2 hours (healthy)".to_string(),
+            }))
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/no_container.bin")),
+        Envelope {
+            id: 21,
+            body: Newspeak::Container(None)
+        }
+    );
+}
+
+#[test]
+fn test_v1_config() {
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_read_config.bin")),
+        Envelope {
+            id: 6,
+            body: Oldspeak::ReadConfig
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_review_config.bin")),
+        Envelope {
+            id: 7,
+            body: Oldspeak::ReviewConfig {
+                edits: vec![ConfigEdit {
+                    source: ConfigSource::Environment,
+                    key: "VIEW_DISTANCE".to_string(),
+                    value: "16".to_string()
+                }],
+                apply: true,
+            }
+        }
+    );
+
+    let entry = |source, key: &str, kind, description: &str, value: Option<&str>| ConfigEntry {
+        source,
+        key: key.to_string(),
+        kind,
+        description: description.to_string(),
+        value: value.map(str::to_string),
+    };
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/config.bin")),
+        Envelope {
+            id: 30,
+            body: Newspeak::Config(vec![
+                entry(
+                    ConfigSource::Environment,
+                    "VIEW_DISTANCE",
+                    ConfigKind::Integer { min: 3, max: 32 },
+                    "chunks sent to clients",
+                    Some("20")
+                ),
+                entry(
+                    ConfigSource::Properties,
+                    "difficulty",
+                    ConfigKind::Choice(vec!["peaceful".to_string(), "hard".to_string()]),
+                    "",
+                    None
+                ),
+                entry(
+                    ConfigSource::Environment,
+                    "MOTD",
+                    ConfigKind::Text { max_len: 256 },
+                    "",
+                    None
+                ),
+                entry(
+                    ConfigSource::Environment,
+                    "ALLOW_FLIGHT",
+                    ConfigKind::Boolean,
+                    "",
+                    None
+                ),
+                entry(
+                    ConfigSource::Environment,
+                    "MEMORY",
+                    ConfigKind::Memory,
+                    "",
+                    Some("2G")
+                ),
+            ])
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/config_review.bin")),
+        Envelope {
+            id: 31,
+            body: Newspeak::ConfigReview(ConfigReview {
+                changes: vec![ConfigChange {
+                    source: ConfigSource::Environment,
+                    key: "VIEW_DISTANCE".to_string(),
+                    old: Some("20".to_string()),
+                    new: "16".to_string()
+                }],
+                problems: vec!["`SEED` is not a setting sentinel knows".to_string()],
+                applied: false,
+            })
+        }
+    );
+}
+
+#[test]
+fn test_v1_roster() {
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_read_roster.bin")),
+        Envelope {
+            id: 8,
+            body: Oldspeak::ReadRoster
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Oldspeak>>(include_bytes!("fixtures/v1/client_edit_roster.bin")),
+        Envelope {
+            id: 9,
+            body: Oldspeak::EditRoster(RosterEdit {
+                list: PlayerList::Bans,
+                name: "griefer_42".to_string(),
+                add: true,
+                reason: Some("lava in the town hall".to_string()),
+            })
+        }
+    );
+
+    let matt = |detail: Option<&str>| ListedPlayer {
+        uuid: "25130343-44e0-4f09-9e8e-8ac79b8b27f5".to_string(),
+        name: "Madoshakalaka".to_string(),
+        detail: detail.map(str::to_string),
+    };
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/roster.bin")),
+        Envelope {
+            id: 32,
+            body: Newspeak::Roster(Roster {
+                whitelist: vec![matt(None)],
+                ops: vec![matt(Some("4"))],
+                bans: vec![],
+            })
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/roster_edited.bin")),
+        Envelope {
+            id: 33,
+            body: Newspeak::RosterEdited(Outcome::Rejected {
+                reason: "only admins may edit the bans".to_string()
+            })
+        }
+    );
+}
+
+#[test]
+fn test_v1_modpack() {
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/modpack.bin")),
+        Envelope {
+            id: 34,
+            body: Newspeak::Modpack(Some(Modpack {
+                version: "3fa2c1d9e07b".to_string(),
+                minecraft: "1.18.1".to_string(),
+                forge: Some("39.0.79".to_string()),
+                built: 1659348000,
+                url: "https://siyuanyan.net/commupack/commupack-3fa2c1d9e07b.zip".to_string(),
+                files: vec![ModFile {
+                    path: "mods/jei-1.18.1-9.1.2.jar".to_string(),
+                    size: 1048576,
+                    sha256: "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+                        .to_string(),
+                }],
+            }))
+        }
+    );
+    assert_eq!(
+        decode::<Envelope<Newspeak>>(include_bytes!("fixtures/v1/no_modpack.bin")),
+        Envelope {
+            id: 35,
+            body: Newspeak::Modpack(None)
+        }
+    );
+}
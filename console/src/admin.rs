@@ -0,0 +1,75 @@
+//! What only admins get to see.
+
+use common::{Lockout, Session};
+use instant::Instant;
+use yew::prelude::*;
+use yew_vdom_gen::prelude::*;
+
+/// The latest lockouts from sentinel, and when they arrived so the countdown goes on between
+/// updates.
+pub struct Lockouts {
+    pub list: Vec<Lockout>,
+    pub received: Instant,
+}
+
+impl Lockouts {
+    pub fn new(list: Vec<Lockout>) -> Self {
+        Self {
+            list,
+            received: Instant::now(),
+        }
+    }
+
+    pub fn view(&self) -> Html {
+        if self.list.is_empty() {
+            return p("nobody got the password wrong lately").into();
+        }
+
+        let elapsed = self.received.elapsed().as_secs();
+        let list = self.list.iter().fold(ul(), |list, l| {
+            let remaining = l.remaining_secs.saturating_sub(elapsed);
+            let state = if remaining > 0 {
+                format!("locked out for another {remaining}s")
+            } else {
+                "not locked out".to_string()
+            };
+            list.child(li().child(format!(
+                "{}: {} wrong passwords, {state}",
+                l.who, l.failures
+            )))
+        });
+        list.into()
+    }
+}
+
+/// Everyone connected to sentinel right now.
+pub struct Sessions {
+    pub list: Vec<Session>,
+    pub received: Instant,
+}
+
+impl Sessions {
+    pub fn new(list: Vec<Session>) -> Self {
+        Self {
+            list,
+            received: Instant::now(),
+        }
+    }
+
+    pub fn view(&self) -> Html {
+        let elapsed = self.received.elapsed().as_secs();
+        let list = self.list.iter().fold(ul(), |list, s| {
+            let role = match s.role {
+                None => "not signed in".to_string(),
+                Some(role) => format!("{role:?}").to_lowercase(),
+            };
+            list.child(li().child(format!(
+                "{} from {}, {role}, connected for {}s",
+                s.who,
+                s.ip,
+                s.connected_secs + elapsed
+            )))
+        });
+        list.into()
+    }
+}
@@ -0,0 +1,156 @@
+//! The admin form for `server.properties` and the compose environment.
+
+use common::{ConfigEdit, ConfigEntry, ConfigKind, ConfigReview, ConfigSource};
+use web_sys::{HtmlInputElement, HtmlSelectElement};
+use yew::prelude::*;
+
+#[derive(Properties, PartialEq)]
+pub struct ConfigFormProps {
+    pub entries: Vec<ConfigEntry>,
+    /// Sentinel's answer to the last preview or apply.
+    pub review: Option<ConfigReview>,
+    /// Called with the edits and whether to apply them rather than only preview them.
+    pub on_review: Callback<(Vec<ConfigEdit>, bool)>,
+    /// Restarts the container, `None` if sentinel can't.
+    pub on_restart: Option<Callback<()>>,
+}
+
+fn file(source: ConfigSource) -> &'static str {
+    match source {
+        ConfigSource::Properties => "server.properties",
+        ConfigSource::Environment => "compose",
+    }
+}
+
+#[function_component(ConfigForm)]
+pub fn config_form(props: &ConfigFormProps) -> Html {
+    let edits = use_state(Vec::<ConfigEdit>::new);
+    {
+        // whatever was applied is in the new entries
+        let edits = edits.clone();
+        use_effect_with_deps(
+            move |_| {
+                edits.set(Vec::new());
+                || ()
+            },
+            props.entries.clone(),
+        );
+    }
+
+    let edit = {
+        let edits = edits.clone();
+        move |source: ConfigSource, key: String, value: String| {
+            let mut next = (*edits).clone();
+            next.retain(|e| !(e.source == source && e.key == key));
+            next.push(ConfigEdit { source, key, value });
+            edits.set(next);
+        }
+    };
+
+    let rows = props.entries.iter().map(|entry| {
+        let current = edits
+            .iter()
+            .find(|e| e.source == entry.source && e.key == entry.key)
+            .map(|e| e.value.clone())
+            .or_else(|| entry.value.clone())
+            .unwrap_or_default();
+        let (source, key) = (entry.source, entry.key.clone());
+        let edit = edit.clone();
+
+        let field = match &entry.kind {
+            ConfigKind::Boolean | ConfigKind::Choice(_) => {
+                let choices = match &entry.kind {
+                    ConfigKind::Choice(choices) => choices.clone(),
+                    _ => vec!["true".to_string(), "false".to_string()],
+                };
+                let onchange = Callback::from(move |e: Event| {
+                    let value = e.target_unchecked_into::<HtmlSelectElement>().value();
+                    edit(source, key.clone(), value)
+                });
+                html! {
+                    <select {onchange}>
+                        if entry.value.is_none() {
+                            <option selected={current.is_empty()} value="">{"unset"}</option>
+                        }
+                        { for choices.into_iter().map(|choice| html! {
+                            <option selected={choice.eq_ignore_ascii_case(&current)} value={choice.clone()}>{choice}</option>
+                        }) }
+                    </select>
+                }
+            }
+            ConfigKind::Text { .. } | ConfigKind::Integer { .. } | ConfigKind::Memory => {
+                let oninput = Callback::from(move |e: InputEvent| {
+                    let value = e.target_unchecked_into::<HtmlInputElement>().value();
+                    edit(source, key.clone(), value)
+                });
+                html! { <input type="text" value={current} {oninput}/> }
+            }
+        };
+
+        html! {
+            <tr>
+                <td>{format!("{} ({})", entry.key, file(entry.source))}</td>
+                <td>{field}</td>
+                <td>{entry.description.clone()}</td>
+            </tr>
+        }
+    });
+
+    let review = props.review.as_ref().map(|review| {
+        let changes = review.changes.iter().map(|c| {
+            html! {
+                <li>{format!(
+                    "{} ({}): {} → {}",
+                    c.key,
+                    file(c.source),
+                    c.old.as_deref().unwrap_or("unset"),
+                    c.new
+                )}</li>
+            }
+        });
+        let problems = review
+            .problems
+            .iter()
+            .map(|p| html! { <li>{p.clone()}</li> });
+        let restart = match (&props.on_restart, review.applied) {
+            (Some(on_restart), true) => {
+                let on_restart = on_restart.clone();
+                let onclick = Callback::from(move |_| on_restart.emit(()));
+                html! { <button {onclick}>{"restart the container"}</button> }
+            }
+            _ => html! {},
+        };
+        html! {
+            <>
+                <ul>{ for changes }</ul>
+                <ul>{ for problems }</ul>
+                if review.applied {
+                    <p>{"applied, the server uses the new settings once the container restarts"}</p>
+                    {restart}
+                } else if review.changes.is_empty() && review.problems.is_empty() {
+                    <p>{"nothing would change"}</p>
+                }
+            </>
+        }
+    });
+
+    let button = |label: &'static str, apply: bool, enabled: bool| {
+        let on_review = props.on_review.clone();
+        let edits = (*edits).clone();
+        let onclick = Callback::from(move |_| on_review.emit((edits.clone(), apply)));
+        html! { <button {onclick} disabled={!enabled}>{label}</button> }
+    };
+    let reviewed = matches!(
+        &props.review,
+        Some(r) if !r.applied && r.problems.is_empty() && !r.changes.is_empty()
+    );
+
+    html! {
+        <>
+            <table>{ for rows }</table>
+            {button("preview", false, !edits.is_empty())}
+            {button("apply", true, !edits.is_empty() && reviewed)}
+            { for review }
+        </>
+    }
+}
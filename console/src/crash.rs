@@ -0,0 +1,38 @@
+//! The last time the minecraft server went down on its own.
+
+use common::CrashReport;
+use yew::prelude::*;
+use yew_vdom_gen::prelude::*;
+
+/// Seconds since `report` was made, by the browser's clock.
+pub fn age(report: &CrashReport) -> u64 {
+    ((js_sys::Date::now() / 1000.0) as u64).saturating_sub(report.at)
+}
+
+fn ago(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s ago"),
+        60..=3599 => format!("{}min ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+pub fn view(report: &CrashReport) -> Html {
+    let summary = p(format!(
+        "{}, {}: {}",
+        ago(age(report)),
+        report.incident,
+        report.action
+    ));
+    let details = match &report.report {
+        Some(content) => html! {
+            <details>
+                <summary>{"crash report"}</summary>
+                <pre>{content.clone()}</pre>
+            </details>
+        },
+        None => p("no crash report was written").into(),
+    };
+    fragment().child(summary).child(details).into()
+}
@@ -1 +1,9 @@
+pub mod admin;
+pub mod config;
+pub mod crash;
 pub mod interop;
+pub mod modpack;
+pub mod modsync;
+pub mod requests;
+pub mod roster;
+pub mod wire;
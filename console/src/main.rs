@@ -1,15 +1,27 @@
-use common::{AuthResult, ClientOpt, ContainerStatus, Newspeak, OnlinePeople, ServerStatus};
+use common::protocol;
+use common::{
+    AuthResult, ClientOpt, ConfigEdit, ConfigEntry, ConfigReview, Container, ContainerStatus,
+    CrashReport, Modpack, Newspeak, Oldspeak, OnlinePeople, Outcome, Role, Roster, RosterEdit,
+    ServerStatus,
+};
+use console::admin::{Lockouts, Sessions};
+use console::config::ConfigForm;
 use console::interop::show_congrats_toast;
 use console::interop::ResourceProvider;
+use console::modsync::ModSync;
+use console::requests::{describe, Requests};
+use console::roster::RosterPage;
+use console::wire;
+use futures::future::Either;
 use futures::stream::{SplitSink, SplitStream};
 use futures::{SinkExt, StreamExt};
-use gloo_timers::callback::Timeout;
 use gloo_timers::future::TimeoutFuture;
 use instant::Instant;
 use reqwasm::websocket::futures::WebSocket;
 use reqwasm::websocket::Message;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::ops::Deref;
 
 use std::rc::Rc;
@@ -18,7 +30,6 @@ use std::sync::RwLock;
 
 use stylist::yew::styled_component;
 
-use web_sys::HtmlButtonElement;
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
 
@@ -53,6 +64,22 @@ pub fn app() -> Html {
 
     let authenticated = use_state(|| false);
     let authenticating = use_state(|| false);
+    let role: UseStateHandle<Option<Role>> = use_state(|| None);
+    let lockouts: UseStateHandle<Option<Lockouts>> = use_state(|| None);
+    let sessions: UseStateHandle<Option<Sessions>> = use_state(|| None);
+    let crash: UseStateHandle<Option<CrashReport>> = use_state(|| None);
+    // whether sentinel takes ClientOpt::RefreshNow
+    let refreshable = use_state(|| false);
+    // whether sentinel takes the compose ClientOpts and sends Newspeak::Container
+    let composable = use_state(|| false);
+    let container: UseStateHandle<Option<Container>> = use_state(|| None);
+    // admins only, see protocol::CONFIG
+    let config: UseStateHandle<Option<Vec<ConfigEntry>>> = use_state(|| None);
+    let config_review: UseStateHandle<Option<ConfigReview>> = use_state(|| None);
+    // see protocol::ROSTER
+    let roster: UseStateHandle<Option<Roster>> = use_state(|| None);
+    // see protocol::MODPACK
+    let modpack: UseStateHandle<Option<Modpack>> = use_state(|| None);
 
     let socket = if cfg!(debug_assertions) {
         "wss://localhost:3000/ws"
@@ -70,6 +97,8 @@ pub fn app() -> Html {
 
     let re_render = use_state(|| ());
 
+    let requests = use_mut_ref(Requests::default);
+
     let button_waiting = use_state(|| false);
 
     let input_ref = use_node_ref();
@@ -80,34 +109,82 @@ pub fn app() -> Html {
         // let con_watcher = con_watcher.clone();
 
         let reporting_server_status = server_status.clone();
+        let reporting_requests = requests.clone();
         let open_soc_report = open_soc.clone();
         let authenticated = authenticated.clone();
         let authenticating = authenticating.clone();
+        let role = role.clone();
+        let lockouts = lockouts.clone();
+        let sessions = sessions.clone();
+        let crash = crash.clone();
+        let refreshable = refreshable.clone();
+        let composable = composable.clone();
+        let container = container.clone();
+        let config = config.clone();
+        let config_review = config_review.clone();
+        let roster = roster.clone();
+        let modpack = modpack.clone();
 
         let input_ref = input_ref.clone();
 
         // let button_waiting = button_waiting.clone();
         let reporter = async move {
-            let config = bincode::config::standard();
-
             loop {
                 {
                     let g = open_soc_report.deref().read().unwrap();
+                    if let Some((w, _)) = g.as_ref() {
+                        w.lock().unwrap().send(wire::hello()).await.ok();
+                    }
                     {
                         let mut t = g.as_ref().map(|(_, g)| g.lock().unwrap());
                         match t.as_deref_mut() {
                             Some(ws) => {
-                                while let Some(Ok(Message::Bytes(b))) = ws.next().await {
-                                    let n: Option<(Newspeak, _)> =
-                                        bincode::decode_from_slice(b.as_slice(), config)
-                                            .map_err(|e| {
-                                                gloo_console::warn!(e.to_string());
-                                            })
-                                            .ok();
+                                // whether sentinel answers heartbeats, see protocol::HEARTBEAT
+                                let mut heartbeat = false;
+                                // whether admins may read and edit the config, see protocol::CONFIG
+                                let mut configurable = false;
+                                // whether sentinel keeps the whitelist, ops and bans
+                                let mut rosterable = false;
+                                let mut last_heard = Instant::now();
+                                loop {
+                                    let next = futures::future::select(
+                                        ws.next(),
+                                        TimeoutFuture::new(wire::HEARTBEAT_INTERVAL_MS),
+                                    )
+                                    .await;
+                                    let b = match next {
+                                        Either::Left((Some(Ok(Message::Bytes(b))), _)) => b,
+                                        Either::Left(_) => break,
+                                        Either::Right(_) => {
+                                            if heartbeat {
+                                                if last_heard.elapsed() > wire::SILENCE_LIMIT {
+                                                    gloo_console::warn!(
+                                                        "sentinel went silent, reconnecting"
+                                                    );
+                                                    break;
+                                                }
+                                                if let Some((w, _)) = g.as_ref() {
+                                                    w.lock()
+                                                        .unwrap()
+                                                        .send(wire::frame(Oldspeak::Heartbeat))
+                                                        .await
+                                                        .ok();
+                                                }
+                                            }
+                                            continue;
+                                        }
+                                    };
+                                    last_heard = Instant::now();
+
+                                    let n: Option<Newspeak> = wire::unframe(b.as_slice())
+                                        .map_err(|e| {
+                                            gloo_console::warn!(e.to_string());
+                                        })
+                                        .ok();
 
                                     match n {
                                         None => {}
-                                        Some((n, _)) => {
+                                        Some(n) => {
                                             match n {
                                                 Newspeak::AuthResult(r) => match r {
                                                     AuthResult::Goob => {
@@ -125,6 +202,17 @@ pub fn app() -> Html {
 
                                                         authenticating.set(false);
                                                         authenticated.set(true);
+                                                        if rosterable {
+                                                            if let Some((w, _)) = g.as_ref() {
+                                                                w.lock()
+                                                                    .unwrap()
+                                                                    .send(wire::frame(
+                                                                        Oldspeak::ReadRoster,
+                                                                    ))
+                                                                    .await
+                                                                    .ok();
+                                                            }
+                                                        }
                                                         console::interop::show_congrats_toast("welcome, fellow equal member of communism");
                                                     }
                                                     AuthResult::Sus => {
@@ -144,6 +232,91 @@ pub fn app() -> Html {
                                                 Newspeak::Feedback(f) => {
                                                     show_congrats_toast(&f);
                                                 }
+                                                Newspeak::Hello(agreed) => {
+                                                    heartbeat = agreed.has(protocol::HEARTBEAT);
+                                                    refreshable.set(agreed.has(protocol::REFRESH));
+                                                    composable.set(agreed.has(protocol::COMPOSE));
+                                                    configurable = agreed.has(protocol::CONFIG);
+                                                    rosterable = agreed.has(protocol::ROSTER);
+                                                }
+                                                Newspeak::Heartbeat => {}
+                                                Newspeak::Sessions(s) => {
+                                                    sessions.set(Some(Sessions::new(s)))
+                                                }
+                                                Newspeak::Clearance(r) => {
+                                                    if r == Role::Admin && configurable {
+                                                        if let Some((w, _)) = g.as_ref() {
+                                                            w.lock()
+                                                                .unwrap()
+                                                                .send(wire::frame(
+                                                                    Oldspeak::ReadConfig,
+                                                                ))
+                                                                .await
+                                                                .ok();
+                                                        }
+                                                    }
+                                                    role.set(Some(r))
+                                                }
+                                                Newspeak::Lockouts(l) => {
+                                                    lockouts.set(Some(Lockouts::new(l)))
+                                                }
+                                                Newspeak::Container(c) => container.set(c),
+                                                Newspeak::Config(c) => config.set(Some(c)),
+                                                Newspeak::ConfigReview(r) => {
+                                                    if r.applied {
+                                                        show_congrats_toast("config applied");
+                                                    } else if !r.problems.is_empty() {
+                                                        console::interop::show_execution_toast(
+                                                            &r.problems.join(", "),
+                                                        );
+                                                    }
+                                                    config_review.set(Some(r));
+                                                }
+                                                Newspeak::Roster(r) => roster.set(Some(r)),
+                                                Newspeak::Modpack(m) => modpack.set(m),
+                                                Newspeak::RosterEdited(outcome) => match outcome {
+                                                    Outcome::Rejected { reason }
+                                                    | Outcome::Failed { reason } => {
+                                                        console::interop::show_execution_toast(
+                                                            &reason,
+                                                        )
+                                                    }
+                                                    _ => show_congrats_toast("roster updated"),
+                                                },
+                                                Newspeak::Crash(c) => {
+                                                    // the last crash also arrives on sign in,
+                                                    // only news is worth a toast
+                                                    if console::crash::age(&c) < 60 {
+                                                        console::interop::show_execution_toast(
+                                                            &format!(
+                                                                "the server crashed: {}",
+                                                                c.action
+                                                            ),
+                                                        );
+                                                    }
+                                                    crash.set(Some(c));
+                                                }
+                                                Newspeak::Response(r) => {
+                                                    let mut requests =
+                                                        reporting_requests.borrow_mut();
+                                                    if let Some(r) = requests.respond(r) {
+                                                        match &r.outcome {
+                                                            Some(Outcome::Completed) => {
+                                                                show_congrats_toast(&format!(
+                                                                    "{} done",
+                                                                    describe(r.opt)
+                                                                ))
+                                                            }
+                                                            Some(
+                                                                Outcome::Rejected { reason }
+                                                                | Outcome::Failed { reason },
+                                                            ) => console::interop::show_execution_toast(
+                                                                reason,
+                                                            ),
+                                                            _ => {}
+                                                        }
+                                                    }
+                                                }
                                             }
                                         }
                                     };
@@ -155,7 +328,19 @@ pub fn app() -> Html {
                 }
 
                 authenticated.set(false);
+                role.set(None);
+                lockouts.set(None);
+                sessions.set(None);
+                crash.set(None);
+                refreshable.set(false);
+                composable.set(false);
+                container.set(None);
+                config.set(None);
+                config_review.set(None);
+                roster.set(None);
+                modpack.set(None);
                 button_waiting.set(false);
+                reporting_requests.borrow_mut().lost();
 
                 {
                     let mut g = open_soc_report.deref().write().unwrap();
@@ -262,7 +447,7 @@ pub fn app() -> Html {
                         authenticating.set(true);
                         s.lock()
                             .unwrap()
-                            .send(Message::Text(stored_password))
+                            .send(wire::frame(Oldspeak::Password(stored_password)))
                             .await
                             .ok();
                     }
@@ -318,6 +503,60 @@ pub fn app() -> Html {
             fragment().child(p("receiving from sentinel..."))
         });
 
+    let container_display = match container.as_ref() {
+        Some(Container {
+            image,
+            digest,
+            health,
+            ..
+        }) => fragment()
+            .child(h1("Image"))
+            .child(p(match digest {
+                Some(digest) => Cow::from(digest.clone()),
+                None => Cow::from(format!("{image}, digest unknown")),
+            }))
+            .child(h1("Container Health"))
+            .child(p(match health {
+                Some(health) => Cow::from(health.clone()),
+                None => Cow::from("no health check"),
+            })),
+        None => fragment(),
+    };
+
+    let admin_display = if *role == Some(Role::Admin) {
+        let frag = match lockouts.as_ref() {
+            Some(lockouts) => fragment().child(h1("Lockouts")).child(lockouts.view()),
+            None => fragment(),
+        };
+        match sessions.as_ref() {
+            Some(sessions) => frag.child(h1("Sessions")).child(sessions.view()),
+            None => frag,
+        }
+    } else {
+        fragment()
+    };
+
+    let modpack_display = match modpack.as_ref() {
+        Some(m) => fragment()
+            .child(h1("Modpack"))
+            .child(console::modpack::view(m)),
+        None => fragment(),
+    };
+
+    let mod_check_display = html! {
+        <>
+            <h1>{"Mod Check"}</h1>
+            <ModSync pack={(*modpack).clone()}/>
+        </>
+    };
+
+    let crash_display = match crash.as_ref() {
+        Some(c) => fragment()
+            .child(h1("Last Crash"))
+            .child(console::crash::view(c)),
+        None => fragment(),
+    };
+
     let frag = fragment().child(h1("'Chung' Minecraft Server Dashboard"));
 
     let frag = if *authenticated {
@@ -358,9 +597,9 @@ pub fn app() -> Html {
                                 authenticating.set(true);
                                 s.lock()
                                     .unwrap()
-                                    .send(Message::Text(
+                                    .send(wire::frame(Oldspeak::Password(
                                         input_ref.cast::<HtmlInputElement>().unwrap().value(),
-                                    ))
+                                    )))
                                     .await
                                     .ok();
                             }
@@ -386,27 +625,23 @@ pub fn app() -> Html {
             )>,
         >,
     >,
-                    authenticating: UseStateHandle<bool>| {
+                    requests: Rc<RefCell<Requests>>| {
         wasm_bindgen_futures::spawn_local(async move {
-            let config = bincode::config::standard();
             let soc = open_soc.deref().read().unwrap();
 
             match soc.as_ref() {
                 None => {}
                 Some((s, _)) => {
-                    authenticating.set(true);
-                    s.lock()
-                        .unwrap()
-                        .send(Message::Bytes(bincode::encode_to_vec(opt, config).unwrap()))
-                        .await
-                        .ok();
+                    let (id, message) = wire::request(opt);
+                    requests.borrow_mut().sent(id, opt);
+                    s.lock().unwrap().send(message).await.ok();
                 }
             }
         });
     };
 
-    let debounce = |opt: ClientOpt,
-                    soc: Rc<
+    let on_opt_click = |opt: ClientOpt,
+                        soc: Rc<
         RwLock<
             Option<(
                 Mutex<SplitSink<WebSocket, Message>>,
@@ -414,22 +649,15 @@ pub fn app() -> Html {
             )>,
         >,
     >,
-                    authenticating: UseStateHandle<bool>| {
-        on_click(move |e| {
-            let soc = soc.clone();
-            let authenticating = authenticating.clone();
-            let b: HtmlButtonElement = e.target_unchecked_into();
-            b.set_disabled(true);
-
-            let t = Timeout::new(2_000, move || b.set_disabled(false));
-            t.forget();
-            send_opt(opt, soc, authenticating);
-        })
+                        requests: Rc<RefCell<Requests>>| {
+        on_click(move |_| send_opt(opt, soc.clone(), requests.clone()))
     };
 
+    let in_flight = |opt: ClientOpt| requests.borrow().in_flight(opt);
+
     let reboot_button = button("reboot");
     let reboot_button = if let Some((true, _)) = is_running_or_closed {
-        if !*authenticated || !toast_ready {
+        if !*authenticated || !toast_ready || in_flight(ClientOpt::Reboot) {
             reboot_button.disabled("true".into())
         } else {
             reboot_button
@@ -437,15 +665,15 @@ pub fn app() -> Html {
     } else {
         reboot_button.disabled("true".into())
     }
-    .listener(debounce(
+    .listener(on_opt_click(
         ClientOpt::Reboot,
         open_soc.clone(),
-        authenticating.clone(),
+        requests.clone(),
     ));
 
     let shutdown_button = button("shutdown");
     let shutdown_button = if let Some((true, _)) = is_running_or_closed {
-        if !*authenticated || !toast_ready {
+        if !*authenticated || !toast_ready || in_flight(ClientOpt::Off) {
             shutdown_button.disabled("true".into())
         } else {
             shutdown_button
@@ -453,15 +681,15 @@ pub fn app() -> Html {
     } else {
         shutdown_button.disabled("true".into())
     }
-    .listener(debounce(
+    .listener(on_opt_click(
         ClientOpt::Off,
         open_soc.clone(),
-        authenticating.clone(),
+        requests.clone(),
     ));
 
     let power_on_button = button("power on");
     let power_on_button = if let Some((_, true)) = is_running_or_closed {
-        if !*authenticated || !toast_ready {
+        if !*authenticated || !toast_ready || in_flight(ClientOpt::On) {
             power_on_button.disabled("true".into())
         } else {
             power_on_button
@@ -469,12 +697,152 @@ pub fn app() -> Html {
     } else {
         power_on_button.disabled("true".into())
     }
-    .listener(debounce(ClientOpt::On, open_soc, authenticating));
+    .listener(on_opt_click(
+        ClientOpt::On,
+        open_soc.clone(),
+        requests.clone(),
+    ));
+
+    let compose_buttons: Html = if *composable {
+        let host_running = matches!(is_running_or_closed, Some((true, _)));
+        [
+            ClientOpt::ComposeUp,
+            ClientOpt::ComposeRestart,
+            ClientOpt::ComposeDown,
+            ClientOpt::ComposePull,
+        ]
+        .into_iter()
+        // pulling is for admins only
+        .filter(|opt| *opt != ClientOpt::ComposePull || *role == Some(Role::Admin))
+        .fold(fragment(), |frag, opt| {
+            let compose_button = button(describe(opt));
+            let compose_button =
+                if !host_running || !*authenticated || !toast_ready || in_flight(opt) {
+                    compose_button.disabled("true".into())
+                } else {
+                    compose_button
+                };
+            frag.child(compose_button.listener(on_opt_click(
+                opt,
+                open_soc.clone(),
+                requests.clone(),
+            )))
+        })
+        .into()
+    } else {
+        fragment().into()
+    };
+
+    let config_display: Html = match config.as_ref() {
+        Some(entries) if *role == Some(Role::Admin) => {
+            let on_review = {
+                let open_soc = open_soc.clone();
+                Callback::from(move |(edits, apply): (Vec<ConfigEdit>, bool)| {
+                    let open_soc = open_soc.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        let soc = open_soc.deref().read().unwrap();
+                        if let Some((s, _)) = soc.as_ref() {
+                            s.lock()
+                                .unwrap()
+                                .send(wire::frame(Oldspeak::ReviewConfig { edits, apply }))
+                                .await
+                                .ok();
+                        }
+                    });
+                })
+            };
+            // the new settings only take effect once the container restarts
+            let on_restart = if *composable {
+                let open_soc = open_soc.clone();
+                let requests = requests.clone();
+                Some(Callback::from(move |()| {
+                    send_opt(
+                        ClientOpt::ComposeRestart,
+                        open_soc.clone(),
+                        requests.clone(),
+                    )
+                }))
+            } else {
+                None
+            };
+            html! {
+                <>
+                    <h1>{"Config"}</h1>
+                    <ConfigForm
+                        entries={entries.clone()}
+                        review={(*config_review).clone()}
+                        {on_review}
+                        {on_restart}
+                    />
+                </>
+            }
+        }
+        _ => fragment().into(),
+    };
+
+    let roster_display: Html = match roster.as_ref() {
+        Some(r) => {
+            let on_edit = {
+                let open_soc = open_soc.clone();
+                Callback::from(move |edit: RosterEdit| {
+                    let open_soc = open_soc.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        let soc = open_soc.deref().read().unwrap();
+                        if let Some((s, _)) = soc.as_ref() {
+                            s.lock()
+                                .unwrap()
+                                .send(wire::frame(Oldspeak::EditRoster(edit)))
+                                .await
+                                .ok();
+                        }
+                    });
+                })
+            };
+            html! {
+                <>
+                    <h1>{"Players"}</h1>
+                    <RosterPage
+                        roster={r.clone()}
+                        role={role.unwrap_or(Role::Viewer)}
+                        {on_edit}
+                    />
+                </>
+            }
+        }
+        None => fragment().into(),
+    };
+
+    let refresh_button: Html = if *refreshable {
+        let refresh_button = button("refresh now");
+        if !*authenticated || !toast_ready || in_flight(ClientOpt::RefreshNow) {
+            refresh_button.disabled("true".into())
+        } else {
+            refresh_button
+        }
+        .listener(on_opt_click(
+            ClientOpt::RefreshNow,
+            open_soc,
+            requests.clone(),
+        ))
+        .into()
+    } else {
+        fragment().into()
+    };
 
     frag.child(reboot_button)
         .child(power_on_button)
         .child(shutdown_button)
+        .child(refresh_button)
+        .child(compose_buttons)
+        .child(requests.borrow().view())
+        .child(admin_display)
+        .child(config_display)
+        .child(roster_display)
+        .child(crash_display)
         .child(status_display)
+        .child(container_display)
+        .child(modpack_display)
+        .child(mod_check_display)
         .into()
 }
 
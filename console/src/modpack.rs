@@ -0,0 +1,26 @@
+//! Which client pack players need, the one the server runs.
+
+use common::Modpack;
+use yew::prelude::*;
+use yew_vdom_gen::prelude::*;
+
+pub fn view(pack: &Modpack) -> Html {
+    let game = match &pack.forge {
+        Some(forge) => format!("minecraft {} with forge {forge}", pack.minecraft),
+        None => format!("minecraft {} with forge", pack.minecraft),
+    };
+    let mods = pack
+        .files
+        .iter()
+        .filter(|f| f.path.starts_with("mods/"))
+        .count();
+    fragment()
+        .child(p(format!(
+            "version {} for {game}, {mods} mods",
+            pack.version
+        )))
+        .child(html! {
+            <a href={pack.url.clone()}>{"download the client pack"}</a>
+        })
+        .into()
+}
@@ -0,0 +1,279 @@
+//! Checking a player's `mods` folder against the pack the server runs.
+
+use common::{ModFile, Modpack};
+use js_sys::{Array, Object, Promise, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    DataTransfer, File, FileList, FileSystemDirectoryEntry, FileSystemEntry, FileSystemFileEntry,
+    HtmlInputElement,
+};
+use yew::prelude::*;
+
+/// The current pack's manifest as `deploy pack` publishes it, for when sentinel can't tell.
+const PUBLISHED: &str = "https://siyuanyan.net/commupack.json";
+
+/// How the player's mods differ from the pack's, by file name.
+#[derive(Default, PartialEq)]
+struct SyncReport {
+    /// In the pack but not in the folder.
+    missing: Vec<String>,
+    /// In the folder but not in the pack.
+    extra: Vec<String>,
+    /// In both, but not the same file.
+    different: Vec<String>,
+    matching: usize,
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn mods(pack: &Modpack) -> impl Iterator<Item = &ModFile> {
+    pack.files.iter().filter(|f| f.path.starts_with("mods/"))
+}
+
+/// Lowercase hex, like the manifest's.
+async fn sha256(file: &File) -> Result<String, JsValue> {
+    let buffer = JsFuture::from(file.array_buffer()).await?;
+    let subtle = web_sys::window()
+        .ok_or_else(|| JsValue::from_str("no window"))?
+        .crypto()?
+        .subtle();
+    let digest = JsFuture::from(
+        subtle.digest_with_str_and_buffer_source("SHA-256", buffer.unchecked_ref::<Object>())?,
+    )
+    .await?;
+    Ok(Uint8Array::new(&digest)
+        .to_vec()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Only files with the pack's size are hashed, the rest can't be the same anyway.
+async fn compare(pack: &Modpack, files: Vec<File>) -> Result<SyncReport, JsValue> {
+    let jars: Vec<File> = files
+        .into_iter()
+        .filter(|f| f.name().ends_with(".jar"))
+        .collect();
+    let mut report = SyncReport::default();
+    for wanted in mods(pack) {
+        let name = file_name(&wanted.path);
+        let same = match jars.iter().find(|f| f.name() == name) {
+            None => {
+                report.missing.push(name.to_string());
+                continue;
+            }
+            Some(jar) => jar.size() as u64 == wanted.size && sha256(jar).await? == wanted.sha256,
+        };
+        if same {
+            report.matching += 1;
+        } else {
+            report.different.push(name.to_string());
+        }
+    }
+    report.extra = jars
+        .iter()
+        .map(File::name)
+        .filter(|name| !mods(pack).any(|m| file_name(&m.path) == name))
+        .collect();
+    Ok(report)
+}
+
+async fn fetch_published() -> Result<Modpack, reqwasm::Error> {
+    reqwasm::http::Request::get(PUBLISHED)
+        .send()
+        .await?
+        .json()
+        .await
+}
+
+fn files(list: Option<FileList>) -> Vec<File> {
+    match list {
+        Some(list) => (0..list.length()).filter_map(|i| list.get(i)).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// What the player picked. A dropped folder only shows up as an entry, its files have to be read
+/// from it.
+enum Picked {
+    Files(Vec<File>),
+    Entries(Vec<FileSystemEntry>),
+}
+
+/// The dropped files and folders. Has to be called before the drop handler returns, the browser
+/// empties the [`DataTransfer`] after.
+fn dropped(transfer: &DataTransfer) -> Picked {
+    let items = transfer.items();
+    let entries: Vec<FileSystemEntry> = (0..items.length())
+        .filter_map(|i| items.get(i))
+        .filter_map(|item| item.webkit_get_as_entry().ok().flatten())
+        .collect();
+    if entries.is_empty() {
+        Picked::Files(files(transfer.files()))
+    } else {
+        Picked::Entries(entries)
+    }
+}
+
+/// Every file in `entries` and the folders among them, however deep.
+async fn walk(mut entries: Vec<FileSystemEntry>) -> Result<Vec<File>, JsValue> {
+    let mut files = Vec::new();
+    while let Some(entry) = entries.pop() {
+        if entry.is_file() {
+            let entry: FileSystemFileEntry = entry.unchecked_into();
+            let file = JsFuture::from(Promise::new(&mut |resolve, reject| {
+                entry.file_with_callback_and_callback(&resolve, &reject)
+            }))
+            .await?;
+            files.push(file.unchecked_into());
+        } else if entry.is_directory() {
+            let reader = entry
+                .unchecked_into::<FileSystemDirectoryEntry>()
+                .create_reader();
+            // a folder is read in batches, until one comes back empty
+            loop {
+                let batch = JsFuture::from(Promise::new(&mut |resolve, reject| {
+                    if let Err(e) =
+                        reader.read_entries_with_callback_and_callback(&resolve, &reject)
+                    {
+                        reject.call1(&JsValue::NULL, &e).ok();
+                    }
+                }))
+                .await?
+                .unchecked_into::<Array>();
+                if batch.length() == 0 {
+                    break;
+                }
+                entries.extend(batch.iter().map(JsCast::unchecked_into));
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn names(title: &str, names: &[String]) -> Html {
+    if names.is_empty() {
+        return html! {};
+    }
+    html! {
+        <>
+            <p>{title}</p>
+            <ul>{ for names.iter().map(|n| html! { <li>{n.clone()}</li> }) }</ul>
+        </>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct ModSyncProps {
+    /// The pack sentinel says the server runs.
+    pub pack: Option<Modpack>,
+}
+
+#[function_component(ModSync)]
+pub fn mod_sync(props: &ModSyncProps) -> Html {
+    let published: UseStateHandle<Option<Modpack>> = use_state(|| None);
+    let report: UseStateHandle<Option<SyncReport>> = use_state(|| None);
+    let checking = use_state(|| false);
+    {
+        let published = published.clone();
+        use_effect_with_deps(
+            move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    match fetch_published().await {
+                        Ok(pack) => published.set(Some(pack)),
+                        Err(e) => gloo_console::warn!(format!("no published modpack: {e}")),
+                    }
+                });
+                || ()
+            },
+            (),
+        );
+    }
+
+    let (pack, note) = match (&props.pack, &*published) {
+        (Some(pack), _) => (pack.clone(), "the server runs"),
+        (None, Some(pack)) => (pack.clone(), "the latest, the server isn't telling"),
+        (None, None) => return html! { <p>{"looking for the modpack..."}</p> },
+    };
+
+    let check = {
+        let pack = pack.clone();
+        let report = report.clone();
+        let checking = checking.clone();
+        move |picked: Picked| {
+            if matches!(&picked, Picked::Files(files) if files.is_empty()) {
+                return;
+            }
+            let (pack, report, checking) = (pack.clone(), report.clone(), checking.clone());
+            checking.set(true);
+            wasm_bindgen_futures::spawn_local(async move {
+                let files = match picked {
+                    Picked::Files(files) => Ok(files),
+                    Picked::Entries(entries) => walk(entries).await,
+                };
+                let compared = match files {
+                    Ok(files) if files.is_empty() => Ok(None),
+                    Ok(files) => compare(&pack, files).await.map(Some),
+                    Err(e) => Err(e),
+                };
+                match compared {
+                    Ok(Some(r)) => report.set(Some(r)),
+                    Ok(None) => crate::interop::show_execution_toast("there are no mods in there"),
+                    Err(e) => {
+                        gloo_console::warn!(e);
+                        crate::interop::show_execution_toast("failed to read your mods");
+                    }
+                }
+                checking.set(false);
+            });
+        }
+    };
+    let onchange = {
+        let check = check.clone();
+        Callback::from(move |e: Event| {
+            check(Picked::Files(files(
+                e.target_unchecked_into::<HtmlInputElement>().files(),
+            )))
+        })
+    };
+    let ondrop = Callback::from(move |e: DragEvent| {
+        e.prevent_default();
+        if let Some(transfer) = e.data_transfer() {
+            check(dropped(&transfer))
+        }
+    });
+    // without it the browser opens the dropped file instead
+    let ondragover = Callback::from(|e: DragEvent| e.prevent_default());
+
+    let verdict = match (&*report, *checking) {
+        (_, true) => html! { <p>{"hashing your mods..."}</p> },
+        (None, false) => html! {},
+        (Some(r), false)
+            if r.missing.is_empty() && r.extra.is_empty() && r.different.is_empty() =>
+        {
+            html! { <p>{format!("all {} mods match, you're good to go", r.matching)}</p> }
+        }
+        (Some(r), false) => html! {
+            <>
+                {names("missing, install them from the pack:", &r.missing)}
+                {names("not in the pack, remove them:", &r.extra)}
+                {names("a different version than the pack's, replace them:", &r.different)}
+            </>
+        },
+    };
+
+    html! {
+        <>
+            <p>{format!("version {}, {note}", pack.version)}</p>
+            <a href={pack.url.clone()}>{format!("download version {}", pack.version)}</a>
+            <div {ondrop} {ondragover}>
+                <p>{"choose or drop your mods folder to check it against the pack"}</p>
+                <input type="file" multiple=true webkitdirectory="" {onchange}/>
+            </div>
+            {verdict}
+        </>
+    }
+}
@@ -0,0 +1,103 @@
+//! Commands sent to sentinel and what became of them.
+
+use common::{ClientOpt, Outcome, Response};
+use instant::Instant;
+use std::collections::BTreeMap;
+use yew::prelude::*;
+use yew_vdom_gen::prelude::*;
+
+/// How many finished requests stay on screen.
+const KEEP_FINISHED: usize = 5;
+
+const SPINNER: [&str; 4] = ["◐", "◓", "◑", "◒"];
+
+pub struct Request {
+    pub opt: ClientOpt,
+    /// `None` until sentinel responds.
+    pub outcome: Option<Outcome>,
+    pub since: Instant,
+}
+
+impl Request {
+    fn finished(&self) -> bool {
+        matches!(
+            self.outcome,
+            Some(Outcome::Rejected { .. } | Outcome::Completed | Outcome::Failed { .. })
+        )
+    }
+}
+
+pub fn describe(opt: ClientOpt) -> &'static str {
+    match opt {
+        ClientOpt::On => "power on",
+        ClientOpt::Off => "shutdown",
+        ClientOpt::Reboot => "reboot",
+        ClientOpt::RefreshNow => "refresh",
+        ClientOpt::ComposeUp => "compose up",
+        ClientOpt::ComposeDown => "compose down",
+        ClientOpt::ComposeRestart => "compose restart",
+        ClientOpt::ComposePull => "image pull",
+    }
+}
+
+#[derive(Default)]
+pub struct Requests(BTreeMap<u64, Request>);
+
+impl Requests {
+    pub fn sent(&mut self, id: u64, opt: ClientOpt) {
+        self.0.insert(
+            id,
+            Request {
+                opt,
+                outcome: None,
+                since: Instant::now(),
+            },
+        );
+
+        let finished: Vec<_> = self
+            .0
+            .iter()
+            .filter(|(_, r)| r.finished())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished.iter().rev().skip(KEEP_FINISHED) {
+            self.0.remove(id);
+        }
+    }
+
+    /// Returns the request the response is about, if we remember it.
+    pub fn respond(&mut self, Response { request, outcome }: Response) -> Option<&Request> {
+        let r = self.0.get_mut(&request)?;
+        r.outcome = Some(outcome);
+        Some(r)
+    }
+
+    /// Whether an `opt` is still waiting for sentinel.
+    pub fn in_flight(&self, opt: ClientOpt) -> bool {
+        self.0.values().any(|r| r.opt == opt && !r.finished())
+    }
+
+    /// The connection dropped, nothing unfinished will ever hear back.
+    pub fn lost(&mut self) {
+        for r in self.0.values_mut().filter(|r| !r.finished()) {
+            r.outcome = Some(Outcome::Failed {
+                reason: "lost connection to sentinel".to_string(),
+            });
+        }
+    }
+
+    pub fn view(&self) -> Html {
+        let list = self.0.values().rev().fold(ul(), |list, r| {
+            let spinner = SPINNER[(r.since.elapsed().as_millis() / 150) as usize % SPINNER.len()];
+            let state = match &r.outcome {
+                None => format!("{spinner} waiting for sentinel"),
+                Some(Outcome::Ack) => format!("{spinner} sentinel is on it"),
+                Some(Outcome::Completed) => "✔ done".to_string(),
+                Some(Outcome::Rejected { reason }) => format!("✘ rejected: {reason}"),
+                Some(Outcome::Failed { reason }) => format!("✘ failed: {reason}"),
+            };
+            list.child(li().child(format!("{}: {state}", describe(r.opt))))
+        });
+        list.into()
+    }
+}
@@ -0,0 +1,127 @@
+//! The whitelist, ops and bans, and adding players to and removing them from them.
+
+use common::{ListedPlayer, PlayerList, Role, Roster, RosterEdit};
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+
+#[derive(Properties, PartialEq)]
+pub struct RosterPageProps {
+    pub roster: Roster,
+    /// Decides which lists can be edited, sentinel checks again anyway.
+    pub role: Role,
+    pub on_edit: Callback<RosterEdit>,
+}
+
+#[function_component(RosterPage)]
+pub fn roster_page(props: &RosterPageProps) -> Html {
+    let lists = [
+        (PlayerList::Whitelist, "Whitelist", &props.roster.whitelist),
+        (PlayerList::Ops, "Ops", &props.roster.ops),
+        (PlayerList::Bans, "Bans", &props.roster.bans),
+    ];
+    let lists = lists.into_iter().map(|(list, title, players)| {
+        html! {
+            <>
+                <h2>{title}</h2>
+                <PlayerListEditor
+                    {list}
+                    players={players.clone()}
+                    editable={props.role.may_edit(list)}
+                    on_edit={props.on_edit.clone()}
+                />
+            </>
+        }
+    });
+    html! { <>{ for lists }</> }
+}
+
+#[derive(Properties, PartialEq)]
+struct PlayerListEditorProps {
+    list: PlayerList,
+    players: Vec<ListedPlayer>,
+    editable: bool,
+    on_edit: Callback<RosterEdit>,
+}
+
+#[function_component(PlayerListEditor)]
+fn player_list_editor(props: &PlayerListEditorProps) -> Html {
+    let name = use_state(String::new);
+    let reason = use_state(String::new);
+    let list = props.list;
+
+    let players = props.players.iter().map(|player| {
+        let label = match (&player.detail, list) {
+            (Some(level), PlayerList::Ops) => format!("{} (level {level})", player.name),
+            (Some(reason), _) => format!("{}: {reason}", player.name),
+            (None, _) => player.name.clone(),
+        };
+        let remove = if props.editable {
+            let on_edit = props.on_edit.clone();
+            let name = player.name.clone();
+            let onclick = Callback::from(move |_| {
+                on_edit.emit(RosterEdit {
+                    list,
+                    name: name.clone(),
+                    add: false,
+                    reason: None,
+                })
+            });
+            html! { <button {onclick}>{"remove"}</button> }
+        } else {
+            html! {}
+        };
+        html! { <li title={player.uuid.clone()}>{label}{remove}</li> }
+    });
+
+    let players = if props.players.is_empty() {
+        html! { <p>{"nobody"}</p> }
+    } else {
+        html! { <ul>{ for players }</ul> }
+    };
+
+    if !props.editable {
+        return players;
+    }
+
+    let input = |state: &UseStateHandle<String>, placeholder: &'static str| {
+        let oninput = {
+            let state = state.clone();
+            Callback::from(move |e: InputEvent| {
+                state.set(e.target_unchecked_into::<HtmlInputElement>().value())
+            })
+        };
+        html! { <input type="text" {placeholder} value={(**state).clone()} {oninput}/> }
+    };
+
+    let onsubmit = {
+        let on_edit = props.on_edit.clone();
+        let (name, reason) = (name.clone(), reason.clone());
+        Callback::from(move |e: FocusEvent| {
+            e.prevent_default();
+            if name.trim().is_empty() {
+                return;
+            }
+            on_edit.emit(RosterEdit {
+                list,
+                name: name.trim().to_string(),
+                add: true,
+                reason: Some(reason.trim().to_string()).filter(|r| !r.is_empty()),
+            });
+            name.set(String::new());
+            reason.set(String::new());
+        })
+    };
+
+    html! {
+        <>
+            {players}
+            <form {onsubmit}>
+                {input(&name, "minecraft name")}
+                if list == PlayerList::Bans {
+                    {input(&reason, "reason")}
+                }
+                <button type="submit">{"add"}</button>
+            </form>
+        </>
+    }
+}
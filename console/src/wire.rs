@@ -0,0 +1,40 @@
+//! Framing for the websocket, see `common::protocol`.
+
+use common::protocol::{Envelope, Hello};
+use common::{ClientOpt, Newspeak, Oldspeak};
+use reqwasm::websocket::Message;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// After this long without hearing from sentinel the console sends a heartbeat.
+pub const HEARTBEAT_INTERVAL_MS: u32 = 10_000;
+
+/// After this long without hearing from sentinel, not even a heartbeat, the console reconnects.
+pub const SILENCE_LIMIT: Duration = Duration::from_secs(30);
+
+/// The first message on every fresh connection.
+pub fn hello() -> Message {
+    NEXT_ID.store(0, Ordering::Relaxed);
+    frame(Oldspeak::Hello(Hello::ours()))
+}
+
+pub fn frame(body: Oldspeak) -> Message {
+    let envelope = Envelope {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        body,
+    };
+    Message::Bytes(bincode::encode_to_vec(envelope, bincode::config::standard()).unwrap())
+}
+
+pub fn unframe(bytes: &[u8]) -> Result<Newspeak, bincode::error::DecodeError> {
+    bincode::decode_from_slice::<Envelope<Newspeak>, _>(bytes, bincode::config::standard())
+        .map(|(envelope, _)| envelope.body)
+}
+
+/// Frames a command, returning the id its [`Response`](common::Response)s will refer to.
+pub fn request(opt: ClientOpt) -> (u64, Message) {
+    let id = NEXT_ID.load(Ordering::Relaxed);
+    (id, frame(Oldspeak::Opt(opt)))
+}
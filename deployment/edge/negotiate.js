@@ -0,0 +1,58 @@
+// A CloudFront Function for viewer requests to the assets distribution.
+//
+// `deploy` uploads every compressible asset as is, and next to it with `.br`, `.zst` and `.gz`
+// appended, brotli, zstd and gzip compressed with the matching Content-Encoding. This picks the
+// variant the browser accepts with the highest q-value, preferring them in that order when it
+// weighs them the same, or the asset as is if it accepts none. The extensions have to match
+// `COMPRESSIBLE` and the encodings `ENCODINGS` in `src/lib.rs`.
+var COMPRESSIBLE = /\.(js|wasm|css|html|svg|json|txt|xml|map)$/;
+
+var ENCODINGS = [['br', '.br'], ['zstd', '.zst'], ['gzip', '.gz']];
+
+// The q-value of every coding in an Accept-Encoding header, 1 if it has none.
+function qualities(header) {
+    var q = {};
+    header.split(',').forEach(function (part) {
+        var params = part.split(';');
+        var coding = params[0].trim().toLowerCase();
+        if (!coding) {
+            return;
+        }
+        var quality = 1;
+        for (var i = 1; i < params.length; i++) {
+            var param = params[i].trim().toLowerCase();
+            if (param.indexOf('q=') === 0) {
+                quality = parseFloat(param.slice(2));
+                if (isNaN(quality)) {
+                    quality = 0;
+                }
+            }
+        }
+        q[coding] = quality;
+    });
+    return q;
+}
+
+// The suffix of the best variant for the header, '' for the asset as is. `br;q=0` refuses br.
+function negotiate(header) {
+    var q = qualities(header);
+    var best = '';
+    var bestQuality = 0;
+    ENCODINGS.forEach(function (encoding) {
+        var quality = encoding[0] in q ? q[encoding[0]] : ('*' in q ? q['*'] : 0);
+        if (quality > bestQuality) {
+            best = encoding[1];
+            bestQuality = quality;
+        }
+    });
+    return best;
+}
+
+function handler(event) {
+    var request = event.request;
+    var header = request.headers['accept-encoding'];
+    if (header && COMPRESSIBLE.test(request.uri)) {
+        request.uri += negotiate(header.value);
+    }
+    return request;
+}
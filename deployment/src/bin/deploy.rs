@@ -0,0 +1,197 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use deployment::config::Config;
+use deployment::modpack::{self, Remote};
+use deployment::progress::{Progress, View};
+use deployment::{service, static_deployment};
+
+/// Deploy the static sites, modpacks and services `deploy.toml` describes.
+#[derive(Parser)]
+struct Args {
+    /// Only show what would change, after building.
+    #[clap(long, global = true)]
+    dry_run: bool,
+    /// Print every step and the output of the tools it runs as it goes, instead of the live view.
+    #[clap(long, short, global = true)]
+    verbose: bool,
+    /// Only deploy this entry of the command's section of the config, can be given more than once.
+    /// Every entry by default.
+    #[clap(long, global = true)]
+    target: Vec<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build a new release of static sites and switch to it.
+    Site {
+        #[clap(subcommand)]
+        action: Option<SiteAction>,
+    },
+    /// Build and upload modpacks, and make them current.
+    Pack {
+        #[clap(subcommand)]
+        action: Option<PackAction>,
+    },
+    /// Build services and switch their hosts over to them, rolling back if they aren't healthy.
+    Service,
+}
+
+#[derive(Subcommand)]
+enum SiteAction {
+    /// List the releases still there, newest first.
+    ListReleases,
+    /// Make an older release live again.
+    Rollback { release: String },
+}
+
+#[derive(Subcommand, Clone)]
+enum PackAction {
+    /// List the uploaded versions, newest first.
+    List,
+    /// Make an older version current again.
+    Rollback { version: String },
+}
+
+impl Args {
+    /// Whether the command changes something, and ctrl-c has to wait for it to be safe to stop.
+    fn changes(&self) -> bool {
+        !self.dry_run
+            && !matches!(
+                self.command,
+                Command::Site {
+                    action: Some(SiteAction::ListReleases)
+                } | Command::Pack {
+                    action: Some(PackAction::List)
+                }
+            )
+    }
+
+    /// Whether the live view shows what's going on, only for deploys.
+    fn live(&self) -> bool {
+        self.changes()
+            && !self.verbose
+            && matches!(
+                self.command,
+                Command::Site { action: None } | Command::Pack { action: None } | Command::Service
+            )
+    }
+}
+
+async fn run(args: &Args, config: &Config, progress: &Progress) -> Result<()> {
+    match &args.command {
+        Command::Site { action } => {
+            let sites = Config::select(&config.sites, &args.target)?;
+            if let Some(SiteAction::Rollback { .. }) = action {
+                one(&sites, "roll back")?;
+            }
+            for (name, site) in sites {
+                match action {
+                    None => {
+                        progress.step(format!("deploying {name}"));
+                        static_deployment::deploy(site, args.dry_run, progress).await?
+                    }
+                    Some(SiteAction::ListReleases) => {
+                        println!("{name}:");
+                        static_deployment::list_releases(site).await?
+                    }
+                    Some(SiteAction::Rollback { release }) => {
+                        static_deployment::rollback(site, release, progress).await?
+                    }
+                }
+            }
+        }
+        Command::Pack { action } => {
+            let packs = Config::select(&config.packs, &args.target)?;
+            if let Some(PackAction::Rollback { .. }) = action {
+                one(&packs, "roll back")?;
+            }
+            for (name, pack) in packs {
+                let (name, pack, action) = (name.to_string(), pack.clone(), action.clone());
+                let (dry_run, progress) = (args.dry_run, progress.clone());
+                blocking(move || {
+                    let remote = Remote {
+                        name: &name,
+                        pack: &pack,
+                    };
+                    match action {
+                        None => {
+                            progress.step(format!("deploying {name}"));
+                            modpack::deploy(&remote, dry_run, &progress)
+                        }
+                        Some(PackAction::List) => {
+                            println!("{name}:");
+                            modpack::list(&remote, &progress)
+                        }
+                        Some(PackAction::Rollback { version }) => {
+                            modpack::rollback(&remote, &version, &progress)
+                        }
+                    }
+                })
+                .await?;
+            }
+        }
+        Command::Service => {
+            for (name, service) in Config::select(&config.services, &args.target)? {
+                progress.step(format!("deploying {name}"));
+                let (service, dry_run, progress) =
+                    (service.clone(), args.dry_run, progress.clone());
+                blocking(move || service::deploy(&service, dry_run, &progress)).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs what waits on the tools it calls, and sleeps between them, off the async workers.
+async fn blocking(f: impl FnOnce() -> Result<()> + Send + 'static) -> Result<()> {
+    tokio::task::spawn_blocking(f).await?
+}
+
+/// A deploy that stopped because of ctrl-c exits the way it would have without waiting for it.
+fn exit(result: Result<()>, aborted: bool) -> Result<()> {
+    match result {
+        Err(e) if aborted => {
+            eprintln!("Error: {e:?}");
+            std::process::exit(130);
+        }
+        result => result,
+    }
+}
+
+/// Some things only make sense for one entry at a time.
+fn one<T>(selected: &[T], what: &str) -> Result<()> {
+    if selected.len() != 1 {
+        anyhow::bail!("pick the one to {what} with --target");
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let config = Config::load()?;
+    // a terminal the view is drawn on doesn't send ctrl-c, the view aborts the deploy instead
+    let (progress, view) = if args.live() {
+        View::start()?
+    } else {
+        (Progress::plain(args.verbose), None)
+    };
+    if view.is_none() && args.changes() {
+        let aborting = progress.clone();
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                aborting.abort();
+            }
+        });
+    }
+    let result = run(&args, &config, &progress).await;
+    let aborted = progress.aborted();
+    // the view closes once nothing reports to it anymore
+    drop(progress);
+    if let Some(view) = view {
+        view.finish()?;
+    }
+    exit(result, aborted)
+}
@@ -0,0 +1,164 @@
+//! The CDNs in front of static sites, which have to be told to forget what changed.
+//!
+//! Besides [`CloudFront`], a [`LocalCdn`] stands in for one, writing each invalidation's paths to
+//! a `<id>.json` file in a directory.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use aws_sdk_cloudfront::model::{InvalidationBatch, Paths};
+use aws_sdk_cloudfront::{Client, Region};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often [`Invalidate::wait`] asks whether an invalidation completed.
+const POLL: Duration = Duration::from_secs(10);
+
+/// How long [`Invalidate::wait`] waits at most. CloudFront usually takes a minute or two.
+const WAIT: Duration = Duration::from_secs(15 * 60);
+
+#[async_trait]
+pub trait Invalidate: Send + Sync {
+    /// Starts invalidating `paths`, returning the invalidation's id.
+    async fn invalidate(&self, paths: &[String]) -> Result<String>;
+
+    async fn completed(&self, id: &str) -> Result<bool>;
+
+    /// Returns once the invalidation `id` completed, or fails after [`WAIT`].
+    async fn wait(&self, id: &str) -> Result<()> {
+        let started = SystemTime::now();
+        while !self.completed(id).await? {
+            if started.elapsed()? > WAIT {
+                bail!("invalidation {id} is still in progress after {WAIT:?}");
+            }
+            tokio::time::sleep(POLL).await;
+        }
+        Ok(())
+    }
+}
+
+/// A CloudFront distribution.
+pub struct CloudFront {
+    client: Client,
+    distribution: String,
+}
+
+impl CloudFront {
+    pub async fn new(distribution: &str) -> Result<Self> {
+        dotenv::dotenv().ok();
+        // CloudFront is global, its API only lives in us-east-1
+        let shared_config = aws_config::from_env()
+            .region(Region::new("us-east-1"))
+            .load()
+            .await;
+        Ok(Self {
+            client: Client::new(&shared_config),
+            distribution: distribution.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Invalidate for CloudFront {
+    async fn invalidate(&self, paths: &[String]) -> Result<String> {
+        // tells retries of the same request apart from new ones
+        let caller_reference = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos()
+            .to_string();
+        let batch = InvalidationBatch::builder()
+            .paths(
+                Paths::builder()
+                    .quantity(paths.len() as i32)
+                    .set_items(Some(paths.to_vec()))
+                    .build(),
+            )
+            .caller_reference(caller_reference)
+            .build();
+        let created = self
+            .client
+            .create_invalidation()
+            .distribution_id(&self.distribution)
+            .invalidation_batch(batch)
+            .send()
+            .await
+            .with_context(|| format!("failed to invalidate {}", paths.join(" ")))?;
+        created
+            .invalidation
+            .and_then(|i| i.id)
+            .context("CloudFront didn't say which invalidation it created")
+    }
+
+    async fn completed(&self, id: &str) -> Result<bool> {
+        let invalidation = self
+            .client
+            .get_invalidation()
+            .distribution_id(&self.distribution)
+            .id(id)
+            .send()
+            .await?
+            .invalidation
+            .with_context(|| format!("there is no invalidation {id}"))?;
+        Ok(invalidation.status.as_deref() == Some("Completed"))
+    }
+}
+
+/// A local directory, to try invalidations out. They complete at once.
+pub struct LocalCdn {
+    pub root: PathBuf,
+}
+
+impl LocalCdn {
+    fn path(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{id}.json"))
+    }
+}
+
+#[async_trait]
+impl Invalidate for LocalCdn {
+    async fn invalidate(&self, paths: &[String]) -> Result<String> {
+        tokio::fs::create_dir_all(&self.root).await?;
+        let mut count = 0;
+        let mut entries = tokio::fs::read_dir(&self.root).await?;
+        while entries.next_entry().await?.is_some() {
+            count += 1;
+        }
+        let id = format!("I{}", count + 1);
+        tokio::fs::write(self.path(&id), serde_json::to_vec(paths)?).await?;
+        Ok(id)
+    }
+
+    async fn completed(&self, id: &str) -> Result<bool> {
+        if !self.path(id).exists() {
+            bail!("there is no invalidation {id}");
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_cdn() {
+        let dir = tempfile::tempdir().unwrap();
+        let cdn = LocalCdn {
+            root: dir.path().join("cdn"),
+        };
+        let paths = vec![
+            "/minecraft/".to_string(),
+            "/minecraft/index.html".to_string(),
+        ];
+        let id = cdn.invalidate(&paths).await.unwrap();
+        assert_eq!(id, "I1");
+        cdn.wait(&id).await.unwrap();
+        let written = std::fs::read(cdn.path(&id)).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Vec<String>>(&written).unwrap(),
+            paths
+        );
+
+        assert_eq!(cdn.invalidate(&paths).await.unwrap(), "I2");
+        assert!(cdn.completed("I3").await.is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! `deploy.toml`, describing everything `deploy` deploys and where to.
+
+use crate::cdn::{CloudFront, Invalidate, LocalCdn};
+use crate::target::{DeployTarget, LocalDir, Ssh, S3};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Where the config is read from, unless `DEPLOY_CONFIG` says otherwise.
+pub const CONFIG: &str = "deploy.toml";
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub sites: BTreeMap<String, Site>,
+    /// By the name their zips and manifests are served under.
+    #[serde(default)]
+    pub packs: BTreeMap<String, Pack>,
+    /// Binaries running as systemd services, like sentinel.
+    #[serde(default)]
+    pub services: BTreeMap<String, Service>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Site {
+    /// The yew crate to build, relative to where the deployment runs.
+    #[serde(rename = "crate")]
+    pub yew_crate: PathBuf,
+    /// Where the site's assets go in `assets`, no starting or ending slash.
+    pub prefix: String,
+    /// Where `assets` is served from, no ending slash.
+    pub assets_url: String,
+    /// How many releases stay in `assets` after a deploy, the new one included, at least 2. Pages
+    /// loaded before a switch keep finding their assets, and the older ones can be rolled back to.
+    #[serde(default = "default_keep_releases")]
+    pub keep_releases: usize,
+    #[serde(default)]
+    pub compression: Compression,
+    /// Holds the assets of every release.
+    pub assets: Target,
+    /// Serves the live `index.html`.
+    pub index: Target,
+    /// In front of `index`, it has to forget the old `index.html` whenever it's replaced.
+    pub cdn: Option<Cdn>,
+}
+
+fn default_keep_releases() -> usize {
+    3
+}
+
+/// How hard to compress the variants of each asset, from 0 to 11 for brotli, 1 to 22 for zstd and
+/// 0 to 9 for gzip.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Compression {
+    pub brotli: u32,
+    pub zstd: u32,
+    pub gzip: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            brotli: 11,
+            zstd: 19,
+            gzip: 9,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cdn {
+    /// Where the root of `index` is served on the CDN, starting and ending with a slash.
+    #[serde(default = "default_cdn_root")]
+    pub root: String,
+    /// Whether deploys and rollbacks wait for the invalidation to complete.
+    #[serde(default)]
+    pub wait: bool,
+    pub distribution: Distribution,
+}
+
+fn default_cdn_root() -> String {
+    "/".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Distribution {
+    CloudFront {
+        id: String,
+    },
+    /// Writes invalidations to a directory instead.
+    Local {
+        root: PathBuf,
+    },
+}
+
+impl Distribution {
+    pub async fn open(&self) -> Result<Box<dyn Invalidate>> {
+        let cdn: Box<dyn Invalidate> = match self {
+            Distribution::CloudFront { id } => Box::new(CloudFront::new(id).await?),
+            Distribution::Local { root } => Box::new(LocalCdn { root: root.clone() }),
+        };
+        Ok(cdn)
+    }
+}
+
+/// A modpack, see [`crate::modpack`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pack {
+    /// The directory the pack is built from.
+    pub dir: PathBuf,
+    /// The compose file whose `VERSION` and `FORGE_VERSION` the pack is for.
+    pub compose: PathBuf,
+    /// The web server, anything ssh takes.
+    pub host: String,
+    /// The web server's root. Every version goes in `<root>/<name>/`, and `<root>/<name>.zip` and
+    /// `<root>/<name>.json` point at the current one.
+    pub root: String,
+    /// Where `root` is served, no ending slash.
+    pub url: String,
+}
+
+/// A binary of the workspace running as a systemd service.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Service {
+    /// The package the binary is built from.
+    pub package: String,
+    /// The workspace's root.
+    pub workspace: PathBuf,
+    /// What `cross` builds for, the host's target triple.
+    pub triple: String,
+    /// Anything ssh takes, like `root@xray`.
+    pub host: String,
+    /// What the unit runs, a symlink to one of the binaries uploaded next to it.
+    pub path: String,
+    /// The systemd unit running it.
+    pub unit: String,
+    /// Answers with a success once the service is up, like sentinel's `/healthz`.
+    pub health: String,
+    /// How many binaries stay on the host after a deploy, the new one included.
+    #[serde(default = "default_keep_releases")]
+    pub keep_releases: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Target {
+    S3 {
+        bucket: String,
+        region: String,
+        /// Prepended to every key.
+        #[serde(default)]
+        root: String,
+        /// For S3 compatible stores like MinIO.
+        endpoint: Option<String>,
+    },
+    Local {
+        root: PathBuf,
+    },
+    Ssh {
+        /// Anything ssh takes, like `root@xray`.
+        host: String,
+        root: String,
+    },
+}
+
+impl Target {
+    pub async fn open(&self) -> Result<Box<dyn DeployTarget>> {
+        let target: Box<dyn DeployTarget> = match self {
+            Target::S3 {
+                bucket,
+                region,
+                root,
+                endpoint,
+            } => Box::new(S3::new(bucket, region, root, endpoint.as_deref()).await?),
+            Target::Local { root } => Box::new(LocalDir { root: root.clone() }),
+            Target::Ssh { host, root } => Box::new(Ssh {
+                host: host.clone(),
+                root: root.clone(),
+            }),
+        };
+        Ok(target)
+    }
+}
+
+impl Config {
+    /// Reads [`CONFIG`], or the file `DEPLOY_CONFIG` names.
+    pub fn load() -> Result<Self> {
+        dotenv::dotenv().ok();
+        let path = std::env::var("DEPLOY_CONFIG").unwrap_or_else(|_| CONFIG.to_string());
+        let config =
+            std::fs::read_to_string(&path).with_context(|| format!("failed to read {path}"))?;
+        Self::parse(&config).with_context(|| format!("{path} is not a valid config"))
+    }
+
+    pub fn parse(config: &str) -> Result<Self> {
+        let config: Config = toml::from_str(config)?;
+        for (name, site) in &config.sites {
+            // pages still running the previous release load their assets from it for a while
+            if site.keep_releases < 2 {
+                bail!("site `{name}` has to keep the release it deploys and the one before it");
+            }
+            let Compression { brotli, zstd, gzip } = site.compression;
+            if brotli > 11 || !(1..=22).contains(&zstd) || gzip > 9 {
+                bail!("site `{name}` asks for a compression level there is none of");
+            }
+            if site.prefix.starts_with('/') || site.prefix.ends_with('/') {
+                bail!("the prefix of site `{name}` can't start or end with a slash");
+            }
+            if let Some(cdn) = &site.cdn {
+                if !cdn.root.starts_with('/') || !cdn.root.ends_with('/') {
+                    bail!("the cdn root of site `{name}` has to start and end with a slash");
+                }
+            }
+        }
+        for (name, service) in &config.services {
+            if service.keep_releases == 0 {
+                bail!("service `{name}` has to keep at least the binary it deploys");
+            }
+        }
+        Ok(config)
+    }
+
+    /// The entries of a section of the config named in `targets`, all of them if it names none.
+    pub fn select<'a, T>(
+        section: &'a BTreeMap<String, T>,
+        targets: &[String],
+    ) -> Result<Vec<(&'a str, &'a T)>> {
+        if targets.is_empty() {
+            return Ok(section.iter().map(|(name, t)| (name.as_str(), t)).collect());
+        }
+        targets
+            .iter()
+            .map(|name| match section.get_key_value(name) {
+                Some((name, t)) => Ok((name.as_str(), t)),
+                None => bail!("there is no `{name}` to deploy in the config"),
+            })
+            .collect()
+    }
+
+    pub fn site(&self, name: &str) -> Result<&Site> {
+        self.sites
+            .get(name)
+            .with_context(|| format!("there is no site `{name}` in the config"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deploy_toml() {
+        let config = Config::parse(include_str!("../deploy.toml")).unwrap();
+        let console = config.site("console").unwrap();
+        assert_eq!(console.prefix, "minecraft");
+        assert!(matches!(console.index, Target::Ssh { .. }));
+        assert!(config.site("fov-calculator").is_ok());
+        assert!(console.cdn.is_none());
+        assert_eq!(config.packs["commupack"].host, "xray");
+        assert_eq!(config.services["sentinel"].unit, "sentinel");
+    }
+
+    #[test]
+    fn test_select() {
+        let config = Config::parse(include_str!("../deploy.toml")).unwrap();
+        let all = Config::select(&config.sites, &[]).unwrap();
+        assert_eq!(
+            all.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            vec!["console", "fov-calculator"]
+        );
+        let one = Config::select(&config.sites, &["fov-calculator".to_string()]).unwrap();
+        assert_eq!(one[0].1.prefix, "minecraft/fov-calculator");
+        assert!(Config::select(&config.sites, &["sentinel".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_local_site() {
+        let local = r#"
+            [sites.console]
+            crate = "../console"
+            prefix = "minecraft"
+            assets_url = "http://localhost:8080"
+            assets = { kind = "local", root = "/tmp/assets" }
+            index = { kind = "local", root = "/tmp/site" }
+            "#;
+        let config = Config::parse(local).unwrap();
+        assert_eq!(config.site("console").unwrap().keep_releases, 3);
+        assert_eq!(config.site("console").unwrap().compression.brotli, 11);
+        assert_eq!(config.site("console").unwrap().compression.zstd, 19);
+        assert!(config.site("missing").is_err());
+        let greedy = local.replace(
+            "assets =",
+            "compression = { zstd = 23 }\n            assets =",
+        );
+        assert!(Config::parse(&greedy).is_err());
+        let forgetful = local.replace("assets =", "keep_releases = 1\n            assets =");
+        assert!(Config::parse(&forgetful).is_err());
+        assert!(Config::parse(&local.replace("\"minecraft\"", "\"/minecraft\"")).is_err());
+    }
+
+    #[test]
+    fn test_cdn() {
+        let site = r#"
+            [sites.console]
+            crate = "../console"
+            prefix = "minecraft"
+            assets_url = "http://localhost:8080"
+            assets = { kind = "local", root = "/tmp/assets" }
+            index = { kind = "local", root = "/tmp/site" }
+            cdn = { root = "/minecraft/", distribution = { kind = "cloudfront", id = "E1" } }
+            "#;
+        let config = Config::parse(site).unwrap();
+        let cdn = config.site("console").unwrap().cdn.as_ref().unwrap();
+        assert!(!cdn.wait);
+        assert!(matches!(&cdn.distribution, Distribution::CloudFront { id } if id == "E1"));
+        assert!(Config::parse(&site.replace("\"/minecraft/\"", "\"minecraft\"")).is_err());
+    }
+}
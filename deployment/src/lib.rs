@@ -1,32 +1,91 @@
-pub mod static_deployment;
+pub mod cdn;
+pub mod config;
+pub mod modpack;
 pub mod path_util;
+pub mod progress;
+pub mod service;
+pub mod static_deployment;
+pub mod target;
 
-use async_compression::tokio::write::BrotliEncoder;
+use async_compression::tokio::write::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use async_compression::Level;
+use config::Compression;
 use futures::StreamExt;
 use std::path::Path;
 use std::time::Instant;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use walkdir::WalkDir;
 
-/// gzip everything except index.html
-async fn gzip_release_dir<P: AsRef<Path>>(dir: P) -> String {
-    let compress = WalkDir::new(dir)
+/// The file types worth compressing. `edge/negotiate.js` serves the variants of exactly these.
+pub const COMPRESSIBLE: &[&str] = &[
+    "js", "wasm", "css", "html", "svg", "json", "txt", "xml", "map",
+];
+
+/// The encodings every compressible file gets a variant in, with the suffix of its key and the
+/// directory it is written to next to `uncompressed`.
+pub const ENCODINGS: &[(&str, &str, &str)] = &[
+    ("br", ".br", "brotli"),
+    ("zstd", ".zst", "zstd"),
+    ("gzip", ".gz", "gzip"),
+];
+
+/// Single quotes `s` for the remote shell.
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+pub fn compressible(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some(e) if COMPRESSIBLE.contains(&e))
+}
+
+async fn finish<E: AsyncWrite + Unpin>(mut encoder: E, input: &[u8]) -> E {
+    encoder.write_all(input).await.unwrap();
+    encoder.shutdown().await.unwrap();
+    encoder
+}
+
+/// `input` compressed in `encoding`, one of [`ENCODINGS`], as hard as `levels` says
+async fn encode(encoding: &str, input: &[u8], levels: Compression) -> Vec<u8> {
+    let out = Vec::new();
+    match encoding {
+        "br" => {
+            let encoder = BrotliEncoder::with_quality(out, Level::Precise(levels.brotli));
+            finish(encoder, input).await.into_inner()
+        }
+        "zstd" => {
+            let encoder = ZstdEncoder::with_quality(out, Level::Precise(levels.zstd));
+            finish(encoder, input).await.into_inner()
+        }
+        "gzip" => {
+            let encoder = GzipEncoder::with_quality(out, Level::Precise(levels.gzip));
+            finish(encoder, input).await.into_inner()
+        }
+        _ => unreachable!("{encoding} is not one of the ENCODINGS"),
+    }
+}
+
+/// brotli, zstd and gzip everything compressible except index.html, into the `brotli`, `zstd`
+/// and `gzip` directories next to `dir`
+async fn compress_release_dir<P: AsRef<Path>>(dir: P, levels: Compression) -> String {
+    let dir = dir.as_ref().to_owned();
+    let compress = WalkDir::new(&dir)
         .into_iter()
         .filter_map(|dir| dir.ok())
         .filter_map(|d| {
             let path = d.path().to_owned();
             let file_name = path.file_name().unwrap().to_string_lossy();
-            (path.is_file() && file_name != "index.html" && (file_name.ends_with(".js") || file_name.ends_with(".wasm") )).then(
-                || {
-                    path
-                    // path.file_name().unwrap().to_string_lossy()
-                },
-            )
+            (path.is_file() && file_name != "index.html" && compressible(&path)).then_some(path)
         })
         .map(|path| {
-            let dir = path.parent().unwrap().parent().unwrap().join("brotli");
-            std::fs::create_dir_all(&dir).unwrap();
+            let relative = path.strip_prefix(&dir).unwrap().to_owned();
+            let variants = ENCODINGS
+                .iter()
+                .map(|(_, _, variant_dir)| dir.parent().unwrap().join(variant_dir).join(&relative))
+                .collect::<Vec<_>>();
+            for variant in &variants {
+                std::fs::create_dir_all(variant.parent().unwrap()).unwrap();
+            }
 
             tokio::task::spawn(async move {
                 let mut input = vec![];
@@ -36,26 +95,17 @@ async fn gzip_release_dir<P: AsRef<Path>>(dir: P) -> String {
                     .read_to_end(&mut input)
                     .await
                     .ok();
-
-                let output = BufWriter::new(
-                    File::create(dir.join(path.file_name().unwrap()))
-                        .await
-                        .unwrap(),
-                );
-                let mut encoder = BrotliEncoder::new(output);
                 let start = Instant::now();
-
-                encoder.write_all(input.as_slice()).await.ok();
-
-                encoder.shutdown().await.unwrap();
-
-                let mut contents = encoder.into_inner();
-                contents.flush().await.unwrap();
+                let mut lens = String::new();
+                for ((encoding, _, name), variant) in ENCODINGS.iter().zip(&variants) {
+                    let compressed = encode(encoding, &input, levels).await;
+                    lens.push_str(&format!("\t{name} len: {:?}", compressed.len()));
+                    tokio::fs::write(variant, compressed).await.unwrap();
+                }
                 format!(
-                    "\t{} compressed, source len: {:?}\ttarget len: {:?}\telapsed: {:?}\n",
-                    path.file_name().unwrap().to_string_lossy(),
+                    "\t{} compressed, source len: {:?}{lens}\telapsed: {:?}\n",
+                    relative.display(),
                     input.len(),
-                    contents.into_inner().metadata().await.unwrap().len(),
                     start.elapsed()
                 )
             })
@@ -77,6 +127,30 @@ mod tests {
 
     #[tokio::test]
     async fn test_compress_fov_works() {
-        gzip_release_dir("../fov-calculator/dist/release/uncompressed").await;
+        compress_release_dir(
+            "../fov-calculator/dist/release/uncompressed",
+            Compression::default(),
+        )
+        .await;
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("/site/minecraft"), "'/site/minecraft'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_edge_rule_agrees() {
+        let edge_rule = include_str!("../edge/negotiate.js");
+        for extension in COMPRESSIBLE {
+            assert!(
+                edge_rule.contains(extension),
+                "the edge rule misses {extension}"
+            );
+        }
+        for (encoding, suffix, _) in ENCODINGS {
+            assert!(edge_rule.contains(&format!("['{encoding}', '{suffix}']")));
+        }
     }
 }
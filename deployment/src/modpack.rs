@@ -0,0 +1,340 @@
+//! Versioned modpacks.
+//!
+//! `deploy pack` zips a pack's directory as `<name>-<version>.zip` with a [`Modpack`] manifest in
+//! it, uploads it next to the older versions and points `<name>.zip`, which the compose file's
+//! `MODPACK` downloads, at it. The manifest is also served on its own as `<name>.json`, for the
+//! console's mod checker. The older versions stay around for `deploy pack rollback`.
+
+use crate::config::Pack;
+use crate::progress::Progress;
+use crate::quote;
+use anyhow::{bail, Context, Result};
+use common::{ModFile, Modpack};
+use sha2::{Digest, Sha256};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+/// The manifest's name in the pack.
+pub const MANIFEST: &str = "modpack.json";
+
+/// A pack in the config, with the name its files are served under.
+pub struct Remote<'a> {
+    pub name: &'a str,
+    pub pack: &'a Pack,
+}
+
+impl Remote<'_> {
+    fn file_name(&self, version: &str) -> String {
+        format!("{}-{version}.zip", self.name)
+    }
+
+    fn manifest_name(&self, version: &str) -> String {
+        format!("{}-{version}.json", self.name)
+    }
+
+    /// Where every version lives on the web server.
+    fn dir(&self) -> String {
+        format!("{}/{}", self.pack.root, self.name)
+    }
+
+    /// What `MODPACK` downloads, a symlink to the current version.
+    fn current(&self) -> String {
+        format!("{}/{}.zip", self.pack.root, self.name)
+    }
+
+    /// The current version's manifest, a symlink too.
+    fn current_manifest(&self) -> String {
+        format!("{}/{}.json", self.pack.root, self.name)
+    }
+
+    fn ssh(&self, script: &str, progress: &Progress) -> Result<String> {
+        progress.run(Command::new("ssh").arg(&self.pack.host).arg(script))
+    }
+
+    /// Points the current zip and manifest at `version`, atomically so a download never finds
+    /// them missing.
+    fn switch(&self, version: &str, progress: &Progress) -> Result<()> {
+        let _critical = progress.critical();
+        let zip = quote(&format!("{}/{}", self.dir(), self.file_name(version)));
+        let manifest = quote(&format!("{}/{}", self.dir(), self.manifest_name(version)));
+        let (current, current_manifest) = (quote(&self.current()), quote(&self.current_manifest()));
+        self.ssh(
+            &format!(
+                "test -f {zip} && test -f {manifest} \
+                 && ln -sfn {zip} {current}.new && mv -T {current}.new {current} \
+                 && ln -sfn {manifest} {current_manifest}.new \
+                 && mv -T {current_manifest}.new {current_manifest}"
+            ),
+            progress,
+        )
+        .with_context(|| format!("there is no {} {version}", self.name))?;
+        Ok(())
+    }
+}
+
+/// The `VERSION` and `FORGE_VERSION` the compose file gives the image.
+pub fn versions_in(compose: &str) -> (Option<String>, Option<String>) {
+    let get = |key: &str| {
+        compose.lines().find_map(|line| {
+            let (k, v) = line.trim().split_once(':')?;
+            (k.trim() == key).then(|| v.trim().trim_matches(['"', '\'']).to_string())
+        })
+    };
+    (get("VERSION"), get("FORGE_VERSION"))
+}
+
+/// Every file under `dir` but the manifest, hashed and in a stable order.
+pub fn files(dir: &Path) -> Result<Vec<ModFile>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .strip_prefix(dir)?
+            .iter()
+            .map(|s| s.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if path == MANIFEST {
+            continue;
+        }
+        let bytes = std::fs::read(entry.path())?;
+        files.push(ModFile {
+            path,
+            size: bytes.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&bytes)),
+        });
+    }
+    Ok(files)
+}
+
+/// Where zip stores the files under `dir`, which is `dir` without what makes it absolute.
+fn stored(dir: &Path) -> PathBuf {
+    dir.components()
+        .filter(|c| matches!(c, Component::Normal(_) | Component::ParentDir))
+        .collect()
+}
+
+/// The same files for the same game always make the same version.
+pub fn version(minecraft: &str, forge: Option<&str>, files: &[ModFile]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(minecraft);
+    hasher.update([0]);
+    hasher.update(forge.unwrap_or_default());
+    for file in files {
+        hasher.update([0]);
+        hasher.update(&file.path);
+        hasher.update([0]);
+        hasher.update(&file.sha256);
+    }
+    format!("{:x}", hasher.finalize())[..12].to_string()
+}
+
+/// The manifest of the pack.
+pub fn manifest(remote: &Remote, minecraft: String, forge: Option<String>) -> Result<Modpack> {
+    let files = files(&remote.pack.dir)?;
+    let version = version(&minecraft, forge.as_deref(), &files);
+    Ok(Modpack {
+        url: format!(
+            "{}/{}/{}",
+            remote.pack.url,
+            remote.name,
+            remote.file_name(&version)
+        ),
+        version,
+        minecraft,
+        forge,
+        built: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        files,
+    })
+}
+
+/// Builds and uploads the pack, and makes it the current one. With `dry_run`, it only builds the
+/// manifest and tells what it would upload.
+pub fn deploy(remote: &Remote, dry_run: bool, progress: &Progress) -> Result<()> {
+    let pack = remote.pack;
+    let compose = std::fs::read_to_string(&pack.compose)
+        .with_context(|| format!("failed to read {}", pack.compose.display()))?;
+    let (minecraft, forge) = versions_in(&compose);
+    let minecraft = minecraft.context("the compose file has no VERSION")?;
+
+    progress.check()?;
+    progress.step(format!("hashing {}/", pack.dir.display()));
+    let modpack = manifest(remote, minecraft, forge)?;
+    progress.log(format!(
+        "{} files, version {}",
+        modpack.files.len(),
+        modpack.version
+    ));
+
+    let zip = remote.file_name(&modpack.version);
+    if dry_run {
+        progress.step("dry run");
+        progress.log(format!(
+            "would upload {zip} and its manifest to {}:{}/",
+            pack.host,
+            remote.dir()
+        ));
+        progress.log(format!(
+            "would point {} at {}",
+            remote.current(),
+            modpack.version
+        ));
+        return Ok(());
+    }
+
+    progress.check()?;
+    progress.step(format!("uploading {zip}"));
+    let built = tempfile::tempdir()?;
+    let local = built.path().join(&zip);
+    progress.run(
+        Command::new("zip")
+            .arg("-qr")
+            .arg(&local)
+            .arg(format!("{}/", pack.dir.display())),
+    )?;
+    // the manifest goes where the pack's files are in the zip, replacing any left in the pack
+    let stored = stored(&pack.dir).join(MANIFEST);
+    // deep enough for the `..` zip keeps to stay in the temp dir
+    let climbs = stored
+        .components()
+        .filter(|c| c == &Component::ParentDir)
+        .count();
+    let staged = (0..climbs).fold(built.path().join("manifest"), |dir, i| {
+        dir.join(i.to_string())
+    });
+    let manifest = staged.join(&stored);
+    std::fs::create_dir_all(manifest.parent().unwrap_or(&staged))?;
+    std::fs::write(&manifest, serde_json::to_string_pretty(&modpack)?)?;
+    progress.run(
+        Command::new("zip")
+            .arg("-q")
+            .arg(&local)
+            .arg(&stored)
+            .current_dir(&staged),
+    )?;
+    progress.log(format!("zipped {zip}"));
+
+    remote.ssh(&format!("mkdir -p {}", quote(&remote.dir())), progress)?;
+    // the same files make the same version, which may be the one being downloaded right now, so
+    // both land next to their names and are moved over them; the manifest takes its version's name
+    let uploads = [
+        (local, zip.clone()),
+        (manifest, remote.manifest_name(&modpack.version)),
+    ];
+    let mut moves = Vec::new();
+    for (local, name) in &uploads {
+        let path = format!("{}/{name}", remote.dir());
+        progress.run(
+            Command::new("scp")
+                .arg(local)
+                .arg(format!("{}:{path}.new", pack.host)),
+        )?;
+        moves.push(format!(
+            "mv -T {} {}",
+            quote(&format!("{path}.new")),
+            quote(&path)
+        ));
+    }
+    remote.ssh(&moves.join(" && "), progress)?;
+    progress.log(format!("uploaded {zip} and its manifest"));
+
+    progress.check()?;
+    progress.step(format!(
+        "switching {} to {}",
+        remote.current(),
+        modpack.version
+    ));
+    remote.switch(&modpack.version, progress)?;
+    progress.log(format!("{}.zip is now {}", remote.name, modpack.version));
+    progress.log("restart the container to install it on the server");
+    Ok(())
+}
+
+/// Prints every uploaded version, newest first.
+pub fn list(remote: &Remote, progress: &Progress) -> Result<()> {
+    let current = remote.ssh(
+        &format!("readlink {} || true", quote(&remote.current())),
+        progress,
+    )?;
+    let uploaded = remote.ssh(&format!("ls -1t {}", quote(&remote.dir())), progress)?;
+    let prefix = format!("{}-", remote.name);
+    for zip in uploaded.lines().filter(|l| l.ends_with(".zip")) {
+        let version = zip.trim_start_matches(&prefix).trim_end_matches(".zip");
+        if current.trim().ends_with(zip) {
+            println!("{version} (current)");
+        } else {
+            println!("{version}");
+        }
+    }
+    Ok(())
+}
+
+/// Points the current zip back at an older version.
+pub fn rollback(remote: &Remote, version: &str, progress: &Progress) -> Result<()> {
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("`{version}` is not a modpack version, see `deploy pack list`");
+    }
+    remote.switch(version, progress)?;
+    println!(
+        "{}.zip is now {version}, restart the container to install it on the server",
+        remote.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_in() {
+        let compose = r#"
+      environment:
+          MODPACK: "https://siyuanyan.net/commupack.zip"
+          VERSION: "1.18.1"
+          TYPE: "FORGE"
+"#;
+        assert_eq!(versions_in(compose), (Some("1.18.1".to_string()), None));
+    }
+
+    #[test]
+    fn test_files_and_version() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("mods")).unwrap();
+        std::fs::write(dir.path().join("mods/a.jar"), "test").unwrap();
+        std::fs::write(dir.path().join(MANIFEST), "{}").unwrap();
+
+        let files = files(dir.path()).unwrap();
+        assert_eq!(
+            files,
+            vec![ModFile {
+                path: "mods/a.jar".to_string(),
+                size: 4,
+                sha256: "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+                    .to_string(),
+            }]
+        );
+
+        let version = version("1.18.1", None, &files);
+        assert_eq!(version.len(), 12);
+        assert_eq!(super::version("1.18.1", None, &files), version);
+        assert_ne!(super::version("1.18.2", None, &files), version);
+        assert_ne!(super::version("1.18.1", None, &[]), version);
+    }
+
+    #[test]
+    fn test_stored() {
+        assert_eq!(stored(Path::new("./commupack/")), Path::new("commupack"));
+        assert_eq!(
+            stored(Path::new("/srv/commupack")),
+            Path::new("srv/commupack")
+        );
+        assert_eq!(stored(Path::new("../commupack")), Path::new("../commupack"));
+    }
+}
@@ -0,0 +1,427 @@
+//! What a deploy is doing, printed line by line or shown in a live view in the terminal.
+//!
+//! A deploy goes through steps, like building, compressing and uploading, and logs what each did.
+//! [`Progress::plain`] prints a step as a line and what it logged indented under it, the way the
+//! deploys always have. [`View::start`] shows them in a tui instead, with a gauge for the objects of
+//! the current step, and prints the whole log once it's done so nothing is lost.
+//!
+//! Ctrl-c doesn't stop a deploy where it is, it asks it to with [`Progress::abort`]. The deploy
+//! checks with [`Progress::check`] before the steps it can stop at, and doesn't between switching
+//! to what it uploaded and knowing whether that is healthy, rolled back or cleaned up. Tools run in
+//! a [`Progress::critical`] section don't get the terminal's ctrl-c either.
+
+use anyhow::{anyhow, bail, Context};
+use crossterm::{
+    event::{self, Event as TermEvent, KeyCode, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    tty::IsTty,
+};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Gauge, Paragraph},
+    Frame, Terminal,
+};
+
+/// What is logged once ctrl-c is pressed.
+const ABORTING: &str = "aborting once it is safe to";
+
+enum Event {
+    /// A step starts, the one before it is done. Some steps know how many objects they go through.
+    Step(String, Option<usize>),
+    /// One more object of the current step is done.
+    Tick,
+    Log(String),
+}
+
+/// Where a deploy reports to. Cloning it is cheap.
+#[derive(Clone)]
+pub struct Progress {
+    /// `None` prints every event as it comes.
+    view: Option<Arc<Mutex<Sender<Event>>>>,
+    verbose: bool,
+    aborted: Arc<AtomicBool>,
+    /// How many [`Critical`] sections are open.
+    critical: Arc<AtomicUsize>,
+}
+
+/// Keeps the tools a deploy runs from the terminal's ctrl-c until dropped.
+pub struct Critical(Arc<AtomicUsize>);
+
+impl Drop for Critical {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Progress {
+    /// Prints every step and log line. `verbose` also lets the tools a deploy runs print.
+    pub fn plain(verbose: bool) -> Self {
+        Self {
+            view: None,
+            verbose,
+            aborted: Arc::default(),
+            critical: Arc::default(),
+        }
+    }
+
+    /// Asks the deploy to stop at the next [`Progress::check`].
+    pub fn abort(&self) {
+        if !self.aborted.swap(true, Ordering::SeqCst) {
+            self.log(ABORTING);
+        }
+    }
+
+    pub fn aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    /// Fails if the deploy was asked to stop, for it to call before each step it can stop at.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.aborted() {
+            bail!("interrupted");
+        }
+        Ok(())
+    }
+
+    /// Starts a section the deploy has to see through, however it goes.
+    pub fn critical(&self) -> Critical {
+        self.critical.fetch_add(1, Ordering::SeqCst);
+        Critical(self.critical.clone())
+    }
+
+    fn send(&self, event: Event) {
+        match &self.view {
+            // a closed view has already printed what it got, the rest is printed instead
+            Some(view) => match view.lock().unwrap().send(event) {
+                Ok(()) => {}
+                Err(mpsc::SendError(event)) => Self::print(event),
+            },
+            None => Self::print(event),
+        }
+    }
+
+    fn print(event: Event) {
+        match event {
+            Event::Step(name, _) => println!("{name}"),
+            Event::Tick => {}
+            Event::Log(line) => println!("\t{line}"),
+        }
+    }
+
+    pub fn step(&self, name: impl Into<String>) {
+        self.send(Event::Step(name.into(), None));
+    }
+
+    /// A step going through `total` objects, [`Progress::tick`] once for each.
+    pub fn counted(&self, name: impl Into<String>, total: usize) {
+        self.send(Event::Step(name.into(), Some(total)));
+    }
+
+    pub fn tick(&self) {
+        self.send(Event::Tick);
+    }
+
+    pub fn log(&self, line: impl Into<String>) {
+        self.send(Event::Log(line.into()));
+    }
+
+    pub fn verbose(&self) -> bool {
+        self.verbose
+    }
+
+    /// Where the tools a deploy runs print to, the terminal only when verbose.
+    pub fn stdio(&self) -> Stdio {
+        if self.verbose {
+            Stdio::inherit()
+        } else {
+            Stdio::piped()
+        }
+    }
+
+    /// Runs a tool to its end, returning what it printed to stdout.
+    pub fn run(&self, command: &mut Command) -> anyhow::Result<String> {
+        if self.critical.load(Ordering::SeqCst) > 0 {
+            // the terminal sends ctrl-c to its foreground process group only
+            command.process_group(0);
+        }
+        let output = command
+            .stderr(self.stdio())
+            .output()
+            .with_context(|| format!("failed to call {command:?}"))?;
+        if !output.status.success() {
+            bail!(
+                "{command:?} exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+struct Step {
+    name: String,
+    total: Option<usize>,
+    done: usize,
+    started: Instant,
+    took: Option<Duration>,
+}
+
+#[derive(Default)]
+struct State {
+    steps: Vec<Step>,
+    log: Vec<String>,
+    /// Every step and log line as [`Progress::plain`] would have printed them.
+    printed: Vec<String>,
+}
+
+impl State {
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Step(name, total) => {
+                self.finish();
+                self.printed.push(name.clone());
+                self.steps.push(Step {
+                    name,
+                    total,
+                    done: 0,
+                    started: Instant::now(),
+                    took: None,
+                });
+            }
+            Event::Tick => {
+                if let Some(step) = self.steps.last_mut() {
+                    step.done += 1;
+                }
+            }
+            Event::Log(line) => {
+                self.printed.push(format!("\t{line}"));
+                self.log.push(line);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(step) = self.steps.last_mut() {
+            step.took.get_or_insert_with(|| step.started.elapsed());
+        }
+    }
+}
+
+/// The live view, drawn on its own thread while the deploy runs.
+pub struct View {
+    thread: JoinHandle<io::Result<Vec<String>>>,
+}
+
+impl View {
+    /// Starts the view if stdout is a terminal, otherwise the deploy prints as it goes.
+    pub fn start() -> io::Result<(Progress, Option<View>)> {
+        if !io::stdout().is_tty() {
+            return Ok((Progress::plain(false), None));
+        }
+        let (tx, rx) = mpsc::channel();
+        let progress = Progress {
+            view: Some(Arc::new(Mutex::new(tx))),
+            verbose: false,
+            aborted: Arc::default(),
+            critical: Arc::default(),
+        };
+        let aborted = progress.aborted.clone();
+        let thread = std::thread::spawn(move || draw_until_done(rx, aborted));
+        Ok((progress, Some(View { thread })))
+    }
+
+    /// Waits for every [`Progress`] of the view to be dropped, closes it and prints its log.
+    pub fn finish(self) -> anyhow::Result<()> {
+        let printed = self
+            .thread
+            .join()
+            .map_err(|_| anyhow!("the progress view panicked"))??;
+        for line in printed {
+            println!("{line}");
+        }
+        Ok(())
+    }
+}
+
+fn draw_until_done(rx: Receiver<Event>, aborted: Arc<AtomicBool>) -> io::Result<Vec<String>> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let mut state = State::default();
+    let result = (|| loop {
+        terminal.draw(|f| ui(f, &state, aborted.load(Ordering::SeqCst)))?;
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(event) => {
+                state.apply(event);
+                // catch up before drawing again, uploads tick a lot
+                while let Ok(event) = rx.try_recv() {
+                    state.apply(event);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        // raw mode turns ctrl-c into a key like any other, the deploy stops once it checks
+        if event::poll(Duration::ZERO)? {
+            if let TermEvent::Key(key) = event::read()? {
+                if key.code == KeyCode::Char('c')
+                    && key.modifiers.contains(KeyModifiers::CONTROL)
+                    && !aborted.swap(true, Ordering::SeqCst)
+                {
+                    state.apply(Event::Log(ABORTING.to_string()));
+                }
+            }
+        }
+    })();
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    state.finish();
+    match result {
+        Ok(()) => Ok(state.printed),
+        Err(e) => {
+            // whatever comes after is printed as it comes, see `Progress::send`
+            for line in &state.printed {
+                println!("{line}");
+            }
+            Err(e)
+        }
+    }
+}
+
+fn ui<B: Backend>(f: &mut Frame<B>, state: &State, aborted: bool) {
+    let current = state.steps.last().filter(|s| s.took.is_none());
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(state.steps.len() as u16 + 2),
+            Constraint::Length(if current.and_then(|s| s.total).is_some() {
+                3
+            } else {
+                0
+            }),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .split(f.size());
+
+    let steps: Vec<Spans> = state
+        .steps
+        .iter()
+        .map(|step| match step.took {
+            Some(took) => Spans::from(Span::styled(
+                format!("done  {} ({:.1}s)", step.name, took.as_secs_f64()),
+                Style::default().fg(Color::Green),
+            )),
+            None => Spans::from(Span::styled(
+                format!(
+                    " ...  {} ({}s)",
+                    step.name,
+                    step.started.elapsed().as_secs()
+                ),
+                Style::default().fg(Color::Yellow),
+            )),
+        })
+        .collect();
+    f.render_widget(
+        Paragraph::new(steps).block(Block::default().title("Deploy").borders(Borders::ALL)),
+        chunks[0],
+    );
+
+    if let Some(Step {
+        total: Some(total),
+        done,
+        ..
+    }) = current
+    {
+        let ratio = if *total == 0 {
+            1.0
+        } else {
+            (*done as f64 / *total as f64).min(1.0)
+        };
+        f.render_widget(
+            Gauge::default()
+                .block(Block::default().borders(Borders::ALL))
+                .gauge_style(Style::default().fg(Color::Cyan))
+                .ratio(ratio)
+                .label(format!("{done} of {total}")),
+            chunks[1],
+        );
+    }
+
+    // only the newest lines fit
+    let fits = chunks[2].height.saturating_sub(2) as usize;
+    let log: Vec<Spans> = state
+        .log
+        .iter()
+        .skip(state.log.len().saturating_sub(fits))
+        .map(|line| Spans::from(line.as_str()))
+        .collect();
+    f.render_widget(
+        Paragraph::new(log).block(Block::default().title("Log").borders(Borders::ALL)),
+        chunks[2],
+    );
+
+    let hint = if aborted {
+        ABORTING
+    } else {
+        "press ctrl-c to abort"
+    };
+    f.render_widget(Paragraph::new(hint), chunks[3]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state() {
+        let mut state = State::default();
+        state.apply(Event::Step("uploading objects".to_string(), Some(2)));
+        state.apply(Event::Tick);
+        state.apply(Event::Log("uploaded console.wasm".to_string()));
+        assert_eq!(state.steps[0].done, 1);
+        assert!(state.steps[0].took.is_none());
+
+        state.apply(Event::Step("switching index.html".to_string(), None));
+        assert!(state.steps[0].took.is_some());
+        assert_eq!(
+            state.printed,
+            vec![
+                "uploading objects",
+                "\tuploaded console.wasm",
+                "switching index.html"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_abort() {
+        let progress = Progress::plain(false);
+        assert!(progress.check().is_ok());
+        {
+            let _critical = progress.critical();
+            progress.clone().abort();
+            assert_eq!(progress.critical.load(Ordering::SeqCst), 1);
+        }
+        assert_eq!(progress.critical.load(Ordering::SeqCst), 0);
+        assert!(progress.aborted());
+        assert!(progress.check().is_err());
+    }
+}
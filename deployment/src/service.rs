@@ -0,0 +1,307 @@
+//! Binaries of the workspace running as systemd services on a host, like sentinel.
+//!
+//! `deploy service` cross compiles the binary for the host and uploads it next to the older ones as
+//! `releases/<name>-<version>`, the version being a prefix of its sha256. Only once the upload's
+//! checksum matches and it runs with `--version` on the host, the service's `path`, a symlink, is
+//! pointed at it and the unit restarted. If the service's `health` URL doesn't answer after that,
+//! `path` goes back to the binary that ran before and the unit is restarted on it. Once `path` is
+//! switched, ctrl-c waits for all of that to be through.
+
+use crate::config::Service;
+use crate::progress::Progress;
+use crate::quote;
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// How long a restarted service gets to answer on its `health` URL.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait between asking.
+const HEALTH_POLL: Duration = Duration::from_secs(2);
+
+/// Where `cross` puts the release build of the service's binary.
+fn binary(service: &Service) -> PathBuf {
+    service
+        .workspace
+        .join("target")
+        .join(&service.triple)
+        .join("release")
+        .join(&service.package)
+}
+
+/// Where every uploaded binary is kept, next to `path`.
+fn releases_dir(service: &Service) -> String {
+    match service.path.rsplit_once('/') {
+        Some((parent, _)) => format!("{parent}/releases"),
+        None => "releases".to_string(),
+    }
+}
+
+fn versioned(service: &Service, version: &str) -> String {
+    let name = service.path.rsplit('/').next().unwrap_or(&service.path);
+    format!("{}/{name}-{version}", releases_dir(service))
+}
+
+fn ssh(service: &Service, script: &str, progress: &Progress) -> Result<String> {
+    progress.run(Command::new("ssh").arg(&service.host).arg(script))
+}
+
+fn systemctl(service: &Service, action: &str, progress: &Progress) -> Result<String> {
+    ssh(
+        service,
+        &format!("systemctl {action} {}", quote(&service.unit)),
+        progress,
+    )
+}
+
+/// The binary `path` points at now, `None` if there is none. One that isn't in the releases yet,
+/// from before there were any, is copied there first, to have something to roll back to.
+fn live(service: &Service, progress: &Progress) -> Result<Option<String>> {
+    let path = quote(&service.path);
+    let initial = quote(&versioned(service, "initial"));
+    let live = ssh(
+        service,
+        &format!(
+            "if [ -L {path} ]; then readlink -f {path}; \
+             elif [ -f {path} ]; then cp -p {path} {initial} && echo {initial}; fi"
+        ),
+        progress,
+    )?;
+    let live = live.trim();
+    Ok((!live.is_empty()).then(|| live.to_string()))
+}
+
+/// Points `path` at `binary` in one go.
+fn switch(service: &Service, binary: &str, progress: &Progress) -> Result<()> {
+    let (binary, path) = (quote(binary), quote(&service.path));
+    ssh(
+        service,
+        &format!("ln -sfn {binary} {path}.new && mv -T {path}.new {path}"),
+        progress,
+    )?;
+    Ok(())
+}
+
+/// Whether the service answers on its `health` URL within [`HEALTH_TIMEOUT`].
+fn healthy(service: &Service, progress: &Progress) -> bool {
+    let started = Instant::now();
+    loop {
+        let probe = progress.run(
+            Command::new("curl")
+                .arg("--fail")
+                .arg("--silent")
+                .arg("--show-error")
+                .arg("--max-time")
+                .arg("5")
+                .arg(&service.health),
+        );
+        match probe {
+            Ok(_) => return true,
+            Err(e) if started.elapsed() > HEALTH_TIMEOUT => {
+                progress.log(format!("{e:#}"));
+                return false;
+            }
+            Err(_) => std::thread::sleep(HEALTH_POLL),
+        }
+    }
+}
+
+/// The binaries in `listing`, newest first, to delete so only the `keep` newest are left. The
+/// ones in `kept` never are.
+fn outdated(listing: &str, keep: usize, kept: &[&str]) -> Vec<String> {
+    listing
+        .lines()
+        .filter(|name| !name.is_empty())
+        .skip(keep)
+        .filter(|name| !kept.contains(name))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Deletes all but the `keep_releases` newest binaries, never the live one or the one before it.
+fn clean_up(service: &Service, kept: &[&str], progress: &Progress) -> Result<()> {
+    let dir = releases_dir(service);
+    let listing = ssh(service, &format!("ls -1t {}", quote(&dir)), progress)?;
+    let kept: Vec<&str> = kept
+        .iter()
+        .map(|binary| binary.rsplit('/').next().unwrap_or(binary))
+        .collect();
+    let outdated = outdated(&listing, service.keep_releases, &kept);
+    for name in &outdated {
+        progress.log(format!("deleting {dir}/{name}"));
+        ssh(
+            service,
+            &format!("rm -f {}", quote(&format!("{dir}/{name}"))),
+            progress,
+        )?;
+    }
+    progress.log(format!(
+        "kept the {} newest binaries",
+        service.keep_releases
+    ));
+    Ok(())
+}
+
+/// Builds the service's binary and switches the service over to it, rolling back if it isn't
+/// healthy after. With `dry_run`, it only builds and tells what it would upload.
+pub fn deploy(service: &Service, dry_run: bool, progress: &Progress) -> Result<()> {
+    progress.check()?;
+    progress.step(format!(
+        "building {} for {}",
+        service.package, service.triple
+    ));
+    progress.run(
+        Command::new("cross")
+            .arg("build")
+            .arg("--release")
+            .arg("-p")
+            .arg(&service.package)
+            .arg("--target")
+            .arg(&service.triple)
+            .current_dir(&service.workspace)
+            .stdout(progress.stdio()),
+    )?;
+    let binary = binary(service);
+    let body =
+        std::fs::read(&binary).with_context(|| format!("cross built no {}", binary.display()))?;
+    let hash = format!("{:x}", Sha256::digest(&body));
+    let version = &hash[..12];
+    let versioned = versioned(service, version);
+    progress.log(format!(
+        "built {} ({} bytes), version {version}",
+        binary.display(),
+        body.len()
+    ));
+
+    if dry_run {
+        progress.step("dry run");
+        progress.log(format!("would upload it as {}:{versioned}", service.host));
+        progress.log(format!(
+            "would point {} at it, restart {} and roll back unless {} answers",
+            service.path, service.unit, service.health
+        ));
+        return Ok(());
+    }
+
+    progress.check()?;
+    progress.step(format!("uploading {versioned}"));
+    let staged = format!("{versioned}.new");
+    ssh(
+        service,
+        &format!("mkdir -p {}", quote(&releases_dir(service))),
+        progress,
+    )?;
+    progress.run(
+        Command::new("scp")
+            .arg(&binary)
+            .arg(format!("{}:{staged}", service.host)),
+    )?;
+
+    progress.check()?;
+    progress.step("verifying the upload");
+    let uploaded = ssh(service, &format!("sha256sum {}", quote(&staged)), progress)?;
+    if uploaded.split_whitespace().next() != Some(hash.as_str()) {
+        bail!(
+            "{staged} isn't what was uploaded, its sha256 is `{}`",
+            uploaded.trim()
+        );
+    }
+    progress.log("the checksum matches");
+    let printed = ssh(
+        service,
+        &format!("chmod +x {0} && {0} --version", quote(&staged)),
+        progress,
+    )
+    .with_context(|| format!("{staged} doesn't run on {}", service.host))?;
+    if !printed.starts_with(&service.package) {
+        bail!("{staged} --version says `{}`", printed.trim());
+    }
+    progress.log(format!("it runs, as {}", printed.trim()));
+    ssh(
+        service,
+        &format!("mv -f {} {}", quote(&staged), quote(&versioned)),
+        progress,
+    )?;
+
+    progress.check()?;
+    progress.step(format!("switching {} to {version}", service.path));
+    let _critical = progress.critical();
+    let previous = live(service, progress)?;
+    if previous.as_deref() == Some(versioned.as_str()) {
+        progress.log(format!("{version} is live already"));
+        return Ok(());
+    }
+    switch(service, &versioned, progress)?;
+
+    progress.step(format!("restarting {}", service.unit));
+    let restarted = systemctl(service, "restart", progress);
+    if let Err(e) = &restarted {
+        progress.log(format!("{e:#}"));
+    }
+
+    progress.step(format!("checking {}", service.health));
+    if restarted.is_ok() && healthy(service, progress) {
+        progress.log(format!("{} is healthy on {version}", service.unit));
+        progress.step("cleaning up old binaries");
+        let mut kept = vec![versioned.as_str()];
+        kept.extend(previous.as_deref());
+        return clean_up(service, &kept, progress);
+    }
+
+    let previous = match previous {
+        Some(previous) => previous,
+        None => bail!(
+            "{} isn't healthy on {version}, and there is no binary from before to roll back to",
+            service.unit
+        ),
+    };
+    progress.step(format!("rolling back to {previous}"));
+    switch(service, &previous, progress)?;
+    systemctl(service, "restart", progress)?;
+    if healthy(service, progress) {
+        progress.log(format!("{} is healthy again", service.unit));
+    } else {
+        progress.log(format!(
+            "{} isn't healthy on {previous} either",
+            service.unit
+        ));
+    }
+    bail!(
+        "{} wasn't healthy on {version}, rolled back to {previous}",
+        service.unit
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_versioned() {
+        let config = Config::parse(include_str!("../deploy.toml")).unwrap();
+        let sentinel = &config.services["sentinel"];
+        assert_eq!(releases_dir(sentinel), "/opt/sentinel/releases");
+        assert_eq!(
+            versioned(sentinel, "0123456789ab"),
+            "/opt/sentinel/releases/sentinel-0123456789ab"
+        );
+    }
+
+    #[test]
+    fn test_outdated() {
+        let listing = "sentinel-c\nsentinel-b\nsentinel-a\nsentinel-initial\n";
+        assert_eq!(
+            outdated(listing, 2, &["sentinel-c", "sentinel-b"]),
+            vec!["sentinel-a".to_string(), "sentinel-initial".to_string()]
+        );
+        // the live and previous binaries stay, whatever their times say
+        assert_eq!(
+            outdated(listing, 1, &["sentinel-c", "sentinel-initial"]),
+            vec!["sentinel-b".to_string(), "sentinel-a".to_string()]
+        );
+    }
+}
@@ -1,193 +1,794 @@
-use anyhow::Result;
-use aws_sdk_s3::model::{Delete, Object, ObjectIdentifier};
-use aws_sdk_s3::types::ByteStream;
-use aws_sdk_s3::Client;
-use std::path::Path;
-
-use futures::FutureExt;
-use tokio::fs::File;
+use anyhow::{bail, Context, Result};
+use futures::TryStreamExt;
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use tokio::process::Command;
 
+use crate::config::{Cdn, Site};
+use crate::progress::Progress;
+use crate::target::{DeployTarget, Meta, Stored};
 use crate::StreamExt;
 use walkdir::WalkDir;
 use crate::path_util::CutBase;
 
-async fn list_old_assets(client: &Client, cf_prefix: &str, yew_crate_name: &str) -> Vec<String> {
-    let objects = client
-        .list_objects_v2()
-        .bucket("siyuanyan")
-        .prefix(format!("website-assets/{cf_prefix}"))
-        .send()
-        .await
-        .unwrap();
-
-    let keys = objects
-        .contents
-        .unwrap_or_default()
+async fn list_old_assets(
+    assets: &dyn DeployTarget,
+    cf_prefix: &str,
+    yew_crate_name: &str,
+) -> Result<Vec<Listed>> {
+    let stored = assets.list(&format!("{cf_prefix}/")).await?;
+    Ok(stored
         .into_iter()
-        .filter_map(|Object { key, .. }| key)
-        .map(|k| (k, client.clone()));
+        .filter(|Stored { metadata, .. }| {
+            metadata.get("yew-crate").map(String::as_str) == Some(yew_crate_name)
+        })
+        .map(|Stored { key, size, etag, metadata }| Listed {
+            key,
+            size,
+            md5: etag.unwrap_or_default().trim_matches('"').to_string(),
+            sha256: metadata.get(CONTENT_HASH).cloned(),
+        })
+        .collect())
+}
 
-    let keys = futures::stream::iter(keys);
+/// How many objects are uploaded or copied at once.
+pub(crate) const UPLOAD_CONCURRENCY: usize = 8;
 
-    keys.filter_map(|(k, c)| async move {
-        let params = c
-            .head_object()
-            .bucket("siyuanyan")
-            .key(&k)
-            .send()
-            .await
-            .unwrap();
-        if let Some(remote_crate_name) = params.metadata.unwrap_or_default().get("yew-crate") {
-            if remote_crate_name == yew_crate_name {
-                Some(k)
-            } else {
-                None
+/// The metadata holding the sha256 of an object's body, to tell whether it changed.
+const CONTENT_HASH: &str = "content-sha256";
+
+/// Each release's [`Release`] record, next to its assets.
+const RECORD: &str = "release.json";
+
+/// For what's under a release prefix, which is never written to again once uploaded.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// For `index.html`, which a deploy or rollback replaces, and the release record.
+const SHORT_LIVED: &str = "public, max-age=60";
+
+/// What was deployed in a release, so it can be listed and rolled back to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Release {
+    pub id: String,
+    /// The commit it was built from, `-dirty` if there were uncommitted changes.
+    pub commit: String,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    /// Relative to the release's prefix, `index.html` included.
+    pub files: Vec<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Every release of a site gets its own prefix under this one.
+fn releases_prefix(cf_prefix: &str) -> String {
+    format!("{cf_prefix}/releases/")
+}
+
+/// The release `key` belongs to, `None` for assets uploaded before there were releases.
+fn release_of<'a>(key: &'a str, cf_prefix: &str) -> Option<&'a str> {
+    let rest = key.strip_prefix(&releases_prefix(cf_prefix))?;
+    rest.split_once('/').map(|(release, _)| release)
+}
+
+/// The release `index_html` loads its assets from.
+fn release_in<'a>(index_html: &'a str, site: &Site) -> Option<&'a str> {
+    let marker = format!("{}/{}", site.assets_url, releases_prefix(&site.prefix));
+    let rest = &index_html[index_html.find(&marker)? + marker.len()..];
+    rest.split_once('/').map(|(release, _)| release)
+}
+
+async fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).current_dir(dir).output().await.ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The commit `dir` is at, `unknown` outside of git.
+async fn commit(dir: &Path) -> String {
+    let (head, status) = tokio::join!(
+        git(dir, &["rev-parse", "--short", "HEAD"]),
+        git(dir, &["status", "--porcelain"])
+    );
+    match (head, status) {
+        (Some(head), Some(status)) if !status.is_empty() => format!("{head}-dirty"),
+        (Some(head), _) => head,
+        (None, _) => "unknown".to_string(),
+    }
+}
+
+fn ago(timestamp: u64) -> String {
+    let secs = now().saturating_sub(timestamp);
+    match secs {
+        0..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// The keys to delete so only the `keep` newest releases are left. `current` is always kept, and
+/// assets from before there were releases never are.
+fn outdated(keys: &[String], cf_prefix: &str, current: &str, keep: usize) -> Vec<String> {
+    let mut releases: Vec<u64> = keys
+        .iter()
+        .filter_map(|k| release_of(k, cf_prefix)?.parse().ok())
+        .collect();
+    releases.sort_unstable();
+    releases.dedup();
+    let kept: Vec<String> = releases
+        .iter()
+        .rev()
+        .take(keep)
+        .map(u64::to_string)
+        .collect();
+    keys.iter()
+        .filter(|k| match release_of(k, cf_prefix) {
+            Some(release) => release != current && !kept.iter().any(|r| r == release),
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// A file of the build, or a compressed variant of one, and what is uploaded for it.
+struct Asset {
+    /// Relative to the release's prefix, with the variant's suffix.
+    key_tail: String,
+    body: PathBuf,
+}
+
+/// Every file as is, and the compressible ones in each encoding too.
+fn assets(yew_crate: &Path) -> Vec<Asset> {
+    let release_dir = yew_crate.join("dist/release");
+    let mut assets = Vec::new();
+    for entry in WalkDir::new(release_dir.join("uncompressed"))
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+    {
+        let path = entry.path();
+        let cc = path.cut_base("uncompressed");
+        let key_tail = cc.strip_prefix("uncompressed/").unwrap().to_string();
+        // index.html only goes along for rollbacks, it's served from the index target
+        if key_tail != "index.html" && crate::compressible(path) {
+            for (_, suffix, dir) in crate::ENCODINGS {
+                assets.push(Asset {
+                    key_tail: format!("{key_tail}{suffix}"),
+                    body: release_dir.join(dir).join(&key_tail),
+                });
             }
-        } else {
-            None
         }
-    })
-    .collect()
-    .await
+        assets.push(Asset {
+            key_tail,
+            body: path.into(),
+        });
+    }
+    assets
+}
+
+/// An object as it is, or would be, in the bucket.
+#[derive(Debug, PartialEq)]
+struct Listed {
+    key: String,
+    size: u64,
+    /// S3 uses it as the ETag of objects uploaded in one go.
+    md5: String,
+    /// Only objects uploaded since the hash is stored have it.
+    sha256: Option<String>,
+}
+
+impl Listed {
+    async fn hash(asset: &Asset) -> Result<Self> {
+        let bytes = tokio::fs::read(&asset.body).await?;
+        Ok(Self {
+            key: asset.key_tail.clone(),
+            size: bytes.len() as u64,
+            md5: format!("{:x}", Md5::digest(&bytes)),
+            sha256: Some(format!("{:x}", Sha256::digest(&bytes))),
+        })
+    }
+
+    /// Compares by the stored sha256, or by the ETag for objects that don't have one.
+    fn same(&self, local: &Listed) -> bool {
+        match &self.sha256 {
+            Some(sha256) => local.sha256.as_ref() == Some(sha256),
+            None => self.md5 == local.md5,
+        }
+    }
+}
+
+/// What a deploy does to the bucket, compared to the live release.
+#[derive(Debug, Default, PartialEq)]
+struct Plan {
+    /// Not in the live release, uploaded.
+    added: Vec<Listed>,
+    /// In the live release but different, uploaded, with the live one's size.
+    replaced: Vec<(u64, Listed)>,
+    /// Copied from the live release within the bucket.
+    unchanged: Vec<Listed>,
+    /// Cleaned up after the switch.
+    deleted: Vec<Listed>,
+}
+
+/// `local` is keyed relative to the release's prefix, `remote` by the full key.
+fn plan(
+    local: Vec<Listed>,
+    remote: Vec<Listed>,
+    cf_prefix: &str,
+    live: Option<&str>,
+    release: &str,
+    keep: usize,
+) -> Plan {
+    let prefix = releases_prefix(cf_prefix);
+    let mut keys: Vec<String> = remote.iter().map(|r| r.key.clone()).collect();
+    // the cleanup happens once the release is uploaded
+    keys.extend(local.iter().map(|l| format!("{prefix}{release}/{}", l.key)));
+    let outdated = outdated(&keys, cf_prefix, release, keep);
+
+    let mut plan = Plan::default();
+    for asset in local {
+        let live_key = live.map(|live| format!("{prefix}{live}/{}", asset.key));
+        match remote.iter().find(|r| Some(&r.key) == live_key.as_ref()) {
+            None => plan.added.push(asset),
+            Some(r) if r.same(&asset) => plan.unchanged.push(asset),
+            Some(r) => plan.replaced.push((r.size, asset)),
+        }
+    }
+    plan.deleted = remote
+        .into_iter()
+        .filter(|r| outdated.contains(&r.key))
+        .collect();
+    plan
+}
+
+fn print_plan(plan: &Plan, cf_prefix: &str, live: Option<&str>, release: &str, keep: usize) {
+    match live {
+        Some(live) => println!("dry run, compared to the live release {live}:"),
+        None => println!("dry run, no release is live:"),
+    }
+    println!("\twould put in {}{release}/", releases_prefix(cf_prefix));
+    for Listed { key, size, .. } in &plan.added {
+        println!("\t\t+ {key} ({size} bytes, new)");
+    }
+    for (old, Listed { key, size, .. }) in &plan.replaced {
+        println!("\t\t~ {key} ({old} -> {size} bytes)");
+    }
+    for Listed { key, size, .. } in &plan.unchanged {
+        println!("\t\t= {key} ({size} bytes, copied from the live release)");
+    }
+    println!("\twould switch the live index.html to release {release}");
+    println!("\twould delete, keeping the {keep} newest releases");
+    for Listed { key, size, .. } in &plan.deleted {
+        println!("\t\t- {key} ({size} bytes)");
+    }
+    let uploaded: u64 = plan
+        .added
+        .iter()
+        .chain(plan.replaced.iter().map(|(_, l)| l))
+        .map(|l| l.size)
+        .sum();
+    let deleted: u64 = plan.deleted.iter().map(|l| l.size).sum();
+    println!("{uploaded} bytes would be uploaded and {deleted} bytes deleted");
+}
+
+/// The `Content-Type` of a file, by its extension.
+fn content_type(path: &str) -> &'static str {
+    let extension = match path.rsplit_once('.') {
+        Some((_, extension)) => extension.to_ascii_lowercase(),
+        None => String::new(),
+    };
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "js" | "mjs" => "text/javascript",
+        "css" => "text/css; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "wasm" => "application/wasm",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// How an asset, or a compressed variant of one, is served.
+///
+/// Trunk hashes the names of what it builds, and every release has a prefix of its own anyway, so
+/// all of it can be cached for good, except what's replaced in place.
+fn meta(key_tail: &str, metadata: HashMap<String, String>) -> Meta {
+    let (original, content_encoding) = crate::ENCODINGS
+        .iter()
+        .find_map(|(encoding, suffix, _)| Some((key_tail.strip_suffix(suffix)?, Some(*encoding))))
+        .unwrap_or((key_tail, None));
+    let cache_control = match original {
+        "index.html" | RECORD => SHORT_LIVED,
+        _ => IMMUTABLE,
+    };
+    Meta {
+        content_type: Some(content_type(original).to_string()),
+        content_encoding: content_encoding.map(str::to_string),
+        cache_control: Some(cache_control.to_string()),
+        metadata,
+    }
+}
+
+/// What about how an object is `served` isn't what was `expected`.
+fn mismatches(expected: &Meta, served: &Meta) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let mut check = |what: &str, expected: Option<&String>, served: Option<&String>| {
+        if expected != served {
+            mismatches.push(format!("{what} is {served:?} instead of {expected:?}"));
+        }
+    };
+    check("Content-Type", expected.content_type.as_ref(), served.content_type.as_ref());
+    check(
+        "Content-Encoding",
+        expected.content_encoding.as_ref(),
+        served.content_encoding.as_ref(),
+    );
+    check("Cache-Control", expected.cache_control.as_ref(), served.cache_control.as_ref());
+    for (key, value) in &expected.metadata {
+        check(key, Some(value), served.metadata.get(key));
+    }
+    mismatches
+}
+
+/// HEADs every key on `target` and fails if any isn't served the way it was put.
+async fn verify(
+    target: &dyn DeployTarget,
+    expected: &[(String, Meta)],
+    progress: &Progress,
+) -> Result<()> {
+    let heads = expected.iter().map(|(key, meta)| async move {
+        let served = target.head(key).await?;
+        progress.tick();
+        Ok::<_, anyhow::Error>((key, mismatches(meta, &served)))
+    });
+    let checked: Vec<_> = futures::stream::iter(heads)
+        .buffer_unordered(UPLOAD_CONCURRENCY)
+        .try_collect()
+        .await?;
+    let mut wrong = 0;
+    for (key, mismatches) in checked {
+        if !mismatches.is_empty() {
+            wrong += 1;
+        }
+        for mismatch in mismatches {
+            progress.log(format!("{key}: {mismatch}"));
+        }
+    }
+    if wrong > 0 {
+        bail!("{wrong} of {} objects aren't served the way they were uploaded", expected.len());
+    }
+    Ok(())
+}
+
+/// Makes `index_html` the live one, in one go.
+async fn switch(index: &dyn DeployTarget, index_html: Vec<u8>, progress: &Progress) -> Result<()> {
+    let meta = meta("index.html", HashMap::new());
+    index.put("index.html", index_html, &meta).await?;
+    verify(index, &[("index.html".to_string(), meta)], progress).await
+}
+
+/// Where the CDN serves `index.html`, the directory included.
+fn index_paths(cdn: &Cdn) -> Vec<String> {
+    vec![cdn.root.clone(), format!("{}index.html", cdn.root)]
+}
+
+/// Has the site's CDN, if it has one, forget the `index.html` that was just replaced. Everything
+/// else it serves is under a release prefix, and never changes.
+async fn invalidate(site: &Site, progress: &Progress) -> Result<()> {
+    let cdn = match &site.cdn {
+        Some(cdn) => cdn,
+        None => return Ok(()),
+    };
+    progress.step("invalidating index.html on the CDN");
+    let distribution = cdn.distribution.open().await?;
+    let id = distribution.invalidate(&index_paths(cdn)).await?;
+    progress.log(format!("created invalidation {id}"));
+    if cdn.wait {
+        distribution.wait(&id).await?;
+        progress.log(format!("invalidation {id} completed"));
+    }
+    Ok(())
 }
 
-/// `cf_prefix`: no starting slash, no ending slash
-pub async fn deploy<P: AsRef<Path>>(cf_prefix: &str, yew_crate: P) -> Result<()> {
-    dotenv::dotenv().ok();
+/// Every recorded release of the site, newest first.
+pub async fn releases(assets: &dyn DeployTarget, cf_prefix: &str) -> Result<Vec<Release>> {
+    let records = assets
+        .list(&releases_prefix(cf_prefix))
+        .await?
+        .into_iter()
+        .map(|stored| stored.key)
+        .filter(|k| k.ends_with(&format!("/{RECORD}")) && release_of(k, cf_prefix).is_some());
 
-    let yew_crate_name = yew_crate.as_ref().file_name().unwrap().to_string_lossy();
+    let mut releases = Vec::new();
+    for key in records {
+        releases.push(serde_json::from_slice::<Release>(&assets.get(&key).await?)?);
+    }
+    releases.sort_unstable_by_key(|r| std::cmp::Reverse(r.timestamp));
+    Ok(releases)
+}
 
-    let shared_config = aws_config::from_env().region("ap-east-1").load().await;
+/// The release the live `index.html` belongs to, `None` if there is none.
+async fn live_release(site: &Site, index: &dyn DeployTarget) -> Option<String> {
+    let index_html = index.get("index.html").await.ok()?;
+    release_in(&String::from_utf8_lossy(&index_html), site).map(str::to_string)
+}
 
-    let client = Client::new(&shared_config);
+/// Prints every recorded release of the site, newest first.
+pub async fn list_releases(site: &Site) -> Result<()> {
+    let (assets, index) = tokio::try_join!(site.assets.open(), site.index.open())?;
+    let (releases, live) = tokio::join!(
+        releases(assets.as_ref(), &site.prefix),
+        live_release(site, index.as_ref())
+    );
+    for release in releases? {
+        let Release { id, commit, timestamp, files } = &release;
+        let marker = if live.as_deref() == Some(id.as_str()) { " (live)" } else { "" };
+        println!("{id}\t{commit}\t{}\t{} files{marker}", ago(*timestamp), files.len());
+    }
+    Ok(())
+}
 
-    let trunk = Command::new("trunk")
+/// Makes an older release of the site live again, its assets are still there.
+pub async fn rollback(site: &Site, release: &str, progress: &Progress) -> Result<()> {
+    if release.is_empty() || !release.chars().all(|c| c.is_ascii_digit()) {
+        anyhow::bail!("`{release}` is not a release, see `deploy site list-releases`");
+    }
+    let (assets, index) = tokio::try_join!(site.assets.open(), site.index.open())?;
+    let prefix = format!("{}{release}/", releases_prefix(&site.prefix));
+    let record: Release = serde_json::from_slice(
+        &assets
+            .get(&format!("{prefix}{RECORD}"))
+            .await
+            .with_context(|| format!("there is no record of release {release}"))?,
+    )?;
+    let index_html = assets.get(&format!("{prefix}index.html")).await?;
+
+    progress.step(format!("switching index.html back to release {release} ({})", record.commit));
+    switch(index.as_ref(), index_html, progress).await?;
+    progress.log("index.html is live");
+    invalidate(site, progress).await
+}
+
+/// Builds the site's yew crate and deploys it without downtime.
+///
+/// The assets go to a new release prefix first, `index.html`, which points at them, replaces the
+/// live one in one go only after that, and older releases are cleaned up last. Ctrl-c stops it
+/// before any step up to the switch, never after it.
+///
+/// With `dry_run`, it only builds and prints what it would upload, switch, invalidate and delete,
+/// leaving the targets and the CDN alone.
+pub async fn deploy(site: &Site, dry_run: bool, progress: &Progress) -> Result<()> {
+    let yew_crate = &site.yew_crate;
+    let cf_prefix = site.prefix.as_str();
+    let yew_crate_name = yew_crate.file_name().unwrap().to_string_lossy();
+    let timestamp = now();
+    let release = timestamp.to_string();
+    let keep = site.keep_releases;
+
+    let (assets_target, index_target) = tokio::try_join!(site.assets.open(), site.index.open())?;
+    let (assets_target, index_target) = (assets_target.as_ref(), index_target.as_ref());
+
+    progress.check()?;
+    progress.step(format!("building release {release}"));
+    let trunk_build_output = Command::new("trunk")
         .arg("--config")
         .arg("Trunk-release.toml")
         .arg("build")
         .arg("--release")
-        .current_dir(yew_crate.as_ref())
+        .arg("--public-url")
+        .arg(format!("{}/{}{release}/", site.assets_url, releases_prefix(cf_prefix)))
+        .current_dir(yew_crate)
+        .stdout(progress.stdio())
+        .stderr(progress.stdio())
         .output()
-        .then(|x| async {
-            let x = x.expect("failed to call trunk build");
-            println!("\tfinished trunk build");
-            x
-        });
+        .await
+        .expect("failed to call trunk build");
+
+    if !trunk_build_output.status.success() {
+        anyhow::bail!(
+            "trunk build returned nonzero exit code, here's the stderr:\n{}",
+            String::from_utf8_lossy(&trunk_build_output.stderr)
+        );
+    }
+    progress.log("finished trunk build");
 
-    let compression = crate::gzip_release_dir(yew_crate.as_ref().join("dist/release/uncompressed"));
+    for (_, _, dir) in crate::ENCODINGS {
+        tokio::fs::remove_dir_all(yew_crate.join("dist/release").join(dir)).await.ok();
+    }
+    progress.check()?;
+    progress.step("compressing");
+    let compression = crate::compress_release_dir(
+        yew_crate.join("dist/release/uncompressed"),
+        site.compression,
+    );
+    for line in compression.await.lines() {
+        progress.log(line.trim_start());
+    }
 
-    println!("building distribution and listing old assets from the bucket");
-    let (trunk_build_output, old_objects) = tokio::join!(
-        trunk,
-        list_old_assets(&client, cf_prefix, &yew_crate_name).then(|x| async {
-            println!("\tfinished listing old objects");
-            x
-        }),
+    progress.check()?;
+    progress.step("comparing to the live release");
+    let assets = assets(yew_crate);
+    let local = futures::future::try_join_all(assets.iter().map(Listed::hash));
+    let (local, remote, live) = tokio::join!(
+        local,
+        list_old_assets(assets_target, cf_prefix, &yew_crate_name),
+        live_release(site, index_target)
     );
+    let (local, remote) = (local?, remote?);
+    let files = local.iter().map(|l| l.key.clone()).collect();
+    let plan = plan(local, remote, cf_prefix, live.as_deref(), &release, keep);
 
-    if !trunk_build_output.status.success() {
-        eprintln!("trunk build returned nonzero exit code, here's the stderr:");
-        eprint!("{}", String::from_utf8(trunk_build_output.stderr).unwrap());
-        anyhow::bail!("terminated");
+    if dry_run {
+        print_plan(&plan, cf_prefix, live.as_deref(), &release, keep);
+        if let Some(cdn) = &site.cdn {
+            println!("would invalidate {} on the CDN", index_paths(cdn).join(" "));
+        }
+        return Ok(());
     }
 
-    println!("\t\tfound {} old objects", old_objects.len());
-
-    let old_objects: Vec<_> = old_objects
+    progress.check()?;
+    progress.counted(
+        "uploading objects",
+        plan.added.len() + plan.replaced.len() + plan.unchanged.len(),
+    );
+    let prefix = releases_prefix(cf_prefix);
+    let bodies: HashMap<String, PathBuf> = assets
         .into_iter()
-        .map(|x| {
-            println!("\t\t\t{x}");
-            ObjectIdentifier::builder().set_key(Some(x)).build()
-        })
+        .map(|Asset { key_tail, body }| (key_tail, body))
         .collect();
-
-    if !old_objects.is_empty() {
-        println!("deleting old objects...");
-        client
-            .delete_objects()
-            .bucket("siyuanyan")
-            .delete(Delete::builder().set_objects(Some(old_objects)).build())
-            .send()
-            .await?;
-        println!("\told objects deleted");
-    }
-
-    let upload_to_s3 = async {
-        let mut output = "".to_string();
-        for entry in WalkDir::new(yew_crate.as_ref().join("dist/release/uncompressed"))
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
-            let file_name = path.file_name().unwrap().to_string_lossy();
-            if path.is_file() && file_name != "index.html" {
-                output.push_str("\tuploaded ");
-                output.push_str(&file_name);
-                output.push('\n');
-
-
-                let cc = path.cut_base("uncompressed");
-                let key_tail = cc.strip_prefix("uncompressed/").unwrap();
-                let path = if file_name.ends_with(".js") || file_name.ends_with(".wasm"){
-                    yew_crate.as_ref().join("dist/release/brotli/").join(&*file_name)
-                }else{
-                    path.into()
-                };
-
-                let body = ByteStream::from_file(File::open(path).await.unwrap())
-                    .await
-                    .unwrap();
-
-                let put = client
-                    .put_object()
-                    .bucket("siyuanyan")
-                    .key(format!("website-assets/{cf_prefix}/{key_tail}"))
-                    .metadata("yew-crate", yew_crate_name.clone())
-                    .body(body);
-                let put = if file_name.ends_with(".js") {
-                    put.content_type("text/javascript").content_encoding("br")
-                } else if file_name.ends_with(".wasm") {
-                    put.content_type("application/wasm").content_encoding("br")
-                } else {
-                    put
-                };
-                put.send().await.expect("failed to upload file");
+    let key_meta = |listed: &Listed| {
+        let meta = meta(
+            &listed.key,
+            HashMap::from([
+                ("yew-crate".to_string(), yew_crate_name.to_string()),
+                ("release".to_string(), release.clone()),
+                (CONTENT_HASH.to_string(), listed.sha256.clone().unwrap_or_default()),
+            ]),
+        );
+        (format!("{prefix}{release}/{}", listed.key), meta)
+    };
+    // unchanged objects are copied from the live release instead of uploaded again
+    let transfer = |listed: &Listed, copy_from: Option<String>| {
+        let key_tail = listed.key.clone();
+        let (key, meta) = key_meta(listed);
+        let body = bodies[&key_tail].clone();
+        async move {
+            match copy_from {
+                Some(source) => {
+                    assets_target.copy(&source, &key, &meta).await?;
+                    Ok::<_, anyhow::Error>(format!("copied {key_tail}, unchanged"))
+                }
+                None => {
+                    assets_target.put(&key, tokio::fs::read(body).await?, &meta).await?;
+                    Ok(format!("uploaded {key_tail}"))
+                }
             }
         }
-        output
     };
+    let transfers = plan
+        .added
+        .iter()
+        .chain(plan.replaced.iter().map(|(_, l)| l))
+        .map(|l| transfer(l, None))
+        .chain(plan.unchanged.iter().map(|l| {
+            let live = live.as_deref().expect("only the live release has unchanged objects");
+            transfer(l, Some(format!("{prefix}{live}/{}", l.key)))
+        }));
+    futures::stream::iter(transfers)
+        .buffer_unordered(UPLOAD_CONCURRENCY)
+        .try_for_each(|done| async move {
+            progress.log(done);
+            progress.tick();
+            Ok(())
+        })
+        .await?;
 
-    let scp = Command::new("scp")
-        .arg(
-            yew_crate
-                .as_ref()
-                .join("dist/release/uncompressed/index.html"),
-        )
-        .arg(format!("root@xray:/site/{cf_prefix}/index.html"))
-        .output();
-
-    let remove = tokio::fs::remove_dir_all(yew_crate.as_ref().join("dist/release/brotli"));
-
-    println!("compression and uploading objects to S3");
-    println!("uploading index.html to the backend");
-    let (s3, scp) = tokio::join!(
-        remove.then(|_| compression).then(|r| async move {
-            print!("{r}");
-            upload_to_s3.await
-        }),
-        scp
+    let record = Release {
+        id: release.clone(),
+        commit: commit(yew_crate).await,
+        timestamp,
+        files,
+    };
+    let record_meta = meta(
+        RECORD,
+        HashMap::from([
+            ("yew-crate".to_string(), yew_crate_name.to_string()),
+            ("release".to_string(), release.clone()),
+        ]),
     );
+    assets_target
+        .put(
+            &format!("{prefix}{release}/{RECORD}"),
+            serde_json::to_vec_pretty(&record)?,
+            &record_meta,
+        )
+        .await?;
+    progress.log(format!("recorded release {release} of {}", record.commit));
+
+    let mut expected: Vec<(String, Meta)> = plan
+        .added
+        .iter()
+        .chain(plan.replaced.iter().map(|(_, l)| l))
+        .chain(&plan.unchanged)
+        .map(key_meta)
+        .collect();
+    expected.push((format!("{prefix}{release}/{RECORD}"), record_meta));
+    progress.check()?;
+    progress.counted("verifying the release's headers", expected.len());
+    verify(assets_target, &expected, progress).await?;
+    progress.log(format!("all {} objects are served right", expected.len()));
 
-    print!("{s3}");
+    progress.check()?;
+    progress.step(format!("switching index.html to release {release}"));
+    let index_html = tokio::fs::read(yew_crate.join("dist/release/uncompressed/index.html")).await?;
+    switch(index_target, index_html, progress).await?;
+    progress.log("index.html is live");
+    invalidate(site, progress).await?;
 
-    let scp = scp.expect("failed to call scp");
-    if !scp.status.success() {
-        eprintln!("scp exited with nonzero code, here's the stderr");
-        eprint!("{}", String::from_utf8(scp.stderr).unwrap());
-        anyhow::bail!("terminated");
+    progress.step("cleaning up old releases");
+    let keys: Vec<String> = list_old_assets(assets_target, cf_prefix, &yew_crate_name)
+        .await?
+        .into_iter()
+        .map(|listed| listed.key)
+        .collect();
+    let outdated = outdated(&keys, cf_prefix, &release, keep);
+    for key in &outdated {
+        progress.log(format!("deleting {key}"));
     }
-    println!("\tuploaded index.html");
+    assets_target.delete(&outdated).await?;
+    progress.log(format!("kept the {keep} newest releases"));
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_release_of() {
+        assert_eq!(
+            release_of("minecraft/releases/1650000000/console.wasm", "minecraft"),
+            Some("1650000000")
+        );
+        assert_eq!(release_of("minecraft/console.wasm", "minecraft"), None);
+        assert_eq!(
+            release_of(
+                "minecraft/fov-calculator/releases/1650000000/fov.wasm",
+                "minecraft"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_release_in() {
+        let index_html = r#"<script type="module">
+            import init from 'https://assets.siyuanyan.net/minecraft/releases/1650000000/console.js';
+        </script>"#;
+        let config = Config::parse(include_str!("../deploy.toml")).unwrap();
+        assert_eq!(release_in(index_html, config.site("console").unwrap()), Some("1650000000"));
+        assert_eq!(release_in(index_html, config.site("fov-calculator").unwrap()), None);
+    }
+
+    #[test]
+    fn test_outdated() {
+        let keys: Vec<String> = [
+            "minecraft/console.wasm",
+            "minecraft/releases/1/console.wasm",
+            "minecraft/releases/2/console.wasm",
+            "minecraft/releases/3/console.wasm",
+            "minecraft/releases/3/console.js",
+        ]
+        .iter()
+        .map(|k| k.to_string())
+        .collect();
+        assert_eq!(
+            outdated(&keys, "minecraft", "3", 2),
+            vec![
+                "minecraft/console.wasm".to_string(),
+                "minecraft/releases/1/console.wasm".to_string(),
+            ]
+        );
+        // the current release survives whatever the clocks say
+        assert_eq!(outdated(&keys, "minecraft", "2", 1).len(), 2);
+    }
+
+    #[test]
+    fn test_meta() {
+        let brotli = meta("console.wasm.br", HashMap::new());
+        assert_eq!(brotli.content_type.as_deref(), Some("application/wasm"));
+        assert_eq!(brotli.content_encoding.as_deref(), Some("br"));
+        let gzip = meta("console.js.gz", HashMap::new());
+        assert_eq!(gzip.content_type.as_deref(), Some("text/javascript"));
+        assert_eq!(gzip.content_encoding.as_deref(), Some("gzip"));
+        let identity = meta("console.js", HashMap::new());
+        assert_eq!(identity.content_encoding, None);
+        assert_eq!(identity.cache_control.as_deref(), Some(IMMUTABLE));
+        let image = meta("asset/image/portrait-27-inch.jpg", HashMap::new());
+        assert_eq!(image.content_type.as_deref(), Some("image/jpeg"));
+        let index = meta("index.html", HashMap::new());
+        assert_eq!(index.cache_control.as_deref(), Some(SHORT_LIVED));
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(content_type("console.css"), "text/css; charset=utf-8");
+        assert_eq!(content_type("font/Minecraft.WOFF2"), "font/woff2");
+        assert_eq!(content_type("console.js.map"), "application/json");
+        assert_eq!(content_type("LICENSE"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let expected = meta(
+            "console.wasm.br",
+            HashMap::from([(CONTENT_HASH.to_string(), "a".to_string())]),
+        );
+        assert!(mismatches(&expected, &expected).is_empty());
+        let served = Meta {
+            content_encoding: None,
+            ..expected.clone()
+        };
+        assert_eq!(
+            mismatches(&expected, &served),
+            vec![r#"Content-Encoding is None instead of Some("br")"#.to_string()]
+        );
+        assert_eq!(mismatches(&expected, &Meta::default()).len(), 4);
+    }
+
+    #[test]
+    fn test_plan() {
+        let listed = |key: &str, size, md5: &str| Listed {
+            key: key.to_string(),
+            size,
+            md5: md5.to_string(),
+            sha256: None,
+        };
+        let local = vec![
+            listed("console.js", 10, "a"),
+            listed("console.wasm", 20, "b"),
+            listed("image.png", 30, "c"),
+        ];
+        let remote = vec![
+            listed("minecraft/console.wasm", 15, "x"),
+            listed("minecraft/releases/1/console.js", 10, "a"),
+            listed("minecraft/releases/1/console.wasm", 15, "x"),
+        ];
+        let plan = plan(local, remote, "minecraft", Some("1"), "2", 2);
+        assert_eq!(plan.added, vec![listed("image.png", 30, "c")]);
+        assert_eq!(plan.replaced, vec![(15, listed("console.wasm", 20, "b"))]);
+        assert_eq!(plan.unchanged, vec![listed("console.js", 10, "a")]);
+        assert_eq!(
+            plan.deleted,
+            vec![listed("minecraft/console.wasm", 15, "x")]
+        );
+    }
+}
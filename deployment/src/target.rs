@@ -0,0 +1,528 @@
+//! Where static sites are deployed to.
+//!
+//! A [`DeployTarget`] stores objects by key with their headers and metadata, the way S3 does.
+//! Besides [`S3`], which also covers S3 compatible stand-ins like MinIO, a [`LocalDir`] and a
+//! directory on a host over [`Ssh`] can stand in for a bucket. Those two keep each object's
+//! [`Meta`] in a `<key>.meta` file next to it.
+
+use crate::quote;
+use crate::static_deployment::UPLOAD_CONCURRENCY;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use aws_sdk_s3::model::{Delete, MetadataDirective, Object, ObjectIdentifier};
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Endpoint, Region};
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::process::Command;
+use walkdir::WalkDir;
+
+/// How an object is served, and what deployments know about it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Meta {
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub cache_control: Option<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+/// An object as listed.
+#[derive(Debug, PartialEq)]
+pub struct Stored {
+    pub key: String,
+    pub size: u64,
+    /// Only S3 has them.
+    pub etag: Option<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+#[async_trait]
+pub trait DeployTarget: Send + Sync {
+    /// Every object whose key starts with `prefix`.
+    async fn list(&self, prefix: &str) -> Result<Vec<Stored>>;
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>>;
+
+    /// How the object at `key` is served, without its body.
+    async fn head(&self, key: &str) -> Result<Meta>;
+
+    /// Replaces whatever is at `key` at once, nobody ever reads half of it.
+    async fn put(&self, key: &str, body: Vec<u8>, meta: &Meta) -> Result<()>;
+
+    /// Copies an object within the target, with `meta` instead of its own.
+    async fn copy(&self, from: &str, to: &str, meta: &Meta) -> Result<()>;
+
+    /// Deleting what isn't there is fine.
+    async fn delete(&self, keys: &[String]) -> Result<()>;
+}
+
+/// The suffix of the files holding the [`Meta`] of [`LocalDir`] and [`Ssh`] objects.
+const META: &str = ".meta";
+
+/// `root`, if not empty, with exactly one slash at the end.
+fn directory(root: &str) -> String {
+    match root.trim_end_matches('/') {
+        "" => String::new(),
+        root => format!("{root}/"),
+    }
+}
+
+/// An S3 bucket, or a prefix in one.
+pub struct S3 {
+    client: Client,
+    bucket: String,
+    /// Prepended to every key.
+    root: String,
+}
+
+impl S3 {
+    /// `endpoint` replaces AWS's, for S3 compatible stores.
+    pub async fn new(
+        bucket: &str,
+        region: &str,
+        root: &str,
+        endpoint: Option<&str>,
+    ) -> Result<Self> {
+        dotenv::dotenv().ok();
+        let shared_config = aws_config::from_env()
+            .region(Region::new(region.to_string()))
+            .load()
+            .await;
+        let mut config = aws_sdk_s3::config::Builder::from(&shared_config);
+        if let Some(endpoint) = endpoint {
+            let uri = endpoint
+                .parse()
+                .with_context(|| format!("`{endpoint}` is not an endpoint"))?;
+            config = config.endpoint_resolver(Endpoint::immutable(uri));
+        }
+        Ok(Self {
+            client: Client::from_conf(config.build()),
+            bucket: bucket.to_string(),
+            root: directory(root),
+        })
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{key}", self.root)
+    }
+}
+
+#[async_trait]
+impl DeployTarget for S3 {
+    async fn list(&self, prefix: &str) -> Result<Vec<Stored>> {
+        let mut objects = Vec::new();
+        let mut continuation = None;
+        loop {
+            let page = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(self.key(prefix))
+                .set_continuation_token(continuation)
+                .send()
+                .await?;
+            objects.extend(page.contents.unwrap_or_default());
+            continuation = page.next_continuation_token;
+            if continuation.is_none() {
+                break;
+            }
+        }
+
+        // the metadata only comes with each object on its own, a few are asked for at a time and
+        // they stay in the order S3 listed them
+        let heads = objects.into_iter().map(
+            |Object {
+                 key, size, e_tag, ..
+             }| async move {
+                let key = key.unwrap_or_default();
+                let head = self
+                    .client
+                    .head_object()
+                    .bucket(&self.bucket)
+                    .key(&key)
+                    .send()
+                    .await?;
+                Ok::<_, anyhow::Error>(Stored {
+                    key: key.strip_prefix(&self.root).unwrap_or(&key).to_string(),
+                    size: size as u64,
+                    etag: e_tag,
+                    metadata: head.metadata.unwrap_or_default(),
+                })
+            },
+        );
+        futures::stream::iter(heads)
+            .buffered(UPLOAD_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(key))
+            .send()
+            .await
+            .with_context(|| format!("failed to download {key}"))?;
+        Ok(object.body.collect().await?.into_bytes().to_vec())
+    }
+
+    async fn head(&self, key: &str) -> Result<Meta> {
+        let head = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(key))
+            .send()
+            .await
+            .with_context(|| format!("failed to head {key}"))?;
+        Ok(Meta {
+            content_type: head.content_type,
+            content_encoding: head.content_encoding,
+            cache_control: head.cache_control,
+            metadata: head.metadata.unwrap_or_default(),
+        })
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>, meta: &Meta) -> Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.key(key))
+            .set_content_type(meta.content_type.clone())
+            .set_content_encoding(meta.content_encoding.clone())
+            .set_cache_control(meta.cache_control.clone())
+            .set_metadata(Some(meta.metadata.clone()))
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn copy(&self, from: &str, to: &str, meta: &Meta) -> Result<()> {
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .copy_source(format!("{}/{}", self.bucket, self.key(from)))
+            .key(self.key(to))
+            .metadata_directive(MetadataDirective::Replace)
+            .set_content_type(meta.content_type.clone())
+            .set_content_encoding(meta.content_encoding.clone())
+            .set_cache_control(meta.cache_control.clone())
+            .set_metadata(Some(meta.metadata.clone()))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, keys: &[String]) -> Result<()> {
+        // that's as many as one request takes
+        for keys in keys.chunks(1000) {
+            let objects = keys
+                .iter()
+                .map(|k| ObjectIdentifier::builder().key(self.key(k)).build())
+                .collect();
+            self.client
+                .delete_objects()
+                .bucket(&self.bucket)
+                .delete(Delete::builder().set_objects(Some(objects)).build())
+                .send()
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// A local directory, to try deployments out.
+pub struct LocalDir {
+    pub root: PathBuf,
+}
+
+impl LocalDir {
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    /// Writes next to `path` first, then renames over it.
+    async fn replace(path: PathBuf, contents: Vec<u8>) -> Result<()> {
+        let staged = PathBuf::from(format!("{}.new", path.display()));
+        tokio::fs::write(&staged, contents).await?;
+        tokio::fs::rename(&staged, &path).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DeployTarget for LocalDir {
+    async fn list(&self, prefix: &str) -> Result<Vec<Stored>> {
+        let mut stored = Vec::new();
+        if !self.root.exists() {
+            return Ok(stored);
+        }
+        for entry in WalkDir::new(&self.root).sort_by_file_name() {
+            let entry = entry?;
+            let key = entry
+                .path()
+                .strip_prefix(&self.root)?
+                .iter()
+                .map(|s| s.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if !entry.file_type().is_file() || key.ends_with(META) || !key.starts_with(prefix) {
+                continue;
+            }
+            let meta: Meta =
+                match tokio::fs::read(format!("{}{META}", entry.path().display())).await {
+                    Ok(meta) => serde_json::from_slice(&meta)?,
+                    Err(_) => Meta::default(),
+                };
+            stored.push(Stored {
+                key,
+                size: entry.metadata()?.len(),
+                etag: None,
+                metadata: meta.metadata,
+            });
+        }
+        Ok(stored)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.path(key))
+            .await
+            .with_context(|| format!("failed to read {key}"))
+    }
+
+    async fn head(&self, key: &str) -> Result<Meta> {
+        let path = self.path(key);
+        if !path.is_file() {
+            bail!("there is no {key}");
+        }
+        match tokio::fs::read(format!("{}{META}", path.display())).await {
+            Ok(meta) => Ok(serde_json::from_slice(&meta)?),
+            Err(_) => Ok(Meta::default()),
+        }
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>, meta: &Meta) -> Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let meta_path = PathBuf::from(format!("{}{META}", path.display()));
+        Self::replace(meta_path, serde_json::to_vec(meta)?).await?;
+        Self::replace(path, body).await
+    }
+
+    async fn copy(&self, from: &str, to: &str, meta: &Meta) -> Result<()> {
+        let body = self.get(from).await?;
+        self.put(to, body, meta).await
+    }
+
+    async fn delete(&self, keys: &[String]) -> Result<()> {
+        for key in keys {
+            let path = self.path(key);
+            let meta_path = format!("{}{META}", path.display());
+            for path in [path, meta_path.into()] {
+                match tokio::fs::remove_file(&path).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A directory on a host, over ssh and scp.
+pub struct Ssh {
+    /// Anything ssh takes, like `root@xray`.
+    pub host: String,
+    pub root: String,
+}
+
+impl Ssh {
+    fn path(&self, key: &str) -> String {
+        format!("{}{key}", directory(&self.root))
+    }
+
+    async fn run(command: &mut Command) -> Result<Vec<u8>> {
+        let output = command
+            .output()
+            .await
+            .with_context(|| format!("failed to call {command:?}"))?;
+        if !output.status.success() {
+            bail!(
+                "{command:?} exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.stdout)
+    }
+
+    async fn ssh(&self, script: &str) -> Result<Vec<u8>> {
+        Self::run(Command::new("ssh").arg(&self.host).arg(script)).await
+    }
+
+    /// Uploads `contents` as `path.new`, for a rename to put in place.
+    async fn stage(&self, path: &str, contents: &[u8]) -> Result<String> {
+        let mut local = tempfile::NamedTempFile::new()?;
+        local.write_all(contents)?;
+        let staged = format!("{path}.new");
+        Self::run(
+            Command::new("scp")
+                .arg(local.path())
+                .arg(format!("{}:{staged}", self.host)),
+        )
+        .await?;
+        Ok(staged)
+    }
+
+    /// Writes `meta` next to `path` and runs `then`, which puts the object itself in place.
+    async fn put_with(&self, path: &str, meta: &Meta, then: &str) -> Result<()> {
+        let parent = path.rsplit_once('/').map_or(".", |(parent, _)| parent);
+        self.ssh(&format!("mkdir -p {}", quote(parent))).await?;
+        let meta_path = format!("{path}{META}");
+        let staged = self.stage(&meta_path, &serde_json::to_vec(meta)?).await?;
+        self.ssh(&format!(
+            "mv -f {} {} && {then}",
+            quote(&staged),
+            quote(&meta_path)
+        ))
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DeployTarget for Ssh {
+    async fn list(&self, prefix: &str) -> Result<Vec<Stored>> {
+        // a line per object: its size, key and meta
+        let listing = self
+            .ssh(&format!(
+                "cd {} 2>/dev/null || exit 0; find . -type f ! -name '*{META}' -printf '%s\\t%P\\t' \
+                 -exec sh -c 'cat \"$1{META}\" 2>/dev/null; echo' _ {{}} \\;",
+                quote(&self.root)
+            ))
+            .await?;
+        let mut stored = Vec::new();
+        for line in String::from_utf8_lossy(&listing).lines() {
+            let mut fields = line.splitn(3, '\t');
+            let (size, key, meta) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(size), Some(key), meta) if key.starts_with(prefix) => (size, key, meta),
+                _ => continue,
+            };
+            let meta: Meta = match meta {
+                Some(meta) if !meta.is_empty() => serde_json::from_str(meta)?,
+                _ => Meta::default(),
+            };
+            stored.push(Stored {
+                key: key.to_string(),
+                size: size.parse()?,
+                etag: None,
+                metadata: meta.metadata,
+            });
+        }
+        Ok(stored)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.ssh(&format!("cat {}", quote(&self.path(key))))
+            .await
+            .with_context(|| format!("failed to read {key} on {}", self.host))
+    }
+
+    async fn head(&self, key: &str) -> Result<Meta> {
+        let path = self.path(key);
+        let meta = self
+            .ssh(&format!(
+                "test -f {0} && {{ cat {0}{META} 2>/dev/null; true; }}",
+                quote(&path)
+            ))
+            .await
+            .with_context(|| format!("there is no {key} on {}", self.host))?;
+        if meta.is_empty() {
+            return Ok(Meta::default());
+        }
+        Ok(serde_json::from_slice(&meta)?)
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>, meta: &Meta) -> Result<()> {
+        let path = self.path(key);
+        let staged = self.stage(&path, &body).await?;
+        let then = format!("mv -f {} {}", quote(&staged), quote(&path));
+        self.put_with(&path, meta, &then).await
+    }
+
+    async fn copy(&self, from: &str, to: &str, meta: &Meta) -> Result<()> {
+        let path = self.path(to);
+        let then = format!(
+            "cp {} {}.new && mv -f {1}.new {1}",
+            quote(&self.path(from)),
+            quote(&path)
+        );
+        self.put_with(&path, meta, &then).await
+    }
+
+    async fn delete(&self, keys: &[String]) -> Result<()> {
+        // one command line can only be so long
+        for keys in keys.chunks(200) {
+            let paths: Vec<String> = keys
+                .iter()
+                .flat_map(|k| [self.path(k), format!("{}{META}", self.path(k))])
+                .map(|p| quote(&p))
+                .collect();
+            self.ssh(&format!("rm -f {}", paths.join(" "))).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = LocalDir {
+            root: dir.path().to_path_buf(),
+        };
+        let meta = Meta {
+            content_type: Some("text/html".to_string()),
+            content_encoding: None,
+            cache_control: Some("public, max-age=60".to_string()),
+            metadata: HashMap::from([("release".to_string(), "1".to_string())]),
+        };
+        target
+            .put("site/index.html", b"test".to_vec(), &meta)
+            .await
+            .unwrap();
+        target
+            .copy("site/index.html", "site/copy.html", &meta)
+            .await
+            .unwrap();
+        assert_eq!(target.get("site/copy.html").await.unwrap(), b"test");
+        assert_eq!(target.head("site/copy.html").await.unwrap(), meta);
+        assert!(target.head("site/gone.html").await.is_err());
+
+        let listed = target.list("site/").await.unwrap();
+        assert_eq!(
+            listed.iter().map(|s| s.key.as_str()).collect::<Vec<_>>(),
+            vec!["site/copy.html", "site/index.html"]
+        );
+        assert_eq!(listed[0].size, 4);
+        assert_eq!(listed[0].metadata, meta.metadata);
+        assert!(target.list("other/").await.unwrap().is_empty());
+
+        target
+            .delete(&["site/index.html".to_string(), "site/gone.html".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(target.list("").await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,286 @@
+mod watch;
+
+use clap::{Parser, Subcommand};
+use common::{ClientOpt, ContainerStatus, OnlinePeople, ServerStatus};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Check on and power the 'Chung' minecraft server through sentinel's rest api.
+#[derive(Parser)]
+#[clap(version, about)]
+struct Args {
+    /// Where sentinel listens.
+    #[clap(long, env = "SENTINEL_URL", default_value = "https://siyuanyan.net:3000")]
+    url: String,
+    /// Sentinel's `API_TOKEN`.
+    #[clap(long, env = "SENTINEL_TOKEN", hide_env_values = true)]
+    token: String,
+    /// Accept self-signed certificates, for a sentinel running on localhost.
+    #[clap(long)]
+    insecure: bool,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the status. Exits with 0 only if the minecraft container is up.
+    Status {
+        /// Keep a live view open instead, quit with `q`.
+        #[clap(long)]
+        watch: bool,
+        /// Seconds between polls in the live view.
+        #[clap(long, default_value_t = 5)]
+        interval: u64,
+        /// Seconds sentinel's poll of the server may be old, it's stale after that.
+        #[clap(long, default_value_t = 120)]
+        max_age: u64,
+    },
+    /// Power on the server.
+    On,
+    /// Power off the server.
+    Off,
+    /// Reboot the server.
+    Reboot,
+    /// Run docker compose on the minecraft project.
+    Compose {
+        #[clap(subcommand)]
+        action: ComposeAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ComposeAction {
+    /// `docker compose up -d`
+    Up,
+    /// `docker compose down`
+    Down,
+    /// `docker compose restart`
+    Restart,
+    /// `docker compose pull`, refused unless sentinel treats the api as an admin.
+    Pull,
+}
+
+impl From<ComposeAction> for ClientOpt {
+    fn from(action: ComposeAction) -> Self {
+        match action {
+            ComposeAction::Up => ClientOpt::ComposeUp,
+            ComposeAction::Down => ClientOpt::ComposeDown,
+            ComposeAction::Restart => ClientOpt::ComposeRestart,
+            ComposeAction::Pull => ClientOpt::ComposePull,
+        }
+    }
+}
+
+/// exit codes scripts can rely on
+#[derive(Debug)]
+enum Failure {
+    /// the server is reachable but the minecraft container isn't up
+    NotUp = 1,
+    /// sentinel didn't accept our token, or refused the request
+    Unauthorized = 3,
+    /// sentinel, or the server behind it, can't be reached
+    Unreachable = 4,
+    /// sentinel last polled the server longer ago than `--max-age`
+    Stale = 5,
+}
+
+impl From<Failure> for ExitCode {
+    fn from(f: Failure) -> Self {
+        ExitCode::from(f as u8)
+    }
+}
+
+/// The status, and when sentinel polled it in seconds since the unix epoch.
+#[derive(Deserialize)]
+struct Status {
+    #[serde(flatten)]
+    status: ServerStatus,
+    polled_at: u64,
+}
+
+impl Status {
+    /// How long ago sentinel polled it.
+    fn age(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Duration::from_secs(now.saturating_sub(self.polled_at))
+    }
+}
+
+#[derive(Deserialize)]
+struct Feedback {
+    feedback: String,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    error: String,
+}
+
+struct Sentinel {
+    http: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl Sentinel {
+    fn new(url: String, token: String, insecure: bool) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .danger_accept_invalid_certs(insecure)
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        Ok(Self {
+            http,
+            url: url.trim_end_matches('/').to_string(),
+            token,
+        })
+    }
+
+    async fn status(&self) -> Result<Status, (Failure, String)> {
+        let response = self
+            .http
+            .get(format!("{}/api/status", self.url))
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(|e| (Failure::Unreachable, e.to_string()))?;
+        Self::parse(response).await
+    }
+
+    async fn power(&self, opt: ClientOpt) -> Result<Feedback, (Failure, String)> {
+        let opt = match opt {
+            ClientOpt::On => "on",
+            ClientOpt::Off => "off",
+            ClientOpt::Reboot => "reboot",
+            ClientOpt::RefreshNow => "refresh_now",
+            ClientOpt::ComposeUp => "compose_up",
+            ClientOpt::ComposeDown => "compose_down",
+            ClientOpt::ComposeRestart => "compose_restart",
+            ClientOpt::ComposePull => "compose_pull",
+        };
+        let response = self
+            .http
+            .post(format!("{}/api/power/{opt}", self.url))
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(|e| (Failure::Unreachable, e.to_string()))?;
+        Self::parse(response).await
+    }
+
+    async fn parse<T: for<'de> Deserialize<'de>>(
+        response: reqwest::Response,
+    ) -> Result<T, (Failure, String)> {
+        let status = response.status();
+        if status.is_success() {
+            return response
+                .json()
+                .await
+                .map_err(|e| (Failure::Unreachable, e.to_string()));
+        }
+
+        let failure = match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Failure::Unauthorized,
+            _ => Failure::Unreachable,
+        };
+        let reason = match response.json::<ApiError>().await {
+            Ok(ApiError { error }) => error,
+            Err(_) => status.to_string(),
+        };
+        Err((failure, reason))
+    }
+}
+
+fn is_up(status: &ServerStatus) -> bool {
+    matches!(status.container, ContainerStatus::Up(_))
+}
+
+fn print_status(Status { status, .. }: &Status, age: Duration) {
+    println!("polled:    {}s ago", age.as_secs());
+    println!("host:      {}", status.host);
+    println!(
+        "container: {}",
+        match &status.container {
+            ContainerStatus::Unknown => "unknown",
+            ContainerStatus::NotUp => "not up",
+            ContainerStatus::Up(s) => s,
+        }
+    );
+    println!(
+        "online:    {}",
+        match &status.online {
+            OnlinePeople::Unknown => "unknown",
+            OnlinePeople::Known(s) => s.trim(),
+        }
+    );
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+    let args = Args::parse();
+
+    let sentinel = match Sentinel::new(args.url, args.token, args.insecure) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{e}");
+            return Failure::Unreachable.into();
+        }
+    };
+
+    let result = match args.command {
+        Command::Status {
+            watch: true,
+            interval,
+            max_age,
+        } => {
+            watch::run(
+                sentinel,
+                Duration::from_secs(interval),
+                Duration::from_secs(max_age),
+            )
+            .await
+        }
+        Command::Status {
+            watch: false,
+            max_age,
+            ..
+        } => sentinel.status().await.and_then(|s| {
+            let age = s.age();
+            print_status(&s, age);
+            if age > Duration::from_secs(max_age) {
+                Err((
+                    Failure::Stale,
+                    format!("sentinel last polled the server {}s ago", age.as_secs()),
+                ))
+            } else if is_up(&s.status) {
+                Ok(())
+            } else {
+                Err((Failure::NotUp, "the minecraft container is not up".to_string()))
+            }
+        }),
+        Command::On => power(&sentinel, ClientOpt::On).await,
+        Command::Off => power(&sentinel, ClientOpt::Off).await,
+        Command::Reboot => power(&sentinel, ClientOpt::Reboot).await,
+        Command::Compose { action } => power(&sentinel, action.into()).await,
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err((failure, reason)) => {
+            eprintln!("{reason}");
+            failure.into()
+        }
+    }
+}
+
+async fn power(sentinel: &Sentinel, opt: ClientOpt) -> Result<(), (Failure, String)> {
+    let Feedback { feedback } = sentinel.power(opt).await?;
+    println!("{feedback}");
+    Ok(())
+}
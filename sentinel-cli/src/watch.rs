@@ -0,0 +1,149 @@
+//! `status --watch`, a live view of the server in the terminal.
+
+use crate::{Failure, Sentinel, Status};
+use common::{ContainerStatus, OnlinePeople};
+use crossterm::{
+    event::{self, Event, KeyCode},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Frame, Terminal,
+};
+
+type Latest = Option<(Result<Status, String>, Instant)>;
+
+/// Polls sentinel every `interval`, warning once what it polled is older than `max_age`.
+pub async fn run(
+    sentinel: Sentinel,
+    interval: Duration,
+    max_age: Duration,
+) -> Result<(), (Failure, String)> {
+    let sentinel = Arc::new(sentinel);
+    let (tx, rx) = watch::channel::<Latest>(None);
+
+    let poll = tokio::spawn(async move {
+        loop {
+            let status = sentinel.status().await.map_err(|(_, reason)| reason);
+            if tx.send(Some((status, Instant::now()))).is_err() {
+                return;
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+
+    let result = draw_until_quit(rx, max_age).map_err(|e| (Failure::Unreachable, e.to_string()));
+    poll.abort();
+    result
+}
+
+fn draw_until_quit(rx: watch::Receiver<Latest>, max_age: Duration) -> io::Result<()> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = (|| loop {
+        terminal.draw(|f| ui(f, &rx.borrow(), max_age))?;
+        if event::poll(Duration::from_millis(250))? {
+            if let Event::Key(key) = event::read()? {
+                if let KeyCode::Char('q') | KeyCode::Esc = key.code {
+                    return Ok(());
+                }
+            }
+        }
+    })();
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+fn section<'a>(title: &'a str, text: String, color: Color) -> Paragraph<'a> {
+    Paragraph::new(Span::styled(text, Style::default().fg(color)))
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .wrap(Wrap { trim: true })
+}
+
+fn ui<B: Backend>(f: &mut Frame<B>, latest: &Latest, max_age: Duration) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .split(f.size());
+
+    let mut stale = false;
+    let footer = match latest {
+        None => {
+            f.render_widget(
+                Paragraph::new("receiving from sentinel...")
+                    .block(Block::default().borders(Borders::ALL)),
+                chunks[0],
+            );
+            "press q to quit".to_string()
+        }
+        Some((Err(reason), at)) => {
+            f.render_widget(section("Sentinel", reason.clone(), Color::Red), chunks[0]);
+            format!("last update: {}s ago, press q to quit", at.elapsed().as_secs())
+        }
+        Some((Ok(polled), at)) => {
+            let status = &polled.status;
+            let host_color = if status.host.contains("running") {
+                Color::Green
+            } else {
+                Color::Yellow
+            };
+            f.render_widget(
+                section("Host Status", status.host.clone(), host_color),
+                chunks[0],
+            );
+
+            let (container, color) = match &status.container {
+                ContainerStatus::Unknown => ("unknown".to_string(), Color::Gray),
+                ContainerStatus::NotUp => ("not up".to_string(), Color::Red),
+                ContainerStatus::Up(s) => (s.clone(), Color::Green),
+            };
+            f.render_widget(section("Container Status", container, color), chunks[1]);
+
+            let online = match &status.online {
+                OnlinePeople::Unknown => "unknown".to_string(),
+                OnlinePeople::Known(s) => s.trim().to_string(),
+            };
+            f.render_widget(section("Online People", online, Color::Reset), chunks[2]);
+
+            // sentinel polls on its own cadence, its answer can be older than the last update
+            let age = polled.age();
+            stale = age > max_age;
+            format!(
+                "polled {}s ago{}, last update: {}s ago, press q to quit",
+                age.as_secs(),
+                if stale { " (stale)" } else { "" },
+                at.elapsed().as_secs()
+            )
+        }
+    };
+
+    let style = if stale {
+        Style::default().fg(Color::Red)
+    } else {
+        Style::default()
+    };
+    f.render_widget(
+        Paragraph::new(Spans::from(Span::styled(footer, style))),
+        chunks[3],
+    );
+}
@@ -0,0 +1,184 @@
+//! A REST/JSON api next to the websocket, so the server can be scripted with curl.
+//!
+//! `openapi.json` next to `Cargo.toml` describes it and is served at `/api/openapi.json`.
+
+use crate::command::{Caller, CommandError, Commander};
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use common::{ClientOpt, Container, PlayerList, Role, Roster, RosterEdit, ServerStatus};
+use serde::{Deserialize, Serialize};
+use std::time::UNIX_EPOCH;
+use tower_http::auth::RequireAuthorizationLayer;
+
+const OPENAPI: &str = include_str!("../openapi.json");
+
+/// The status with when it was polled, so scripts can tell how old it is.
+#[derive(Serialize)]
+struct Status {
+    #[serde(flatten)]
+    status: ServerStatus,
+    /// Seconds since the unix epoch.
+    polled_at: u64,
+}
+
+#[derive(Serialize)]
+struct Feedback {
+    feedback: String,
+}
+
+#[derive(Serialize)]
+struct ApiError {
+    error: String,
+}
+
+fn api_error(status: StatusCode, error: impl Into<String>) -> (StatusCode, Json<ApiError>) {
+    (
+        status,
+        Json(ApiError {
+            error: error.into(),
+        }),
+    )
+}
+
+async fn status(
+    Extension(commander): Extension<Commander>,
+) -> Result<Json<Status>, (StatusCode, Json<ApiError>)> {
+    commander
+        .status()
+        .await
+        .map(|polled| {
+            Json(Status {
+                status: polled.status,
+                polled_at: polled
+                    .polled_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            })
+        })
+        .ok_or_else(|| {
+            api_error(
+                StatusCode::BAD_GATEWAY,
+                "sentinel failed to reach the server",
+            )
+        })
+}
+
+async fn container(
+    Extension(commander): Extension<Commander>,
+) -> Result<Json<Option<Container>>, (StatusCode, Json<ApiError>)> {
+    commander
+        .status()
+        .await
+        .map(|polled| Json(polled.container))
+        .ok_or_else(|| {
+            api_error(
+                StatusCode::BAD_GATEWAY,
+                "sentinel failed to reach the server",
+            )
+        })
+}
+
+fn caller() -> Caller {
+    Caller {
+        name: "rest api".to_string(),
+        role: Role::Member,
+    }
+}
+
+fn command_error(e: CommandError) -> (StatusCode, Json<ApiError>) {
+    match e {
+        CommandError::Rejected(reason) => api_error(StatusCode::FORBIDDEN, reason),
+        CommandError::Failed(reason) => api_error(StatusCode::BAD_GATEWAY, reason),
+    }
+}
+
+async fn power(
+    Path(opt): Path<ClientOpt>,
+    Extension(commander): Extension<Commander>,
+) -> Result<Json<Feedback>, (StatusCode, Json<ApiError>)> {
+    commander
+        .execute(opt, &caller())
+        .await
+        .map(|feedback| Json(Feedback { feedback }))
+        .map_err(command_error)
+}
+
+async fn roster(
+    Extension(commander): Extension<Commander>,
+) -> Result<Json<Roster>, (StatusCode, Json<ApiError>)> {
+    commander
+        .read_roster()
+        .await
+        .map(Json)
+        .map_err(command_error)
+}
+
+#[derive(Deserialize)]
+struct Ban {
+    reason: Option<String>,
+}
+
+async fn edit_roster(
+    add: bool,
+    list: PlayerList,
+    name: String,
+    reason: Option<String>,
+    commander: Commander,
+) -> Result<Json<Feedback>, (StatusCode, Json<ApiError>)> {
+    let edit = RosterEdit {
+        list,
+        name,
+        add,
+        reason,
+    };
+    commander
+        .edit_roster(edit, &caller())
+        .await
+        .map(|feedback| Json(Feedback { feedback }))
+        .map_err(command_error)
+}
+
+async fn add_to_roster(
+    Path((list, name)): Path<(PlayerList, String)>,
+    ban: Option<Json<Ban>>,
+    Extension(commander): Extension<Commander>,
+) -> Result<Json<Feedback>, (StatusCode, Json<ApiError>)> {
+    let reason = ban.and_then(|Json(ban)| ban.reason);
+    edit_roster(true, list, name, reason, commander).await
+}
+
+async fn remove_from_roster(
+    Path((list, name)): Path<(PlayerList, String)>,
+    Extension(commander): Extension<Commander>,
+) -> Result<Json<Feedback>, (StatusCode, Json<ApiError>)> {
+    edit_roster(false, list, name, None, commander).await
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+/// `token` is the bearer token every route but the openapi document requires.
+pub fn router(commander: Commander, token: &str) -> Router {
+    let authorized = Router::new()
+        .route("/status", get(status))
+        .route("/container", get(container))
+        .route("/power/:opt", post(power))
+        .route("/roster", get(roster))
+        .route(
+            "/roster/:list/:name",
+            post(add_to_roster).delete(remove_from_roster),
+        )
+        .route_layer(RequireAuthorizationLayer::bearer(token))
+        .layer(Extension(commander));
+
+    Router::new()
+        .route("/openapi.json", get(openapi))
+        .merge(authorized)
+}
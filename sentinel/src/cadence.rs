@@ -0,0 +1,244 @@
+//! How often sentinel polls the server: often while something is changing, rarely while nothing
+//! is.
+
+use common::{ContainerStatus, ServerStatus};
+use std::borrow::Borrow;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// See the README for the env vars.
+#[derive(Clone, Debug)]
+pub struct Cadence {
+    /// While the host or the container is starting or stopping, and never faster than this.
+    pub fast: Duration,
+    /// While the host is running and nothing is changing.
+    pub stable: Duration,
+    /// While the host is stopped.
+    pub stopped: Duration,
+    /// How long to keep polling fast after a power command.
+    pub settle: Duration,
+}
+
+impl Default for Cadence {
+    fn default() -> Self {
+        Self {
+            fast: Duration::from_secs(2),
+            stable: Duration::from_secs(10),
+            stopped: Duration::from_secs(60),
+            settle: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Cadence {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let secs = |name, default: Duration| {
+            Duration::from_secs(
+                dotenv::var(name)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default.as_secs()),
+            )
+        };
+        Self {
+            fast: secs("POLL_FAST_SECS", default.fast),
+            stable: secs("POLL_STABLE_SECS", default.stable),
+            stopped: secs("POLL_STOPPED_SECS", default.stopped),
+            settle: secs("POLL_SETTLE_SECS", default.settle),
+        }
+    }
+
+    /// The longest it waits between two polls, however the server is doing.
+    pub fn slowest(&self) -> Duration {
+        self.stable.max(self.stopped)
+    }
+
+    /// How long to wait after polling `status`.
+    pub fn delay(&self, status: &ServerStatus, hurried: bool) -> Duration {
+        if hurried || in_transition(status) {
+            self.fast
+        } else if status.host == "stopped" {
+            self.stopped
+        } else {
+            self.stable
+        }
+    }
+}
+
+fn in_transition(status: &ServerStatus) -> bool {
+    matches!(
+        status.host.as_str(),
+        "pending" | "stopping" | "shutting-down"
+    ) || matches!(&status.container, ContainerStatus::Up(s) if s.contains("health: starting"))
+}
+
+/// Runs the polling, and lets commands wake it up early.
+pub struct Poller {
+    cadence: Cadence,
+    woken: Notify,
+    hurry_until: Mutex<Option<Instant>>,
+}
+
+impl Poller {
+    pub fn new(cadence: Cadence) -> Arc<Self> {
+        Arc::new(Self {
+            cadence,
+            woken: Notify::new(),
+            hurry_until: Mutex::new(None),
+        })
+    }
+
+    /// Polls again right away, for [`ClientOpt::RefreshNow`](common::ClientOpt).
+    pub fn refresh(&self) {
+        self.woken.notify_one();
+    }
+
+    /// Polls again right away and keeps polling fast for a while, after a power command.
+    pub fn hurry(&self) {
+        *self.hurry_until.lock().unwrap() = Some(Instant::now() + self.cadence.settle);
+        self.woken.notify_one();
+    }
+
+    fn hurried(&self) -> bool {
+        matches!(*self.hurry_until.lock().unwrap(), Some(until) if Instant::now() < until)
+    }
+
+    /// How long to wait after polling `status`.
+    pub fn interval(&self, status: &ServerStatus) -> Duration {
+        self.cadence.delay(status, self.hurried())
+    }
+
+    /// How long what a poll found out is good for: the wait before the next poll, and a moment for
+    /// that poll to take.
+    pub fn good_for(&self, status: &ServerStatus) -> Duration {
+        self.interval(status) + self.cadence.fast
+    }
+
+    /// Polls with `poll` for as long as `keep_going` says so, handing everything polled to
+    /// `publish`.
+    pub async fn run<T, P, F>(
+        &self,
+        mut poll: P,
+        mut publish: impl FnMut(T),
+        keep_going: impl Fn() -> bool,
+    ) where
+        T: Borrow<ServerStatus>,
+        P: FnMut() -> F,
+        F: Future<Output = T>,
+    {
+        while keep_going() {
+            let polled_at = Instant::now();
+            let polled = poll().await;
+            let delay = self.interval(polled.borrow());
+            publish(polled);
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.woken.notified() => {
+                    // however impatient the users, never faster than the fast cadence
+                    tokio::time::sleep_until(polled_at + self.cadence.fast).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::OnlinePeople;
+
+    fn status(host: &str) -> ServerStatus {
+        ServerStatus {
+            host: host.to_string(),
+            container: ContainerStatus::NotUp,
+            online: OnlinePeople::Unknown,
+        }
+    }
+
+    /// Runs `poller` in the background, returning the seconds at which it polled so far.
+    fn record(poller: Arc<Poller>, hosts: Vec<&'static str>) -> Arc<Mutex<Vec<u64>>> {
+        let start = Instant::now();
+        let polls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = polls.clone();
+        tokio::spawn(async move {
+            let mut hosts = hosts.into_iter().cycle();
+            poller
+                .run(
+                    || {
+                        recorded.lock().unwrap().push(start.elapsed().as_secs());
+                        std::future::ready(status(hosts.next().unwrap()))
+                    },
+                    |_: ServerStatus| {},
+                    || true,
+                )
+                .await
+        });
+        polls
+    }
+
+    #[test]
+    fn test_delay_follows_the_host() {
+        let cadence = Cadence::default();
+        assert_eq!(cadence.delay(&status("pending"), false), cadence.fast);
+        assert_eq!(cadence.delay(&status("running"), false), cadence.stable);
+        assert_eq!(cadence.delay(&status("running"), true), cadence.fast);
+        assert_eq!(cadence.delay(&status("stopped"), false), cadence.stopped);
+
+        let starting = ServerStatus {
+            container: ContainerStatus::Up("Up 6 seconds (health: starting)".to_string()),
+            ..status("running")
+        };
+        assert_eq!(cadence.delay(&starting, false), cadence.fast);
+
+        let poller = Poller::new(cadence.clone());
+        assert_eq!(
+            poller.good_for(&status("stopped")),
+            cadence.stopped + cadence.fast
+        );
+        poller.hurry();
+        assert_eq!(poller.good_for(&status("stopped")), cadence.fast * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backs_off_once_stable() {
+        let poller = Poller::new(Cadence::default());
+        let polls = record(poller, vec!["pending", "pending", "running"]);
+
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        assert_eq!(*polls.lock().unwrap(), [0, 2, 4, 14]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refresh_polls_early() {
+        let poller = Poller::new(Cadence::default());
+        let polls = record(poller.clone(), vec!["stopped"]);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        poller.refresh();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        poller.refresh();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        // the first refresh waits out the fast cadence
+        assert_eq!(*polls.lock().unwrap(), [0, 2, 11]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hurry_polls_fast_until_settled() {
+        let poller = Poller::new(Cadence::default());
+        let polls = record(poller.clone(), vec!["running"]);
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        poller.hurry();
+        tokio::time::sleep(Duration::from_secs(71)).await;
+
+        let polls = polls.lock().unwrap();
+        assert_eq!(polls[..4], [0, 5, 7, 9]);
+        // settled after 60s, back to the stable cadence
+        assert_eq!(polls[polls.len() - 2..], [65, 75]);
+    }
+}
@@ -0,0 +1,348 @@
+//! The single path every front end (websocket, discord bot...) goes through to act on the server,
+//! so they share the same permissions and the same audit trail.
+
+use crate::cadence::Poller;
+use crate::compose::Compose;
+use crate::config::ConfigFiles;
+use crate::crash::CrashWatch;
+use crate::limit::Cooldown;
+use crate::roster::{self, RosterKeeper};
+use crate::Polled;
+use aws_sdk_ec2::Client;
+use common::{
+    ClientOpt, ConfigEdit, ConfigEntry, ConfigReview, PlayerList, Role, Roster, RosterEdit,
+};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use tokio::time::Instant;
+
+/// Who is asking, used for permission checks and the audit log.
+#[derive(Clone, Debug)]
+pub struct Caller {
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// The caller isn't allowed to do that.
+    Rejected(String),
+    /// The caller is allowed, but it didn't work out.
+    Failed(String),
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::Rejected(reason) | CommandError::Failed(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// A command [`Commander::authorize`] let through, for [`Commander::perform`] to run.
+pub struct Authorized {
+    opt: ClientOpt,
+    /// When the caller took the power cooldown, to give it back if the command fails.
+    cooldown: Option<Instant>,
+}
+
+/// Acts on the server for every front end, cheap to clone.
+#[derive(Clone)]
+pub struct Commander {
+    client: Client,
+    cooldown: Arc<Cooldown>,
+    poller: Arc<Poller>,
+    crashes: Arc<CrashWatch>,
+    compose: Arc<Compose>,
+    config: Arc<ConfigFiles>,
+    roster: Arc<RosterKeeper>,
+    polled: Arc<watch::Sender<Option<Polled>>>,
+    latest: watch::Receiver<Option<Polled>>,
+    /// Held while polling for [`Commander::status`], so callers don't all ssh into the host at once.
+    polling: Arc<Mutex<()>>,
+}
+
+impl Commander {
+    /// `power_cooldown` is the minimum time between two power commands, see [`Cooldown`].
+    /// `polled` is where the poller publishes what it finds out.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        client: Client,
+        power_cooldown: Duration,
+        poller: Arc<Poller>,
+        crashes: Arc<CrashWatch>,
+        compose: Compose,
+        config: ConfigFiles,
+        roster: RosterKeeper,
+        polled: Arc<watch::Sender<Option<Polled>>>,
+    ) -> Self {
+        Self {
+            client,
+            cooldown: Arc::new(Cooldown::new(power_cooldown)),
+            poller,
+            crashes,
+            compose: Arc::new(compose),
+            config: Arc::new(config),
+            roster: Arc::new(roster),
+            latest: polled.subscribe(),
+            polled,
+            polling: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn crashes(&self) -> &Arc<CrashWatch> {
+        &self.crashes
+    }
+
+    /// What the poller found out last, unless that is older than the poller would have waited to
+    /// poll again, like while nobody has the console open and it doesn't poll at all. Then the
+    /// server is polled here, once for every caller waiting and off the runtime, since ssh blocks,
+    /// and what it found out is published like the poller's.
+    ///
+    /// `None` if that poll failed.
+    pub async fn status(&self) -> Option<Polled> {
+        if let Some(polled) = self.fresh() {
+            return Some(polled);
+        }
+        let _polling = self.polling.lock().await;
+        // whoever held the lock before may have polled already
+        if let Some(polled) = self.fresh() {
+            return Some(polled);
+        }
+        let client = self.client.clone();
+        let runtime = tokio::runtime::Handle::current();
+        let polled = tokio::task::spawn_blocking(move || {
+            runtime.block_on(crate::poll_server_status(&client))
+        })
+        .await
+        .ok()
+        .flatten()?;
+        self.crashes.observe(&polled.status);
+        self.polled.send(Some(polled.clone())).ok();
+        Some(polled)
+    }
+
+    /// The last poll, if the poller wouldn't have polled again yet.
+    fn fresh(&self) -> Option<Polled> {
+        let latest = self.latest.borrow();
+        let polled = latest.as_ref()?;
+        let age = polled.polled_at.elapsed().unwrap_or_default();
+        (age <= self.poller.good_for(&polled.status)).then(|| polled.clone())
+    }
+
+    /// Checks whether `caller` may run `opt` right now, and writes it down in the audit log
+    /// either way.
+    ///
+    /// Everyone but admins has to wait out the cooldown of the previous power command.
+    pub fn authorize(&self, opt: ClientOpt, caller: &Caller) -> Result<Authorized, CommandError> {
+        if !caller.role.may(opt) {
+            tracing::warn!(target: "sentinel::audit", caller = %caller.name, role = ?caller.role, ?opt, "refused");
+            return Err(CommandError::Rejected(
+                format!("a {:?} is not allowed to do that", caller.role).to_lowercase(),
+            ));
+        }
+        let mut cooldown = None;
+        if caller.role < Role::Admin && opt != ClientOpt::RefreshNow {
+            match self.cooldown.take() {
+                Ok(taken) => cooldown = Some(taken),
+                Err(remaining) => {
+                    tracing::warn!(target: "sentinel::audit", caller = %caller.name, role = ?caller.role, ?opt, "refused, cooling down");
+                    return Err(CommandError::Rejected(format!(
+                        "the server was just told what to do, try again in {}s",
+                        remaining.as_secs() + 1
+                    )));
+                }
+            }
+        }
+        tracing::info!(target: "sentinel::audit", caller = %caller.name, role = ?caller.role, ?opt, "executing");
+        Ok(Authorized { opt, cooldown })
+    }
+
+    /// Runs an [`Authorized`] command. If it fails, the cooldown it took is given back, since it
+    /// didn't tell the server to do anything.
+    ///
+    /// Returns the feedback for the caller.
+    pub async fn perform(&self, authorized: Authorized) -> Result<String, CommandError> {
+        let result = self.run(authorized.opt).await;
+        if let (Err(CommandError::Failed(_)), Some(taken)) = (&result, authorized.cooldown) {
+            self.cooldown.give_back(taken);
+        }
+        result
+    }
+
+    async fn run(&self, opt: ClientOpt) -> Result<String, CommandError> {
+        let client = &self.client;
+        let result = match opt {
+            ClientOpt::On => try_send_to_instance!(client, start_instances)
+                .map(|_| "sentinel acknowledged the request, will boot the host shortly")
+                .map_err(|e| e.to_string()),
+            ClientOpt::Off => try_send_to_instance!(client, stop_instances)
+                .map(|_| "sentinel acknowledged the request, will shutdown the host shortly")
+                .map_err(|e| e.to_string()),
+            ClientOpt::Reboot => try_send_to_instance!(client, reboot_instances)
+                .map(|_| "sentinel acknowledged the request, will reboot the host shortly")
+                .map_err(|e| e.to_string()),
+            ClientOpt::RefreshNow => {
+                self.poller.refresh();
+                return Ok("sentinel is refreshing the status".to_string());
+            }
+            ClientOpt::ComposeUp
+            | ClientOpt::ComposeDown
+            | ClientOpt::ComposeRestart
+            | ClientOpt::ComposePull => return self.run_compose(opt).await,
+        };
+        if result.is_ok() {
+            self.crashes.expect_downtime();
+            self.poller.hurry();
+        }
+
+        result.map(str::to_string).map_err(|e| {
+            tracing::error!(target: "sentinel::audit", ?opt, "failed: {e}");
+            CommandError::Failed(format!("sentinel failed to talk to aws: {e}"))
+        })
+    }
+
+    async fn run_compose(&self, opt: ClientOpt) -> Result<String, CommandError> {
+        let command = self
+            .compose
+            .command(opt)
+            .expect("only compose opts get here");
+        if opt != ClientOpt::ComposePull {
+            self.crashes.expect_downtime();
+        }
+        match Compose::run(command).await {
+            Ok(_) => {
+                self.poller.hurry();
+                Ok(match opt {
+                    ClientOpt::ComposePull => {
+                        "sentinel pulled the latest images, compose up to use them"
+                    }
+                    ClientOpt::ComposeDown => "sentinel took the compose project down",
+                    _ => "sentinel (re)started the compose project",
+                }
+                .to_string())
+            }
+            Err(e) => {
+                tracing::error!(target: "sentinel::audit", ?opt, "failed: {e}");
+                Err(CommandError::Failed(format!(
+                    "sentinel failed to run docker compose: {e}"
+                )))
+            }
+        }
+    }
+
+    /// Every editable setting with its current value, for admins only.
+    pub async fn read_config(&self, caller: &Caller) -> Result<Vec<ConfigEntry>, CommandError> {
+        only_admins(caller, "read the config")?;
+        self.config
+            .read()
+            .await
+            .map_err(|e| CommandError::Failed(format!("sentinel failed to read the config: {e}")))
+    }
+
+    /// Reviews `edits` and writes them if `apply`, for admins only.
+    pub async fn review_config(
+        &self,
+        edits: Vec<ConfigEdit>,
+        apply: bool,
+        caller: &Caller,
+    ) -> Result<ConfigReview, CommandError> {
+        only_admins(caller, "edit the config")?;
+        let review = self.config.review(edits, apply).await.map_err(|e| {
+            tracing::error!(target: "sentinel::audit", caller = %caller.name, "config edit failed: {e}");
+            CommandError::Failed(format!("sentinel failed to edit the config: {e}"))
+        })?;
+        if review.applied {
+            tracing::info!(target: "sentinel::audit", caller = %caller.name, changes = ?review.changes, "config edited");
+        }
+        Ok(review)
+    }
+
+    /// The whitelist, ops and bans, for anyone authenticated.
+    pub async fn read_roster(&self) -> Result<Roster, CommandError> {
+        self.roster
+            .read()
+            .await
+            .map_err(|e| CommandError::Failed(format!("sentinel failed to read the roster: {e}")))
+    }
+
+    /// Adds a player to or removes one from a list, if `caller` may edit it.
+    ///
+    /// Returns what the server said.
+    pub async fn edit_roster(
+        &self,
+        edit: RosterEdit,
+        caller: &Caller,
+    ) -> Result<String, CommandError> {
+        let list = roster::describe(edit.list);
+        if !caller.role.may_edit(edit.list) {
+            tracing::warn!(target: "sentinel::audit", caller = %caller.name, role = ?caller.role, ?edit, "refused");
+            return Err(CommandError::Rejected(match edit.list {
+                PlayerList::Whitelist => {
+                    "only members and admins may edit the whitelist".to_string()
+                }
+                _ => format!("only admins may edit the {list}"),
+            }));
+        }
+        roster::check(&edit).map_err(CommandError::Rejected)?;
+        tracing::info!(target: "sentinel::audit", caller = %caller.name, role = ?caller.role, ?edit, "editing the roster");
+        match self.roster.edit(edit).await {
+            Ok(said) => {
+                tracing::info!(target: "sentinel::audit", caller = %caller.name, "the server said {said:?}");
+                Ok(said)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(CommandError::Rejected(e.to_string()))
+            }
+            Err(e) => {
+                tracing::error!(target: "sentinel::audit", caller = %caller.name, "roster edit failed: {e}");
+                Err(CommandError::Failed(format!(
+                    "sentinel failed to edit the {list}: {e}"
+                )))
+            }
+        }
+    }
+
+    /// [`authorize`](Self::authorize)s and [`perform`](Self::perform)s `opt` on behalf of
+    /// `caller`.
+    pub async fn execute(&self, opt: ClientOpt, caller: &Caller) -> Result<String, CommandError> {
+        let authorized = self.authorize(opt, caller)?;
+        self.perform(authorized).await
+    }
+}
+
+fn only_admins(caller: &Caller, what: &str) -> Result<(), CommandError> {
+    if caller.role < Role::Admin {
+        tracing::warn!(target: "sentinel::audit", caller = %caller.name, role = ?caller.role, "refused to {what}");
+        return Err(CommandError::Rejected(format!("only admins may {what}")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_viewer_may_not_power() {
+        assert!(!Role::Viewer.may(ClientOpt::On));
+        assert!(Role::Member.may(ClientOpt::Reboot));
+        assert!(Role::Admin.may(ClientOpt::Off));
+        assert!(Role::Viewer.may(ClientOpt::RefreshNow));
+        assert!(Role::Member.may(ClientOpt::ComposeRestart));
+        assert!(!Role::Member.may(ClientOpt::ComposePull));
+        assert!(Role::Admin.may(ClientOpt::ComposePull));
+    }
+
+    #[test]
+    fn test_who_may_edit_the_roster() {
+        assert!(!Role::Viewer.may_edit(PlayerList::Whitelist));
+        assert!(Role::Member.may_edit(PlayerList::Whitelist));
+        assert!(!Role::Member.may_edit(PlayerList::Ops));
+        assert!(!Role::Member.may_edit(PlayerList::Bans));
+        assert!(Role::Admin.may_edit(PlayerList::Bans));
+    }
+}
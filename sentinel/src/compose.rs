@@ -0,0 +1,187 @@
+//! The minecraft compose project on the host: running compose commands and reading its container.
+
+use crate::ssh;
+use common::{ClientOpt, Container, ContainerStatus};
+use serde::Deserialize;
+use std::io;
+
+/// Lists every container, one json object per line.
+pub const LIST: &str = "docker container ls --all --format '{{json .}}'";
+
+/// See the README for the env vars.
+#[derive(Clone, Debug)]
+pub struct Compose {
+    /// Where `docker-compose.yml` is on the host.
+    pub dir: String,
+    pub container: String,
+}
+
+impl Compose {
+    pub fn from_env() -> Self {
+        Self {
+            dir: dotenv::var("COMPOSE_DIR").unwrap_or_else(|_| "/root".to_string()),
+            container: dotenv::var("MC_CONTAINER").unwrap_or_else(|_| "root-mc-1".to_string()),
+        }
+    }
+
+    /// The shell command behind `opt`, `None` if it isn't a compose one.
+    pub fn command(&self, opt: ClientOpt) -> Option<String> {
+        let args = match opt {
+            ClientOpt::ComposeUp => "up -d",
+            ClientOpt::ComposeDown => "down",
+            ClientOpt::ComposeRestart => "restart",
+            ClientOpt::ComposePull => "pull --quiet",
+            ClientOpt::On | ClientOpt::Off | ClientOpt::Reboot | ClientOpt::RefreshNow => {
+                return None
+            }
+        };
+        Some(format!(
+            "cd {} && docker compose {args}",
+            ssh::quote(&self.dir)
+        ))
+    }
+
+    /// Runs `command` from [`command`](Self::command) on the host.
+    pub async fn run(command: String) -> io::Result<String> {
+        tokio::task::spawn_blocking(move || ssh::run(&ssh::connect()?, &command))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    /// Reads our container with an ssh session to the host. Blocking.
+    ///
+    /// `None` if it doesn't exist.
+    pub fn inspect(&self, sess: &ssh2::Session) -> io::Result<Option<Container>> {
+        let listed = ssh::run(sess, LIST)?;
+        let listed = match find(&listed, &self.container)? {
+            Some(listed) => listed,
+            None => return Ok(None),
+        };
+        let digest = ssh::run(
+            sess,
+            &format!(
+                "docker image inspect --format '{{{{join .RepoDigests \" \"}}}}' {}",
+                ssh::quote(&listed.image)
+            ),
+        )
+        .map_err(|e| tracing::warn!("failed to read the image digest: {e}"))
+        .ok()
+        .and_then(|digests| digests.split_whitespace().next().map(str::to_string));
+        Ok(Some(listed.into_container(digest)))
+    }
+}
+
+/// What `docker container ls --format '{{json .}}'` says about a container, the part we read.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Listed {
+    names: String,
+    image: String,
+    state: String,
+    status: String,
+}
+
+impl Listed {
+    fn into_container(self, digest: Option<String>) -> Container {
+        Container {
+            health: health(&self.status).map(str::to_string),
+            name: self.names,
+            image: self.image,
+            digest,
+            state: self.state,
+            status: self.status,
+        }
+    }
+}
+
+/// Finds the container called `name` in [`LIST`]'s output.
+fn find(listed: &str, name: &str) -> io::Result<Option<Listed>> {
+    for line in listed.lines().filter(|l| !l.trim().is_empty()) {
+        let listed: Listed = serde_json::from_str(line)?;
+        if listed.names.split(',').any(|n| n == name) {
+            return Ok(Some(listed));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the container called `name` runs, with an ssh session to the host. Blocking.
+pub fn running(sess: &ssh2::Session, name: &str) -> io::Result<bool> {
+    is_running(&ssh::run(sess, LIST)?, name)
+}
+
+fn is_running(listed: &str, name: &str) -> io::Result<bool> {
+    Ok(matches!(find(listed, name)?, Some(l) if l.state == "running"))
+}
+
+/// The health docker appends to the status, e.g. `healthy` in `Up 2 hours (healthy)`.
+fn health(status: &str) -> Option<&str> {
+    let (_, health) = status.rsplit_once('(')?;
+    let health = health.strip_suffix(')')?;
+    match health {
+        "healthy" | "unhealthy" => Some(health),
+        "health: starting" => Some("starting"),
+        _ => None,
+    }
+}
+
+/// The [`ContainerStatus`] older clients get, `container` being the result of
+/// [`Compose::inspect`].
+pub fn status(container: &io::Result<Option<Container>>) -> ContainerStatus {
+    match container {
+        Ok(Some(c)) if c.state == "running" => ContainerStatus::Up(c.status.clone()),
+        Ok(_) => ContainerStatus::NotUp,
+        Err(_) => ContainerStatus::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTED: &str = r#"{"Command":"\"/start\"","CreatedAt":"2022-08-01 10:00:00 +0000 UTC","ID":"93b4bc8169e5","Image":"itzg/minecraft-server","Labels":"com.docker.compose.service=mc","LocalVolumes":"0","Mounts":"/opt/mc","Names":"root-mc-1","Networks":"root_default","Ports":"0.0.0.0:25565->25565/tcp","RunningFor":"3 hours ago","Size":"0B","State":"running","Status":"Up 6 seconds (health: starting)"}
+{"Command":"\"/hello\"","CreatedAt":"2022-07-01 10:00:00 +0000 UTC","ID":"1f2e3d4c5b6a","Image":"hello-world","Labels":"","LocalVolumes":"0","Mounts":"","Names":"eager_turing","Networks":"bridge","Ports":"","RunningFor":"4 weeks ago","Size":"0B","State":"exited","Status":"Exited (0) 4 weeks ago"}
+"#;
+
+    #[test]
+    fn test_find_and_read() {
+        let mc = find(LISTED, "root-mc-1").unwrap().unwrap();
+        assert_eq!(
+            mc.into_container(None),
+            Container {
+                name: "root-mc-1".to_string(),
+                image: "itzg/minecraft-server".to_string(),
+                digest: None,
+                state: "running".to_string(),
+                health: Some("starting".to_string()),
+                status: "Up 6 seconds (health: starting)".to_string(),
+            }
+        );
+
+        let exited = find(LISTED, "eager_turing")
+            .unwrap()
+            .unwrap()
+            .into_container(None);
+        assert_eq!(exited.health, None);
+        assert_eq!(status(&Ok(Some(exited))), ContainerStatus::NotUp);
+
+        assert!(find(LISTED, "root-mc-2").unwrap().is_none());
+        assert!(find("not json", "root-mc-1").is_err());
+    }
+
+    #[test]
+    fn test_running() {
+        assert!(is_running(LISTED, "root-mc-1").unwrap());
+        assert!(!is_running(LISTED, "eager_turing").unwrap());
+        assert!(!is_running(LISTED, "root-mc-2").unwrap());
+        assert!(is_running("not json", "root-mc-1").is_err());
+    }
+
+    #[test]
+    fn test_health() {
+        assert_eq!(health("Up 2 hours (healthy)"), Some("healthy"));
+        assert_eq!(health("Up 3 hours (unhealthy)"), Some("unhealthy"));
+        assert_eq!(health("Up 2 hours"), None);
+        assert_eq!(health("Exited (137) 5 minutes ago"), None);
+    }
+}
@@ -0,0 +1,686 @@
+//! Reading and editing `server.properties` and the compose environment of the minecraft service,
+//! within a schema so a typo can't keep the server from starting.
+//!
+//! Both files are edited line by line, so comments, order and formatting survive.
+
+use crate::ssh;
+use common::{ConfigChange, ConfigEdit, ConfigEntry, ConfigKind, ConfigReview, ConfigSource};
+use std::io;
+use std::ops::Range;
+use std::sync::Mutex;
+
+/// Held from reading the compose file to writing it back, since config and roster edits both
+/// rewrite it from their own blocking tasks.
+pub(crate) static COMPOSE_EDIT: Mutex<()> = Mutex::new(());
+
+/// A setting admins may edit.
+struct Setting {
+    source: ConfigSource,
+    key: &'static str,
+    kind: ConfigKind,
+    description: &'static str,
+}
+
+fn schema() -> Vec<Setting> {
+    use ConfigKind::*;
+    use ConfigSource::*;
+
+    let setting = |source, key, kind, description| Setting {
+        source,
+        key,
+        kind,
+        description,
+    };
+    let difficulty = || {
+        Choice(
+            ["peaceful", "easy", "normal", "hard"]
+                .map(String::from)
+                .to_vec(),
+        )
+    };
+    vec![
+        setting(
+            Environment,
+            "MOTD",
+            Text { max_len: 256 },
+            "the message in the server list",
+        ),
+        setting(Environment, "MEMORY", Memory, "the JVM heap, e.g. 2G"),
+        setting(
+            Environment,
+            "VIEW_DISTANCE",
+            Integer { min: 3, max: 32 },
+            "chunks sent to players",
+        ),
+        setting(
+            Environment,
+            "SIMULATION_DISTANCE",
+            Integer { min: 3, max: 32 },
+            "chunks that tick around players",
+        ),
+        setting(
+            Environment,
+            "MAX_PLAYERS",
+            Integer { min: 1, max: 100 },
+            "players online at once",
+        ),
+        setting(
+            Environment,
+            "DIFFICULTY",
+            difficulty(),
+            "the world's difficulty",
+        ),
+        setting(
+            Environment,
+            "PVP",
+            Boolean,
+            "whether players can hurt each other",
+        ),
+        setting(
+            Environment,
+            "ALLOW_FLIGHT",
+            Boolean,
+            "whether flying mods get players kicked",
+        ),
+        setting(
+            Environment,
+            "SEED",
+            Text { max_len: 64 },
+            "the seed of newly generated worlds",
+        ),
+        setting(
+            Properties,
+            "motd",
+            Text { max_len: 256 },
+            "the message in the server list",
+        ),
+        setting(
+            Properties,
+            "view-distance",
+            Integer { min: 3, max: 32 },
+            "chunks sent to players",
+        ),
+        setting(
+            Properties,
+            "simulation-distance",
+            Integer { min: 3, max: 32 },
+            "chunks that tick around players",
+        ),
+        setting(
+            Properties,
+            "max-players",
+            Integer { min: 1, max: 100 },
+            "players online at once",
+        ),
+        setting(
+            Properties,
+            "difficulty",
+            difficulty(),
+            "the world's difficulty",
+        ),
+        setting(
+            Properties,
+            "pvp",
+            Boolean,
+            "whether players can hurt each other",
+        ),
+        setting(
+            Properties,
+            "allow-flight",
+            Boolean,
+            "whether flying mods get players kicked",
+        ),
+        setting(
+            Properties,
+            "spawn-protection",
+            Integer { min: 0, max: 64 },
+            "blocks around spawn only ops can change",
+        ),
+        setting(
+            Properties,
+            "white-list",
+            Boolean,
+            "whether only whitelisted players can join",
+        ),
+        setting(
+            Properties,
+            "enable-command-block",
+            Boolean,
+            "whether command blocks work",
+        ),
+    ]
+}
+
+/// The compose environment variable the image writes over `property` with on every start.
+fn overridden_by(property: &str) -> String {
+    match property {
+        "level-seed" => "SEED".to_string(),
+        "white-list" => "ENABLE_WHITELIST".to_string(),
+        _ => property.to_uppercase().replace('-', "_"),
+    }
+}
+
+/// Checks `value` against `kind` and brings it into the shape its file uses.
+fn validate(kind: &ConfigKind, source: ConfigSource, value: &str) -> Result<String, String> {
+    let value = value.trim();
+    match kind {
+        ConfigKind::Text { max_len } => {
+            if value.contains(['\n', '\r']) {
+                Err("must be a single line".to_string())
+            } else if value.chars().count() > *max_len as usize {
+                Err(format!("must be at most {max_len} characters"))
+            } else {
+                Ok(value.to_string())
+            }
+        }
+        ConfigKind::Integer { min, max } => match value.parse::<i64>() {
+            Ok(n) if (*min..=*max).contains(&n) => Ok(n.to_string()),
+            _ => Err(format!("must be a whole number from {min} to {max}")),
+        },
+        ConfigKind::Boolean => match (value.to_lowercase().as_str(), source) {
+            ("true" | "false", ConfigSource::Properties) => Ok(value.to_lowercase()),
+            ("true" | "false", ConfigSource::Environment) => Ok(value.to_uppercase()),
+            _ => Err("must be true or false".to_string()),
+        },
+        ConfigKind::Choice(choices) => {
+            let value = value.to_lowercase();
+            if choices.contains(&value) {
+                Ok(value)
+            } else {
+                Err(format!("must be one of {}", choices.join(", ")))
+            }
+        }
+        ConfigKind::Memory => {
+            let upper = value.to_uppercase();
+            let amount = upper
+                .strip_suffix('G')
+                .map(|n| (n, 1024))
+                .or_else(|| upper.strip_suffix('M').map(|n| (n, 1)))
+                .and_then(|(n, unit)| Some(n.parse::<u64>().ok()? * unit));
+            match amount {
+                Some(megs) if (512..=32 * 1024).contains(&megs) => Ok(upper),
+                _ => Err("must be between 512M and 32G, like 2G or 1536M".to_string()),
+            }
+        }
+    }
+}
+
+/// `server.properties`.
+#[derive(Debug)]
+struct PropertiesFile(Vec<String>);
+
+impl PropertiesFile {
+    fn parse(s: &str) -> Self {
+        Self(s.lines().map(str::to_string).collect())
+    }
+
+    fn find(&self, key: &str) -> Option<usize> {
+        self.0.iter().position(|line| {
+            !line.trim_start().starts_with('#')
+                && matches!(line.split_once('='), Some((k, _)) if k.trim() == key)
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        let line = &self.0[self.find(key)?];
+        line.split_once('=')
+            .map(|(_, v)| unescape_property(v.trim_start()))
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let line = format!("{key}={}", escape_property(value));
+        match self.find(key) {
+            Some(i) => self.0[i] = line,
+            None => self.0.push(line),
+        }
+    }
+
+    fn render(&self) -> String {
+        self.0.iter().map(|line| format!("{line}\n")).collect()
+    }
+}
+
+/// Java properties escape `\`, `:`, `=` and, as the image writes them, anything not ascii.
+fn escape_property(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' | ':' | '=' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_ascii() => escaped.push(c),
+            c => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    escaped.push_str(&format!("\\u{unit:04x}"));
+                }
+            }
+        }
+    }
+    escaped
+}
+
+fn unescape_property(value: &str) -> String {
+    let mut units = Vec::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('u')) => {
+                chars.next();
+                let hex: String = chars.by_ref().take(4).collect();
+                units.extend(u16::from_str_radix(&hex, 16));
+            }
+            ('\\', Some(next)) => {
+                chars.next();
+                units.extend(next.encode_utf16(&mut [0; 2]).iter().copied());
+            }
+            (c, _) => units.extend(c.encode_utf16(&mut [0; 2]).iter().copied()),
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+/// The `environment` mapping of one service in a compose file.
+#[derive(Debug)]
+pub(crate) struct ComposeEnv {
+    lines: Vec<String>,
+    /// The lines of the mapping's entries.
+    entries: Range<usize>,
+    indent: String,
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+impl ComposeEnv {
+    pub(crate) fn parse(s: &str, service: &str) -> Result<Self, String> {
+        let lines: Vec<String> = s.lines().map(str::to_string).collect();
+        let service_line = lines
+            .iter()
+            .position(|l| l.trim() == format!("{service}:"))
+            .ok_or_else(|| format!("there is no `{service}` service"))?;
+        let service_indent = indent_of(&lines[service_line]);
+
+        let mut environment = None;
+        for (i, line) in lines.iter().enumerate().skip(service_line + 1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if indent_of(line) <= service_indent {
+                break;
+            }
+            if line.trim() == "environment:" {
+                environment = Some(i);
+                break;
+            }
+        }
+        let environment = environment
+            .ok_or_else(|| format!("the `{service}` service has no environment mapping"))?;
+        let environment_indent = indent_of(&lines[environment]);
+
+        let start = environment + 1;
+        let mut end = start;
+        for (i, line) in lines.iter().enumerate().skip(start) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if indent_of(line) <= environment_indent {
+                break;
+            }
+            if line.trim_start().starts_with("- ") {
+                return Err("the environment has to be a mapping, not a list".to_string());
+            }
+            end = i + 1;
+        }
+        let indent = match lines.get(start) {
+            Some(first) if start < end => first[..indent_of(first)].to_string(),
+            _ => " ".repeat(environment_indent + 2),
+        };
+
+        Ok(Self {
+            lines,
+            entries: start..end,
+            indent,
+        })
+    }
+
+    fn find(&self, key: &str) -> Option<usize> {
+        self.entries
+            .clone()
+            .find(|i| matches!(self.lines[*i].split_once(':'), Some((k, _)) if k.trim() == key))
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<String> {
+        let (_, value) = self.lines[self.find(key)?].split_once(':')?;
+        Some(unquote_yaml(value.trim()).replace("$$", "$"))
+    }
+
+    pub(crate) fn set(&mut self, key: &str, value: &str) {
+        // compose would interpolate `$VAR` in the value otherwise
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "$$");
+        let line = format!("{}{key}: \"{escaped}\"", self.indent);
+        match self.find(key) {
+            Some(i) => self.lines[i] = line,
+            None => {
+                self.lines.insert(self.entries.end, line);
+                self.entries.end += 1;
+            }
+        }
+    }
+
+    pub(crate) fn render(&self) -> String {
+        self.lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+}
+
+fn unquote_yaml(value: &str) -> String {
+    if let Some(quoted) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        let mut unquoted = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unquoted.extend(chars.next()),
+                c => unquoted.push(c),
+            }
+        }
+        unquoted
+    } else if let Some(quoted) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        quoted.replace("''", "'")
+    } else {
+        match value.split_once(" #") {
+            Some((value, _comment)) => value.trim_end().to_string(),
+            None => value.to_string(),
+        }
+    }
+}
+
+/// Both files as they are on the host.
+struct Loaded {
+    properties: PropertiesFile,
+    environment: ComposeEnv,
+}
+
+impl Loaded {
+    fn get(&self, source: ConfigSource, key: &str) -> Option<String> {
+        match source {
+            ConfigSource::Properties => self.properties.get(key),
+            ConfigSource::Environment => self.environment.get(key),
+        }
+    }
+
+    fn entries(&self) -> Vec<ConfigEntry> {
+        schema()
+            .into_iter()
+            .map(|s| ConfigEntry {
+                value: self.get(s.source, s.key),
+                source: s.source,
+                key: s.key.to_string(),
+                kind: s.kind,
+                description: s.description.to_string(),
+            })
+            .collect()
+    }
+
+    /// Validates `edits` and applies the valid ones in memory.
+    fn review(&mut self, edits: &[ConfigEdit]) -> ConfigReview {
+        let schema = schema();
+        let mut changes = Vec::new();
+        let mut problems = Vec::new();
+
+        for edit in edits {
+            let setting = match schema
+                .iter()
+                .find(|s| s.source == edit.source && s.key == edit.key)
+            {
+                Some(setting) => setting,
+                None => {
+                    problems.push(format!("`{}` is not a setting sentinel knows", edit.key));
+                    continue;
+                }
+            };
+            let new = match validate(&setting.kind, edit.source, &edit.value) {
+                Ok(new) => new,
+                Err(problem) => {
+                    problems.push(format!("`{}` {problem}", edit.key));
+                    continue;
+                }
+            };
+            if edit.source == ConfigSource::Properties {
+                let variable = overridden_by(&edit.key);
+                if self.environment.get(&variable).is_some() {
+                    problems.push(format!(
+                        "`{}` is overwritten by `{variable}` from the compose environment on every start, edit that instead",
+                        edit.key
+                    ));
+                    continue;
+                }
+            }
+
+            let old = self.get(edit.source, &edit.key);
+            if old.as_deref() == Some(new.as_str()) {
+                continue;
+            }
+            match edit.source {
+                ConfigSource::Properties => self.properties.set(&edit.key, &new),
+                ConfigSource::Environment => self.environment.set(&edit.key, &new),
+            }
+            changes.push(ConfigChange {
+                source: edit.source,
+                key: edit.key.clone(),
+                old,
+                new,
+            });
+        }
+
+        ConfigReview {
+            changes,
+            problems,
+            applied: false,
+        }
+    }
+}
+
+/// Where the files are, see the README for the env vars.
+#[derive(Clone, Debug)]
+pub struct ConfigFiles {
+    pub properties: String,
+    pub compose: String,
+    pub service: String,
+}
+
+impl ConfigFiles {
+    pub fn from_env() -> Self {
+        let data_dir = dotenv::var("MC_DATA_DIR").unwrap_or_else(|_| "/opt/mc".to_string());
+        let compose_dir = dotenv::var("COMPOSE_DIR").unwrap_or_else(|_| "/root".to_string());
+        Self {
+            properties: format!("{data_dir}/server.properties"),
+            compose: format!("{compose_dir}/docker-compose.yml"),
+            service: dotenv::var("COMPOSE_SERVICE").unwrap_or_else(|_| "mc".to_string()),
+        }
+    }
+
+    /// Blocking.
+    fn load(&self, sess: &ssh2::Session) -> io::Result<Loaded> {
+        let properties = ssh::run(sess, &format!("cat {}", ssh::quote(&self.properties)))?;
+        let compose = ssh::run(sess, &format!("cat {}", ssh::quote(&self.compose)))?;
+        Ok(Loaded {
+            properties: PropertiesFile::parse(&properties),
+            environment: ComposeEnv::parse(&compose, &self.service)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        })
+    }
+
+    /// Every setting in the schema with its current value.
+    pub async fn read(&self) -> io::Result<Vec<ConfigEntry>> {
+        let files = self.clone();
+        tokio::task::spawn_blocking(move || Ok(files.load(&ssh::connect()?)?.entries()))
+            .await
+            .expect("reading the config doesn't panic")
+    }
+
+    /// What `edits` would change, written to the host if `apply` and there are no problems.
+    pub async fn review(&self, edits: Vec<ConfigEdit>, apply: bool) -> io::Result<ConfigReview> {
+        let files = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let sess = ssh::connect()?;
+            let _editing = COMPOSE_EDIT.lock().unwrap();
+            let mut loaded = files.load(&sess)?;
+            let mut review = loaded.review(&edits);
+            if apply && review.problems.is_empty() && !review.changes.is_empty() {
+                let touched = |source| review.changes.iter().any(|c| c.source == source);
+                if touched(ConfigSource::Properties) {
+                    ssh::write(&sess, &files.properties, &loaded.properties.render())?;
+                }
+                if touched(ConfigSource::Environment) {
+                    ssh::write(&sess, &files.compose, &loaded.environment.render())?;
+                }
+                review.applied = true;
+            }
+            Ok(review)
+        })
+        .await
+        .expect("reviewing the config doesn't panic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPOSE: &str = r#"version: "3"
+
+services:
+    mc:
+      restart: always
+      image: itzg/minecraft-server
+      environment:
+          MOTD: "§lEqual§r §2Equal§r"
+          MEMORY: "2G"
+          VIEW_DISTANCE: 20 # chunks
+          TYPE: 'FORGE'
+      volumes:
+          - /opt/mc:/data
+"#;
+
+    const PROPERTIES: &str = "#Minecraft server properties
+motd=\\u00A7lEqual\\u00A7r
+pvp=true
+level-seed=200
+";
+
+    fn loaded() -> Loaded {
+        Loaded {
+            properties: PropertiesFile::parse(PROPERTIES),
+            environment: ComposeEnv::parse(COMPOSE, "mc").unwrap(),
+        }
+    }
+
+    fn edit(source: ConfigSource, key: &str, value: &str) -> ConfigEdit {
+        ConfigEdit {
+            source,
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_read() {
+        let loaded = loaded();
+        assert_eq!(
+            loaded.environment.get("MOTD").unwrap(),
+            "§lEqual§r §2Equal§r"
+        );
+        assert_eq!(loaded.environment.get("VIEW_DISTANCE").unwrap(), "20");
+        assert_eq!(loaded.environment.get("TYPE").unwrap(), "FORGE");
+        assert_eq!(loaded.environment.get("SEED"), None);
+        assert_eq!(loaded.properties.get("motd").unwrap(), "§lEqual§r");
+        assert_eq!(loaded.properties.get("difficulty"), None);
+
+        let entries = loaded.entries();
+        let memory = entries.iter().find(|e| e.key == "MEMORY").unwrap();
+        assert_eq!(memory.value.as_deref(), Some("2G"));
+    }
+
+    #[test]
+    fn test_review_and_write() {
+        let mut loaded = loaded();
+        let review = loaded.review(&[
+            edit(ConfigSource::Environment, "VIEW_DISTANCE", "16"),
+            edit(ConfigSource::Environment, "MEMORY", "2g"),
+            edit(ConfigSource::Environment, "PVP", "false"),
+            edit(ConfigSource::Properties, "difficulty", "Hard"),
+            edit(ConfigSource::Properties, "motd", "hi: there"),
+            edit(ConfigSource::Environment, "SIMULATION_DISTANCE", "64"),
+            edit(ConfigSource::Environment, "TYPE", "FABRIC"),
+        ]);
+
+        let changed: Vec<_> = review
+            .changes
+            .iter()
+            .map(|c| (c.key.as_str(), c.old.as_deref(), c.new.as_str()))
+            .collect();
+        // the memory is the same once normalized
+        assert_eq!(
+            changed,
+            [
+                ("VIEW_DISTANCE", Some("20"), "16"),
+                ("PVP", None, "FALSE"),
+                ("difficulty", None, "hard"),
+            ]
+        );
+        assert_eq!(
+            review.problems,
+            [
+                "`motd` is overwritten by `MOTD` from the compose environment on every start, edit that instead",
+                "`SIMULATION_DISTANCE` must be a whole number from 3 to 32",
+                "`TYPE` is not a setting sentinel knows",
+            ]
+        );
+
+        let compose = loaded.environment.render();
+        assert!(compose.contains("          VIEW_DISTANCE: \"16\"\n          TYPE: 'FORGE'\n          PVP: \"FALSE\"\n      volumes:\n"));
+        assert!(compose.starts_with("version: \"3\"\n\nservices:\n"));
+        assert_eq!(
+            loaded.properties.render(),
+            format!("{PROPERTIES}difficulty=hard\n")
+        );
+    }
+
+    #[test]
+    fn test_dollars() {
+        let mut env = ComposeEnv::parse(COMPOSE, "mc").unwrap();
+        env.set("MOTD", "costs $5, \"${HOME}\"");
+        assert!(env
+            .render()
+            .contains("          MOTD: \"costs $$5, \\\"$${HOME}\\\"\"\n"));
+        assert_eq!(env.get("MOTD").unwrap(), "costs $5, \"${HOME}\"");
+    }
+
+    #[test]
+    fn test_validate() {
+        let env = ConfigSource::Environment;
+        assert_eq!(
+            validate(&ConfigKind::Memory, env, "1536m").unwrap(),
+            "1536M"
+        );
+        assert!(validate(&ConfigKind::Memory, env, "256M").is_err());
+        assert!(validate(&ConfigKind::Memory, env, "lots").is_err());
+        assert!(validate(&ConfigKind::Text { max_len: 5 }, env, "a\nb").is_err());
+        assert_eq!(
+            validate(&ConfigKind::Boolean, ConfigSource::Properties, "TRUE").unwrap(),
+            "true"
+        );
+    }
+
+    #[test]
+    fn test_property_escapes() {
+        let motd = "§lEqual: a=b \\o/";
+        assert_eq!(escape_property(motd), "\\u00a7lEqual\\: a\\=b \\\\o/");
+        assert_eq!(unescape_property(&escape_property(motd)), motd);
+    }
+}
@@ -0,0 +1,402 @@
+//! Noticing the minecraft server crash on its own, fetching its crash report and restarting it.
+
+use crate::ssh;
+use common::{ContainerStatus, CrashReport, ServerStatus};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// Crash reports are cut short after this many bytes.
+const REPORT_LIMIT: usize = 64 * 1024;
+
+/// A crash report older than this when the crash is noticed belongs to an older crash.
+const REPORT_FRESHNESS_MINS: u64 = 10;
+
+/// See the README for the env vars.
+#[derive(Clone, Debug)]
+pub struct CrashPolicy {
+    /// How many automatic restarts are allowed within `window`, 0 only reports crashes.
+    pub restarts: u32,
+    pub window: Duration,
+    /// Downtime this soon after a power command or a restart isn't a crash.
+    pub quiet: Duration,
+    pub container: String,
+    /// Where the container's `/data` is mounted on the host.
+    pub data_dir: String,
+    /// Keep polling while nobody is watching, so crashes are noticed before someone tries to join.
+    pub unattended: bool,
+}
+
+impl Default for CrashPolicy {
+    fn default() -> Self {
+        Self {
+            restarts: 3,
+            window: Duration::from_secs(60 * 60),
+            quiet: Duration::from_secs(5 * 60),
+            container: "root-mc-1".to_string(),
+            data_dir: "/opt/mc".to_string(),
+            unattended: true,
+        }
+    }
+}
+
+impl CrashPolicy {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let secs = |name, default: Duration| {
+            Duration::from_secs(
+                dotenv::var(name)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default.as_secs()),
+            )
+        };
+        Self {
+            restarts: dotenv::var("CRASH_RESTARTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.restarts),
+            window: secs("CRASH_RESTART_WINDOW_SECS", default.window),
+            quiet: secs("CRASH_QUIET_SECS", default.quiet),
+            container: dotenv::var("MC_CONTAINER").unwrap_or(default.container),
+            data_dir: dotenv::var("MC_DATA_DIR").unwrap_or(default.data_dir),
+            unattended: dotenv::var("CRASH_WATCH_UNATTENDED")
+                .map(|v| v != "false")
+                .unwrap_or(default.unattended),
+        }
+    }
+}
+
+/// Something that happened to the container without anyone asking for it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Incident {
+    /// It was up and now it isn't.
+    Exited,
+    /// Its uptime went back, docker restarted it after it exited.
+    Restarted,
+    /// Its health check started failing.
+    Unhealthy,
+}
+
+impl Incident {
+    fn describe(&self) -> &'static str {
+        match self {
+            Incident::Exited => "the container exited",
+            Incident::Restarted => "the container exited and docker restarted it",
+            Incident::Unhealthy => "the container became unhealthy",
+        }
+    }
+}
+
+struct Watched {
+    last: Option<ServerStatus>,
+    quiet_until: Option<Instant>,
+    restarts: VecDeque<Instant>,
+    last_report: Option<String>,
+}
+
+/// Looks at every polled status for crashes and publishes a [`CrashReport`] for each.
+pub struct CrashWatch {
+    policy: CrashPolicy,
+    watched: Mutex<Watched>,
+    published: watch::Sender<Option<CrashReport>>,
+}
+
+impl CrashWatch {
+    pub fn new(policy: CrashPolicy) -> Arc<Self> {
+        Arc::new(Self {
+            policy,
+            watched: Mutex::new(Watched {
+                last: None,
+                quiet_until: None,
+                restarts: VecDeque::new(),
+                last_report: None,
+            }),
+            published: watch::channel(None).0,
+        })
+    }
+
+    pub fn unattended(&self) -> bool {
+        self.policy.unattended
+    }
+
+    /// The latest crash, updated with every new one.
+    pub fn subscribe(&self) -> watch::Receiver<Option<CrashReport>> {
+        self.published.subscribe()
+    }
+
+    /// The server is about to go down on purpose, after a power command.
+    pub fn expect_downtime(&self) {
+        self.watched.lock().unwrap().quiet_until = Some(Instant::now() + self.policy.quiet);
+    }
+
+    /// Handles any incident `status` reveals in the background.
+    pub fn observe(self: &Arc<Self>, status: &ServerStatus) {
+        if let Some(incident) = self.incident(status) {
+            tokio::spawn(self.clone().handle(incident));
+        }
+    }
+
+    fn incident(&self, status: &ServerStatus) -> Option<Incident> {
+        if status.container == ContainerStatus::Unknown {
+            // the poll failed, that says nothing about the container
+            return None;
+        }
+        let mut watched = self.watched.lock().unwrap();
+        let previous = watched.last.replace(status.clone())?;
+        if matches!(watched.quiet_until, Some(until) if Instant::now() < until)
+            || previous.host != "running"
+            || status.host != "running"
+        {
+            return None;
+        }
+
+        match (&previous.container, &status.container) {
+            (ContainerStatus::Up(_), ContainerStatus::NotUp) => Some(Incident::Exited),
+            (ContainerStatus::Up(before), ContainerStatus::Up(now)) => {
+                match (uptime(before), uptime(now)) {
+                    (Some(before), Some(now)) if now < before => Some(Incident::Restarted),
+                    _ if unhealthy(now) && !unhealthy(before) => Some(Incident::Unhealthy),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Takes one of the restarts left in the window, returning which one it was.
+    fn take_restart(&self) -> Option<usize> {
+        let mut watched = self.watched.lock().unwrap();
+        let now = Instant::now();
+        while matches!(watched.restarts.front(), Some(at) if now.duration_since(*at) >= self.policy.window)
+        {
+            watched.restarts.pop_front();
+        }
+        if watched.restarts.len() >= self.policy.restarts as usize {
+            return None;
+        }
+        watched.restarts.push_back(now);
+        // the restart itself takes the container down
+        watched.quiet_until = Some(now + self.policy.quiet);
+        Some(watched.restarts.len())
+    }
+
+    async fn handle(self: Arc<Self>, incident: Incident) {
+        let attempt = match incident {
+            Incident::Restarted => None,
+            Incident::Exited | Incident::Unhealthy => self.take_restart(),
+        };
+        let policy = self.policy.clone();
+        let fetched = tokio::task::spawn_blocking(move || {
+            let sess = ssh::connect()?;
+            let report = newest_report(&sess, &policy.data_dir);
+            let restarted = attempt.map(|_| {
+                ssh::run(
+                    &sess,
+                    &format!("docker restart {}", ssh::quote(&policy.container)),
+                )
+            });
+            Ok::<_, io::Error>((report, restarted))
+        })
+        .await
+        .expect("the crash handling doesn't panic");
+
+        let (report, restarted) = match fetched {
+            Ok(fetched) => fetched,
+            Err(e) => (Err(e), None),
+        };
+        let report = match report {
+            Ok(Some((name, content))) => {
+                let mut watched = self.watched.lock().unwrap();
+                if watched.last_report.as_deref() == Some(&name) {
+                    None
+                } else {
+                    watched.last_report = Some(name);
+                    Some(content)
+                }
+            }
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!("failed to fetch the crash report: {e}");
+                None
+            }
+        };
+
+        let action = match (incident, attempt, restarted) {
+            (Incident::Restarted, _, _) => "none needed".to_string(),
+            (_, Some(n), Some(Ok(_))) => format!(
+                "restarted the container ({n} of {} allowed in {} minutes)",
+                self.policy.restarts,
+                self.policy.window.as_secs() / 60
+            ),
+            (_, Some(_), Some(Err(e))) => format!("failed to restart the container: {e}"),
+            (_, Some(_), None) => "couldn't reach the host to restart the container".to_string(),
+            (_, None, _) if self.policy.restarts == 0 => "automatic restarts are off".to_string(),
+            (_, None, _) => format!(
+                "gave up after {} restarts in {} minutes, someone has to look at it",
+                self.policy.restarts,
+                self.policy.window.as_secs() / 60
+            ),
+        };
+        tracing::error!("{}, {action}", incident.describe());
+
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.published.send_replace(Some(CrashReport {
+            at,
+            incident: incident.describe().to_string(),
+            action,
+            report,
+        }));
+    }
+}
+
+fn unhealthy(status: &str) -> bool {
+    status.contains("(unhealthy)")
+}
+
+/// Seconds a container has been up, from the status `docker container ls` shows, e.g.
+/// `Up 2 hours (healthy)` or `Up About a minute`.
+fn uptime(status: &str) -> Option<u64> {
+    let mut words = status.trim().strip_prefix("Up ")?.split_whitespace();
+    let (amount, unit) = match words.next()? {
+        "Less" => return Some(0),
+        "About" => (1, words.nth(1)?),
+        n => (n.parse().ok()?, words.next()?),
+    };
+    let unit = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(amount * unit)
+}
+
+/// The name and the content of the newest crash report, if one was written lately. Blocking.
+fn newest_report(sess: &ssh2::Session, data_dir: &str) -> io::Result<Option<(String, String)>> {
+    let dir = format!("{data_dir}/crash-reports");
+    let newest = ssh::run(
+        sess,
+        &format!(
+            "find {} -maxdepth 1 -type f -mmin -{REPORT_FRESHNESS_MINS} -printf '%T@ %f\\n' | sort -rn | head -n 1",
+            ssh::quote(&dir)
+        ),
+    )?;
+    let name = match newest.trim().split_once(' ') {
+        Some((_, name)) => name.to_string(),
+        None => return Ok(None),
+    };
+    let content = ssh::run(
+        sess,
+        &format!(
+            "head -c {REPORT_LIMIT} {}",
+            ssh::quote(&format!("{dir}/{name}"))
+        ),
+    )?;
+    Ok(Some((name, content)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::OnlinePeople;
+
+    fn status(container: ContainerStatus) -> ServerStatus {
+        ServerStatus {
+            host: "running".to_string(),
+            container,
+            online: OnlinePeople::Unknown,
+        }
+    }
+
+    fn up(s: &str) -> ServerStatus {
+        status(ContainerStatus::Up(s.to_string()))
+    }
+
+    #[test]
+    fn test_uptime() {
+        assert_eq!(uptime("Up Less than a second"), Some(0));
+        assert_eq!(uptime("Up 6 seconds (health: starting)   "), Some(6));
+        assert_eq!(uptime("Up About a minute"), Some(60));
+        assert_eq!(uptime("Up About an hour (healthy)"), Some(3600));
+        assert_eq!(uptime("Up 2 hours (unhealthy)"), Some(7200));
+        assert_eq!(uptime("Exited (1) 2 minutes ago"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_incidents() {
+        let watch = CrashWatch::new(CrashPolicy::default());
+        assert_eq!(watch.incident(&up("Up 2 hours (healthy)")), None);
+        assert_eq!(watch.incident(&up("Up 3 hours (healthy)")), None);
+        assert_eq!(
+            watch.incident(&up("Up 3 hours (unhealthy)")),
+            Some(Incident::Unhealthy)
+        );
+        // still unhealthy is the same incident
+        assert_eq!(watch.incident(&up("Up 3 hours (unhealthy)")), None);
+        assert_eq!(
+            watch.incident(&up("Up 4 seconds (health: starting)")),
+            Some(Incident::Restarted)
+        );
+        // a failed poll is no news
+        assert_eq!(watch.incident(&status(ContainerStatus::Unknown)), None);
+        assert_eq!(
+            watch.incident(&status(ContainerStatus::NotUp)),
+            Some(Incident::Exited)
+        );
+
+        // the host going down takes the container with it
+        watch.incident(&up("Up 2 hours (healthy)"));
+        let stopping = ServerStatus {
+            host: "stopping".to_string(),
+            ..status(ContainerStatus::NotUp)
+        };
+        assert_eq!(watch.incident(&stopping), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expected_downtime_is_no_crash() {
+        let watch = CrashWatch::new(CrashPolicy::default());
+        watch.incident(&up("Up 2 hours (healthy)"));
+        watch.expect_downtime();
+        assert_eq!(watch.incident(&status(ContainerStatus::NotUp)), None);
+
+        tokio::time::advance(watch.policy.quiet).await;
+        watch.incident(&up("Up 5 minutes (healthy)"));
+        assert_eq!(
+            watch.incident(&status(ContainerStatus::NotUp)),
+            Some(Incident::Exited)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restarts_are_bounded() {
+        let watch = CrashWatch::new(CrashPolicy::default());
+        assert_eq!(watch.take_restart(), Some(1));
+        tokio::time::advance(Duration::from_secs(20 * 60)).await;
+        assert_eq!(watch.take_restart(), Some(2));
+        assert_eq!(watch.take_restart(), Some(3));
+        assert_eq!(watch.take_restart(), None);
+
+        // the first one left the window
+        tokio::time::advance(Duration::from_secs(40 * 60)).await;
+        assert_eq!(watch.take_restart(), Some(3));
+        assert_eq!(watch.take_restart(), None);
+
+        let off = CrashWatch::new(CrashPolicy {
+            restarts: 0,
+            ..CrashPolicy::default()
+        });
+        assert_eq!(off.take_restart(), None);
+    }
+}
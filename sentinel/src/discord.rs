@@ -0,0 +1,158 @@
+//! A discord bot front end, for members who never open the dashboard.
+//!
+//! Slash commands map onto [`ClientOpt`] and go through [`Commander::execute`],
+//! just like the websocket does.
+
+use crate::command::{Caller, Commander};
+use common::{ClientOpt, ContainerStatus, OnlinePeople, Role, ServerStatus};
+use serenity::async_trait;
+use serenity::model::gateway::Ready;
+use serenity::model::id::UserId;
+use serenity::model::interactions::application_command::{
+    ApplicationCommand, ApplicationCommandInteraction,
+};
+use serenity::model::interactions::{Interaction, InteractionResponseType};
+use serenity::prelude::*;
+
+struct Handler {
+    commander: Commander,
+    admins: Vec<UserId>,
+    members: Vec<UserId>,
+}
+
+/// comma separated discord user ids
+fn user_ids(var: &str) -> Vec<UserId> {
+    dotenv::var(var)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse::<u64>().ok())
+        .map(UserId)
+        .collect()
+}
+
+impl Handler {
+    fn role_of(&self, user: UserId) -> Role {
+        if self.admins.contains(&user) {
+            Role::Admin
+        } else if self.members.contains(&user) {
+            Role::Member
+        } else {
+            Role::Viewer
+        }
+    }
+
+    async fn reply(&self, command: &ApplicationCommandInteraction) -> (String, bool) {
+        let caller = Caller {
+            name: format!("discord user {} ({})", command.user.tag(), command.user.id),
+            role: self.role_of(command.user.id),
+        };
+
+        let opt = match command.data.name.as_str() {
+            "status" => {
+                let polled = self.commander.status().await;
+                return (describe(polled.map(|p| p.status)), false);
+            }
+            "start" => ClientOpt::On,
+            "stop" => ClientOpt::Off,
+            "reboot" => ClientOpt::Reboot,
+            _ => return ("owo what's this".to_string(), true),
+        };
+
+        match self.commander.execute(opt, &caller).await {
+            Ok(feedback) => (feedback, false),
+            Err(e) => (e.to_string(), true),
+        }
+    }
+}
+
+fn describe(status: Option<ServerStatus>) -> String {
+    match status {
+        None => "sentinel failed to reach the server".to_string(),
+        Some(ServerStatus {
+            host,
+            container,
+            online,
+        }) => {
+            let container = match container {
+                ContainerStatus::Unknown => "unknown".to_string(),
+                ContainerStatus::NotUp => "not up".to_string(),
+                ContainerStatus::Up(s) => s,
+            };
+            let online = match online {
+                OnlinePeople::Unknown => "unknown".to_string(),
+                OnlinePeople::Known(s) => s,
+            };
+            format!("**Host:** {host}\n**Container:** {container}\n**Online:** {online}")
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        tracing::info!("discord bot connected as {}", ready.user.name);
+
+        let registered = ApplicationCommand::set_global_application_commands(&ctx.http, |commands| {
+            commands
+                .create_application_command(|c| {
+                    c.name("status").description("Show the status of the server")
+                })
+                .create_application_command(|c| c.name("start").description("Power on the server"))
+                .create_application_command(|c| c.name("stop").description("Power off the server"))
+                .create_application_command(|c| c.name("reboot").description("Reboot the server"))
+        })
+        .await;
+
+        if let Err(e) = registered {
+            tracing::error!("failed to register discord slash commands: {e}");
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::ApplicationCommand(command) = interaction {
+            let (content, ephemeral) = self.reply(&command).await;
+            let responded = command
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|message| {
+                            message.content(content).ephemeral(ephemeral)
+                        })
+                })
+                .await;
+            if let Err(e) = responded {
+                tracing::warn!("failed to respond to a discord slash command: {e}");
+            }
+        }
+    }
+}
+
+/// Runs the bot until it dies. Never returns if `DISCORD_TOKEN` isn't set.
+pub async fn run(commander: Commander) {
+    let token = match dotenv::var("DISCORD_TOKEN") {
+        Ok(token) => token,
+        Err(_) => {
+            tracing::info!("DISCORD_TOKEN is not set, the discord bot stays asleep");
+            return futures::future::pending().await;
+        }
+    };
+
+    let handler = Handler {
+        commander,
+        admins: user_ids("DISCORD_ADMINS"),
+        members: user_ids("DISCORD_MEMBERS"),
+    };
+
+    let bot = serenity::Client::builder(token, GatewayIntents::empty())
+        .event_handler(handler)
+        .await;
+
+    match bot {
+        Ok(mut bot) => {
+            if let Err(e) = bot.start().await {
+                tracing::error!("discord bot stopped: {e}");
+            }
+        }
+        Err(e) => tracing::error!("failed to create the discord bot: {e}"),
+    }
+}
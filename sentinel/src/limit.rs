@@ -0,0 +1,327 @@
+//! Keeping password guessers and button mashers at bay.
+//!
+//! Wrong passwords are counted per ip and globally. After the free attempts run out the
+//! offender is locked out, twice as long for every further wrong password.
+//! Power commands share a single cooldown, admins excepted.
+
+use common::Lockout;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// See the README for the env vars.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Wrong passwords one ip gets before it's locked out.
+    pub free_attempts: u32,
+    /// Wrong passwords from all ips together before everyone is locked out.
+    pub global_free_attempts: u32,
+    /// The first lockout, doubled for every further wrong password.
+    pub base_lockout: Duration,
+    pub max_lockout: Duration,
+    /// Failures are forgotten after this long without a new one.
+    pub forget_after: Duration,
+    /// Minimum time between two power commands.
+    pub power_cooldown: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            global_free_attempts: 20,
+            base_lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(60 * 60),
+            forget_after: Duration::from_secs(60 * 60),
+            power_cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+fn var<T: FromStr>(name: &str, default: T) -> T {
+    dotenv::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+impl Limits {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let secs = |name, default: Duration| Duration::from_secs(var(name, default.as_secs()));
+        Self {
+            free_attempts: var("AUTH_FREE_ATTEMPTS", default.free_attempts),
+            global_free_attempts: var("AUTH_GLOBAL_FREE_ATTEMPTS", default.global_free_attempts),
+            base_lockout: secs("AUTH_BASE_LOCKOUT_SECS", default.base_lockout),
+            max_lockout: secs("AUTH_MAX_LOCKOUT_SECS", default.max_lockout),
+            forget_after: secs("AUTH_FORGET_AFTER_SECS", default.forget_after),
+            power_cooldown: secs("POWER_COOLDOWN_SECS", default.power_cooldown),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Strikes {
+    failures: u32,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl Strikes {
+    fn strike(&mut self, free_attempts: u32, limits: &Limits, now: Instant) {
+        if self.forgotten(limits, now) {
+            *self = Strikes::default();
+        }
+        self.failures += 1;
+        self.last_failure = Some(now);
+
+        if self.failures > free_attempts {
+            // capped so the shift can't overflow, max_lockout takes over long before that
+            let doublings = (self.failures - free_attempts - 1).min(16);
+            let lockout = (limits.base_lockout * (1 << doublings)).min(limits.max_lockout);
+            self.locked_until = Some(now + lockout);
+        }
+    }
+
+    fn remaining(&self, now: Instant) -> Duration {
+        self.locked_until
+            .map_or(Duration::ZERO, |until| until.saturating_duration_since(now))
+    }
+
+    fn forgotten(&self, limits: &Limits, now: Instant) -> bool {
+        let quiet = match self.last_failure {
+            None => true,
+            Some(last) => now.saturating_duration_since(last) >= limits.forget_after,
+        };
+        quiet && self.remaining(now).is_zero()
+    }
+
+    fn lockout(&self, who: String, now: Instant) -> Lockout {
+        let remaining = self.remaining(now);
+        Lockout {
+            who,
+            failures: self.failures,
+            remaining_secs: remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Record {
+    per_ip: HashMap<IpAddr, Strikes>,
+    global: Strikes,
+}
+
+/// Counts wrong passwords, shared by every websocket connection.
+pub struct AuthGuard {
+    limits: Limits,
+    record: Mutex<Record>,
+    lockouts: watch::Sender<Vec<Lockout>>,
+}
+
+impl AuthGuard {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            record: Mutex::new(Record::default()),
+            lockouts: watch::channel(Vec::new()).0,
+        }
+    }
+
+    /// Everyone with failures on record, updated on every wrong and right password.
+    pub fn subscribe(&self) -> watch::Receiver<Vec<Lockout>> {
+        self.lockouts.subscribe()
+    }
+
+    /// Whether `ip` may try a password now, or how long it has to wait.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let record = self.record.lock().unwrap();
+        let wait = record
+            .per_ip
+            .get(&ip)
+            .map_or(Duration::ZERO, |s| s.remaining(now))
+            .max(record.global.remaining(now));
+        if wait.is_zero() {
+            Ok(())
+        } else {
+            Err(wait)
+        }
+    }
+
+    pub fn failed(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let mut record = self.record.lock().unwrap();
+        record
+            .per_ip
+            .entry(ip)
+            .or_default()
+            .strike(self.limits.free_attempts, &self.limits, now);
+        record
+            .global
+            .strike(self.limits.global_free_attempts, &self.limits, now);
+        self.publish(&mut record, now);
+    }
+
+    /// Forgets the failures of `ip`. The global count stays, a right password doesn't excuse
+    /// the wrong ones coming from elsewhere.
+    pub fn succeeded(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let mut record = self.record.lock().unwrap();
+        if record.per_ip.remove(&ip).is_some() {
+            self.publish(&mut record, now);
+        }
+    }
+
+    fn publish(&self, record: &mut Record, now: Instant) {
+        record
+            .per_ip
+            .retain(|_, strikes| !strikes.forgotten(&self.limits, now));
+
+        let mut lockouts: Vec<Lockout> = record
+            .per_ip
+            .iter()
+            .map(|(ip, strikes)| strikes.lockout(ip.to_string(), now))
+            .collect();
+        lockouts.sort_by_key(|l| Reverse(l.remaining_secs));
+        if !record.global.forgotten(&self.limits, now) {
+            lockouts.insert(0, record.global.lockout("everyone".to_string(), now));
+        }
+
+        self.lockouts.send_replace(lockouts);
+    }
+}
+
+/// A single cooldown shared by every power command.
+pub struct Cooldown {
+    every: Duration,
+    last: Mutex<Option<Instant>>,
+}
+
+impl Cooldown {
+    pub fn new(every: Duration) -> Self {
+        Self {
+            every,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Starts the cooldown if it's over, returning when, otherwise returns how much of it is left.
+    pub fn take(&self) -> Result<Instant, Duration> {
+        let now = Instant::now();
+        let mut last = self.last.lock().unwrap();
+        if let Some(last) = *last {
+            let remaining = self
+                .every
+                .saturating_sub(now.saturating_duration_since(last));
+            if !remaining.is_zero() {
+                return Err(remaining);
+            }
+        }
+        *last = Some(now);
+        Ok(now)
+    }
+
+    /// Ends the cooldown started at `taken`, for a command that failed. Nothing else could have
+    /// been waiting on an older one.
+    pub fn give_back(&self, taken: Instant) {
+        let mut last = self.last.lock().unwrap();
+        if *last == Some(taken) {
+            *last = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lockout_doubles() {
+        let guard = AuthGuard::new(Limits::default());
+
+        for _ in 0..3 {
+            assert!(guard.check(ip(1)).is_ok());
+            guard.failed(ip(1));
+        }
+        assert!(guard.check(ip(1)).is_ok());
+        guard.failed(ip(1));
+        assert_eq!(guard.check(ip(1)), Err(Duration::from_secs(30)));
+        assert!(guard.check(ip(2)).is_ok());
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(guard.check(ip(1)).is_ok());
+        guard.failed(ip(1));
+        assert_eq!(guard.check(ip(1)), Err(Duration::from_secs(60)));
+
+        let lockouts = guard.subscribe().borrow().clone();
+        assert_eq!(lockouts[0].who, "everyone");
+        assert_eq!(
+            lockouts[1..],
+            [Lockout {
+                who: "10.0.0.1".to_string(),
+                failures: 5,
+                remaining_secs: 60
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_are_forgotten() {
+        let guard = AuthGuard::new(Limits::default());
+
+        for _ in 0..3 {
+            guard.failed(ip(1));
+        }
+        tokio::time::advance(Duration::from_secs(60 * 60)).await;
+        guard.failed(ip(1));
+        assert!(guard.check(ip(1)).is_ok());
+
+        guard.failed(ip(1));
+        guard.succeeded(ip(1));
+        let lockouts = guard.subscribe().borrow().clone();
+        assert!(lockouts.iter().all(|l| l.who == "everyone"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_global_lockout() {
+        let guard = AuthGuard::new(Limits::default());
+
+        for i in 0..21 {
+            guard.failed(ip(i));
+        }
+        assert_eq!(guard.check(ip(100)), Err(Duration::from_secs(30)));
+        assert_eq!(guard.subscribe().borrow()[0].who, "everyone");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cooldown() {
+        let cooldown = Cooldown::new(Duration::from_secs(30));
+
+        assert!(cooldown.take().is_ok());
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(cooldown.take(), Err(Duration::from_secs(20)));
+        tokio::time::advance(Duration::from_secs(20)).await;
+        let taken = cooldown.take().unwrap();
+
+        // a failed command gives its cooldown back, unless another one started since
+        cooldown.give_back(taken);
+        let taken = cooldown.take().unwrap();
+        tokio::time::advance(Duration::from_secs(30)).await;
+        let later = cooldown.take().unwrap();
+        cooldown.give_back(taken);
+        assert_eq!(cooldown.take(), Err(Duration::from_secs(30)));
+        cooldown.give_back(later);
+        assert!(cooldown.take().is_ok());
+    }
+}
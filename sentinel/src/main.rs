@@ -3,46 +3,72 @@ use aws_sdk_ec2::{Client, Error as Ec2Error};
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        TypedHeader,
+        ws::{WebSocket, WebSocketUpgrade},
+        ConnectInfo, TypedHeader,
     },
     headers,
     response::IntoResponse,
     routing::get,
     Extension, Router,
 };
-use common::{AuthResult, ClientOpt, ContainerStatus, Newspeak, OnlinePeople, ServerStatus};
-use futures::{sink::SinkExt, stream::StreamExt};
-use ssh2::Session;
-use std::borrow::BorrowMut;
+use command::{Caller, CommandError, Commander};
+use compose::Compose;
+use config::ConfigFiles;
+use crash::{CrashPolicy, CrashWatch};
+use roster::RosterKeeper;
+use common::protocol;
+use common::{
+    AuthResult, ClientOpt, ConfigReview, Container, ContainerStatus, Modpack, Newspeak,
+    OnlinePeople, Outcome, Response, Role, ServerStatus,
+};
+use cadence::{Cadence, Poller};
+use limit::{AuthGuard, Limits};
+use metrics::METRICS;
+use sessions::Sessions;
+use futures::stream::StreamExt;
 
+use std::borrow::Borrow;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::io::Read;
-use std::net::{IpAddr, SocketAddr, TcpStream};
-use std::ops::{AddAssign, SubAssign};
-use std::str::FromStr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::SystemTime;
 use axum_server::tls_rustls::RustlsConfig;
 use tokio::sync::watch::Receiver;
-use tokio::sync::{Mutex, Notify};
+use wire::{Heard, Speaker};
 
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-macro_rules! send_to_instance {
+macro_rules! try_send_to_instance {
     ($client:ident, $id:ident) => {
         $client
             .$id()
             .set_instance_ids(Some(vec!["i-04f77bba0b522dfbe".to_string()]))
             .send()
             .await
-            .unwrap()
     };
 }
 
+// declared after the macros so they can use them
+mod api;
+mod cadence;
+mod command;
+mod compose;
+mod config;
+mod crash;
+#[cfg(feature = "discord")]
+mod discord;
+mod limit;
+mod metrics;
+mod modpack;
+mod roster;
+mod sessions;
+mod ssh;
+mod wire;
+
 async fn show_state(client: &Client) -> Result<InstanceStateName, Ec2Error> {
-    let resp = send_to_instance!(client, describe_instances);
+    let resp = try_send_to_instance!(client, describe_instances)?;
 
     let instance = resp
         .reservations()
@@ -99,56 +125,117 @@ impl Error for TimeoutError {}
 async fn ws_handler(
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
-    Extension(rx): Extension<Receiver<Option<ServerStatus>>>,
-    Extension(client): Extension<Client>,
-    Extension(con_notify): Extension<Arc<Notify>>,
-    Extension(con_count): Extension<Arc<Mutex<u16>>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(rx): Extension<Receiver<Option<Polled>>>,
+    Extension(commander): Extension<Commander>,
+    Extension(auth_guard): Extension<Arc<AuthGuard>>,
+    Extension(sessions): Extension<Arc<Sessions>>,
 ) -> impl IntoResponse {
-    if let Some(TypedHeader(user_agent)) = user_agent {
+    let agent = if let Some(TypedHeader(user_agent)) = user_agent {
         println!("`{}` connected", user_agent.as_str());
-    }
+        user_agent.as_str().to_string()
+    } else {
+        "unknown client".to_string()
+    };
+
+    ws.on_upgrade(move |socket: WebSocket| {
+        handle_socket(
+            socket,
+            rx,
+            commander,
+            auth_guard,
+            sessions,
+            addr.ip(),
+            agent,
+        )
+    })
+}
 
-    ws.on_upgrade(|socket: WebSocket| handle_socket(socket, rx, client, con_count, con_notify))
+/// The role `password` grants, if any.
+fn clearance(password: &str) -> Option<Role> {
+    if dotenv::var("ADMIN_PASSWORD").ok().as_deref() == Some(password) {
+        Some(Role::Admin)
+    } else if password == dotenv::var("PASSWORD").unwrap() {
+        Some(Role::Member)
+    } else {
+        None
+    }
 }
+
 async fn handle_socket(
     socket: WebSocket,
-    mut rx: Receiver<Option<ServerStatus>>,
-    client: Client,
-    con_count: Arc<Mutex<u16>>,
-    con_notify: Arc<Notify>,
+    mut rx: Receiver<Option<Polled>>,
+    commander: Commander,
+    auth_guard: Arc<AuthGuard>,
+    sessions: Arc<Sessions>,
+    ip: IpAddr,
+    agent: String,
 ) {
     let (sender, mut receiver) = socket.split();
 
-    con_notify.notify_one();
-
-    {
-        let mut con = con_count.lock().await;
-        let con = con.borrow_mut();
-        con.add_assign(1);
-    }
-    let sender = Arc::new(Mutex::new(sender));
-
-    let config = bincode::config::standard();
+    let (speaker, first) = match wire::handshake(sender, &mut receiver).await {
+        Some(handshake) => handshake,
+        None => return,
+    };
+    let name = format!("websocket client `{agent}` at {ip}");
+    let session = sessions.join(agent, ip);
+
+    // a version 0 client may have spoken before we knew it was one
+    let mut receiver = futures::stream::iter(first.map(Ok))
+        .chain(receiver)
+        .inspect(|_| session.heard());
+
+    let speaker = Arc::new(speaker);
+
+    let heartbeat = async {
+        let mut pings = tokio::time::interval(wire::PING_INTERVAL);
+        loop {
+            pings.tick().await;
+            if session.silent_for() > wire::SILENCE_LIMIT {
+                tracing::info!("{name} went silent, letting it go");
+                return;
+            }
+            if speaker.ping().await.is_err() {
+                // client disconnected
+                return;
+            }
+        }
+    };
 
     let broadcast_status = {
-        let sender = sender.clone();
+        let speaker = speaker.clone();
 
         async move {
+            // the modpack rarely changes, so it's only sent when it does
+            let mut sent_modpack = None;
             loop {
                 rx.changed().await.ok();
-                let s = rx.borrow_and_update().as_ref().cloned();
-                if let Some(s) = s {
-                    if let Ok(s) = bincode::encode_to_vec(Newspeak::ServerStatus(s), config) {
-                        let send_result = {
-                            let mut sender = sender.lock().await;
-                            let sender = sender.borrow_mut();
-                            sender.send(Message::Binary(s)).await
-                        };
-
-                        if send_result.is_err() {
-                            // client disconnected
+                let polled = rx.borrow_and_update().as_ref().cloned();
+                if let Some(Polled {
+                    status,
+                    container,
+                    modpack,
+                    ..
+                }) = polled
+                {
+                    if speaker.say(Newspeak::ServerStatus(status)).await.is_err() {
+                        // client disconnected
+                        return;
+                    }
+                    if speaker.can(protocol::COMPOSE)
+                        && speaker.say(Newspeak::Container(container)).await.is_err()
+                    {
+                        return;
+                    }
+                    if speaker.can(protocol::MODPACK) && sent_modpack.as_ref() != Some(&modpack) {
+                        if speaker
+                            .say(Newspeak::Modpack(modpack.clone()))
+                            .await
+                            .is_err()
+                        {
                             return;
                         }
+                        sent_modpack = Some(modpack);
                     }
                 }
             }
@@ -156,91 +243,212 @@ async fn handle_socket(
     };
 
     let receive_commands = async {
-        'outer: loop {
+        let role = 'outer: loop {
             if let Some(Ok(m)) = receiver.next().await {
-                match m {
-                    Message::Text(p) => {
-                        if p == dotenv::var("PASSWORD").unwrap() {
-                            break 'outer;
-                        } else {
-                            tracing::info!("wrong password received");
-                            sender
-                                .lock()
+                match speaker.hear(m) {
+                    Heard::Password(p) => {
+                        if let Err(wait) = auth_guard.check(ip) {
+                            tracing::info!("password received from {ip} while locked out");
+                            METRICS.auth_locked_out();
+                            speaker
+                                .say(Newspeak::AuthResult(AuthResult::Sus))
                                 .await
-                                .send(Message::Binary(
-                                    bincode::encode_to_vec(
-                                        Newspeak::AuthResult(AuthResult::Sus),
-                                        config,
-                                    )
-                                    .unwrap(),
-                                ))
+                                .ok();
+                            speaker
+                                .say(Newspeak::Feedback(format!(
+                                    "too many wrong passwords, try again in {}s",
+                                    wait.as_secs() + 1
+                                )))
+                                .await
+                                .ok();
+                        } else if let Some(role) = clearance(&p) {
+                            auth_guard.succeeded(ip);
+                            session.authenticated(role);
+                            break 'outer role;
+                        } else {
+                            tracing::info!("wrong password received from {ip}");
+                            auth_guard.failed(ip);
+                            METRICS.auth_failed();
+                            speaker
+                                .say(Newspeak::AuthResult(AuthResult::Sus))
                                 .await
                                 .ok();
                         }
                     }
-                    Message::Close(_) => return,
+                    Heard::Heartbeat => {
+                        speaker.say(Newspeak::Heartbeat).await.ok();
+                    }
+                    Heard::Close => return,
                     _ => {}
                 }
             } else {
                 return;
             }
-        }
+        };
 
-        tracing::info!("a client authenticated");
-        sender
-            .lock()
-            .await
-            .send(Message::Binary(
-                bincode::encode_to_vec(Newspeak::AuthResult(AuthResult::Goob), config).unwrap(),
-            ))
+        tracing::info!("a client authenticated as {role:?}");
+        speaker
+            .say(Newspeak::AuthResult(AuthResult::Goob))
             .await
             .ok();
+        if speaker.can(protocol::ADMIN) {
+            speaker.say(Newspeak::Clearance(role)).await.ok();
+        }
+
+        let admin_feed = async {
+            if role < Role::Admin || !speaker.can(protocol::ADMIN) {
+                return futures::future::pending().await;
+            }
+            let mut lockouts = auth_guard.subscribe();
+            let mut sessions = sessions.subscribe();
+            let wants_sessions = speaker.can(protocol::SESSIONS);
+
+            let current = lockouts.borrow_and_update().clone();
+            if speaker.say(Newspeak::Lockouts(current)).await.is_err() {
+                return;
+            }
+            if wants_sessions {
+                let current = sessions.borrow_and_update().clone();
+                if speaker.say(Newspeak::Sessions(current)).await.is_err() {
+                    return;
+                }
+            }
 
-        while let Some(msg) = receiver.next().await {
-            if let Ok(msg) = msg {
-                match msg {
-                    Message::Text(_) => {
-                        // println!("client send str: {:?}", t);
+            loop {
+                let newspeak = tokio::select! {
+                    Ok(_) = lockouts.changed() => {
+                        Newspeak::Lockouts(lockouts.borrow_and_update().clone())
                     }
-                    Message::Binary(d) => {
-                        if let Ok((decoded, _)) =
-                            bincode::decode_from_slice::<ClientOpt, _>(d.as_slice(), config)
-                        {
-                            match decoded {
-                                ClientOpt::On => {
-                                    tracing::info!("received power on request");
-                                    send_to_instance!(client, start_instances);
-                                    sender.lock().await.send(Message::Binary(bincode::encode_to_vec(Newspeak::Feedback("sentinel acknowledged the request, will boot the host shortly".to_string()), config).unwrap())).await.ok();
+                    Ok(_) = sessions.changed(), if wants_sessions => {
+                        Newspeak::Sessions(sessions.borrow_and_update().clone())
+                    }
+                    else => return,
+                };
+                if speaker.say(newspeak).await.is_err() {
+                    // client disconnected
+                    return;
+                }
+            }
+        };
+
+        let crash_feed = async {
+            if !speaker.can(protocol::CRASHES) {
+                return futures::future::pending().await;
+            }
+            let mut crashes = commander.crashes().subscribe();
+            // the last crash, if there was one, then every new one
+            loop {
+                let crash = crashes.borrow_and_update().clone();
+                if let Some(crash) = crash {
+                    if speaker.say(Newspeak::Crash(crash)).await.is_err() {
+                        // client disconnected
+                        return;
+                    }
+                }
+                if crashes.changed().await.is_err() {
+                    return;
+                }
+            }
+        };
+
+        let commands = async {
+            while let Some(msg) = receiver.next().await {
+                if let Ok(msg) = msg {
+                    match speaker.hear(msg) {
+                        Heard::Opt { id, opt } => {
+                            let caller = Caller {
+                                name: name.clone(),
+                                role,
+                            };
+                            if speaker.can(protocol::RESPONSES) {
+                                respond(&speaker, &commander, id, opt, &caller).await;
+                            } else {
+                                let feedback = match commander.execute(opt, &caller).await {
+                                    Ok(feedback) => feedback,
+                                    Err(e) => e.to_string(),
+                                };
+                                speaker.say(Newspeak::Feedback(feedback)).await.ok();
+                            }
+                        }
+                        Heard::Heartbeat => {
+                            speaker.say(Newspeak::Heartbeat).await.ok();
+                        }
+                        Heard::ReadConfig if speaker.can(protocol::CONFIG) => {
+                            let caller = Caller {
+                                name: name.clone(),
+                                role,
+                            };
+                            let newspeak = match commander.read_config(&caller).await {
+                                Ok(entries) => Newspeak::Config(entries),
+                                Err(e) => config_problem(e),
+                            };
+                            speaker.say(newspeak).await.ok();
+                        }
+                        Heard::ReviewConfig { edits, apply } if speaker.can(protocol::CONFIG) => {
+                            let caller = Caller {
+                                name: name.clone(),
+                                role,
+                            };
+                            match commander.review_config(edits, apply, &caller).await {
+                                Ok(review) => {
+                                    let applied = review.applied;
+                                    speaker.say(Newspeak::ConfigReview(review)).await.ok();
+                                    if applied {
+                                        if let Ok(entries) = commander.read_config(&caller).await {
+                                            speaker.say(Newspeak::Config(entries)).await.ok();
+                                        }
+                                    }
                                 }
-                                ClientOpt::Off => {
-                                    tracing::info!("received power off request");
-                                    send_to_instance!(client, stop_instances);
-                                    sender.lock().await.send(Message::Binary(bincode::encode_to_vec(Newspeak::Feedback("sentinel acknowledged the request, will shutdown the host shortly".to_string()), config).unwrap())).await.ok();
+                                Err(e) => {
+                                    speaker.say(config_problem(e)).await.ok();
                                 }
-                                ClientOpt::Reboot => {
-                                    tracing::info!("received reboot request");
-                                    send_to_instance!(client, reboot_instances);
-                                    sender.lock().await.send(Message::Binary(bincode::encode_to_vec(Newspeak::Feedback("sentinel acknowledged the request, will reboot the host shortly".to_string()), config).unwrap())).await.ok();
+                            }
+                        }
+                        Heard::ReadRoster if speaker.can(protocol::ROSTER) => {
+                            if let Ok(roster) = commander.read_roster().await {
+                                speaker.say(Newspeak::Roster(roster)).await.ok();
+                            }
+                        }
+                        Heard::EditRoster(edit) if speaker.can(protocol::ROSTER) => {
+                            let caller = Caller {
+                                name: name.clone(),
+                                role,
+                            };
+                            let outcome = match commander.edit_roster(edit, &caller).await {
+                                Ok(_) => Outcome::Completed,
+                                Err(CommandError::Rejected(reason)) => {
+                                    Outcome::Rejected { reason }
                                 }
+                                Err(CommandError::Failed(reason)) => Outcome::Failed { reason },
+                            };
+                            speaker.say(Newspeak::RosterEdited(outcome)).await.ok();
+                            if let Ok(roster) = commander.read_roster().await {
+                                speaker.say(Newspeak::Roster(roster)).await.ok();
                             }
                         }
+                        Heard::Close => {
+                            // println!("client disconnected");
+                            return;
+                        }
+                        Heard::Password(_)
+                        | Heard::ReadConfig
+                        | Heard::ReviewConfig { .. }
+                        | Heard::ReadRoster
+                        | Heard::EditRoster(_)
+                        | Heard::Nothing => {}
                     }
-                    Message::Ping(_) => {
-                        // println!("socket ping");
-                    }
-                    Message::Pong(_) => {
-                        // println!("socket pong");
-                    }
-                    Message::Close(_) => {
-                        // println!("client disconnected");
-                        return;
-                    }
+                } else {
+                    // println!("client disconnected");
+                    return;
                 }
-            } else {
-                // println!("client disconnected");
-                return;
             }
-        }
+        };
+
+        tokio::select!(
+            _ = admin_feed => {}
+            _ = crash_feed => {}
+            _ = commands => {}
+        );
     };
 
     tokio::select!(
@@ -249,79 +457,118 @@ async fn handle_socket(
         }
         _ = receive_commands => {
 
+        }
+        _ = heartbeat => {
+
         }
     );
 
-    let send_result = {
-        let mut sender = sender.lock().await;
-        let sender = sender.borrow_mut();
-        sender.send(Message::Close(None)).await
+    speaker.close().await;
+    tracing::info!("a client left");
+}
+
+/// Runs a request from a client that wants typed [`Response`]s.
+async fn respond(
+    speaker: &Speaker,
+    commander: &Commander,
+    id: u64,
+    opt: ClientOpt,
+    caller: &Caller,
+) {
+    let say = |outcome| {
+        speaker.say(Newspeak::Response(Response {
+            request: id,
+            outcome,
+        }))
+    };
+
+    let authorized = match commander.authorize(opt, caller) {
+        Ok(authorized) => authorized,
+        Err(e) => {
+            say(Outcome::Rejected {
+                reason: e.to_string(),
+            })
+            .await
+            .ok();
+            return;
+        }
     };
-    send_result.ok();
-    {
-        let mut con = con_count.lock().await;
-        let con = con.borrow_mut();
-        con.sub_assign(1);
+    say(Outcome::Ack).await.ok();
+
+    let outcome = match commander.perform(authorized).await {
+        Ok(_) => Outcome::Completed,
+        Err(e) => Outcome::Failed {
+            reason: e.to_string(),
+        },
+    };
+    say(outcome).await.ok();
+}
+
+/// A config request that went nowhere, as a review the console shows.
+fn config_problem(e: CommandError) -> Newspeak {
+    Newspeak::ConfigReview(ConfigReview {
+        changes: Vec::new(),
+        problems: vec![e.to_string()],
+        applied: false,
+    })
+}
+
+/// Everything a poll finds out.
+#[derive(Clone, Debug)]
+pub(crate) struct Polled {
+    pub status: ServerStatus,
+    /// What docker says about the container, `None` if the host is down or it doesn't exist.
+    pub container: Option<Container>,
+    /// The pack the running server unpacked, `None` if it isn't running or the pack has no
+    /// manifest.
+    pub modpack: Option<Modpack>,
+    /// When the poll started.
+    pub polled_at: SystemTime,
+}
+
+impl Borrow<ServerStatus> for Polled {
+    fn borrow(&self) -> &ServerStatus {
+        &self.status
     }
-    tracing::info!("a client left");
 }
 
-async fn poll_server_status(client: &Client) -> Option<ServerStatus> {
+/// Polls the host, the container and who is online, and records it in the [`METRICS`].
+///
+/// `None` if ssh into the host failed.
+pub(crate) async fn poll_server_status(client: &Client) -> Option<Polled> {
+    let started = std::time::Instant::now();
+    let polled = query_server_status(client).await;
+    if polled.is_none() {
+        METRICS.ssh_error();
+    }
+    METRICS.polled(started.elapsed());
+    polled
+}
+
+async fn query_server_status(client: &Client) -> Option<Polled> {
+    let polled_at = SystemTime::now();
     let host = show_state(client)
         .await
-        .map_or("failed to get instance state".to_string(), |n| {
-            n.as_str().to_string()
-        });
-
-    let (container, online) = if host.contains("running") {
-        let addr = SocketAddr::new(
-            IpAddr::from_str(&dotenv::var("MC_HOST").unwrap()).unwrap(),
-            22,
+        .map_or_else(
+            |e| {
+                tracing::warn!("failed to get instance state: {e}");
+                METRICS.ec2_error();
+                "failed to get instance state".to_string()
+            },
+            |n| n.as_str().to_string(),
         );
-        let tcp = TcpStream::connect_timeout(&addr, Duration::from_secs(3)).ok()?;
-        let mut sess = Session::new().unwrap();
-        sess.set_tcp_stream(tcp);
-        sess.handshake().unwrap();
-        sess.userauth_pubkey_file(
-            "root",
-            None,
-            dotenv::var("PRIVATE_KEY").unwrap().as_ref(),
-            None,
-        )
-        .unwrap();
-        sess.set_keepalive(false, 40);
-        let mut channel = sess.channel_session().ok()?;
-        channel.exec("docker container ls").ok()?;
-        let mut s = String::new();
-        let b = channel.read_to_string(&mut s).ok();
-        // println!("{}", s);
-        channel.wait_close().ok();
-        // println!("{}", channel.exit_status() .unwrap());
-        let container = b
-            .map(|_| {
-                let mut s = s.trim().split('\n');
-
-                let cell_bounds = s.next().map(|x| (x.find("STATUS"), x.find("PORTS")));
 
-                cell_bounds
-                    .and_then(|lr| {
-                        if let (Some(l), Some(r)) = lr {
-                            Some((l, r))
-                        } else {
-                            None
-                        }
-                    })
-                    .map(|(l, r)| {
-                        s.next()
-                            .map(|x| ContainerStatus::Up((&x[l..r]).to_string()))
-                            .unwrap_or(ContainerStatus::NotUp)
-                    })
-                    .unwrap_or(ContainerStatus::Unknown)
-            })
-            .unwrap_or(ContainerStatus::Unknown);
+    let (container, details, online, modpack) = if host.contains("running") {
+        let sess = ssh::connect().ok()?;
+        let compose = Compose::from_env();
+        let details = compose.inspect(&sess);
+        if let Err(e) = &details {
+            tracing::warn!("failed to read the container: {e}");
+        }
+        let container = compose::status(&details);
 
         let mut channel = sess.channel_session().ok()?;
-        channel.exec("docker exec root-mc-1 rcon-cli list").ok()?;
+        channel.exec(&format!("docker exec {} rcon-cli list", compose.container)).ok()?;
         let mut s = String::new();
 
         channel.read_to_string(&mut s).ok();
@@ -334,20 +581,35 @@ async fn poll_server_status(client: &Client) -> Option<ServerStatus> {
             .map(OnlinePeople::Known)
             .unwrap_or(OnlinePeople::Unknown);
 
-        (container, online)
+        let modpack = match &container {
+            ContainerStatus::Up(_) => modpack::read(&sess, &modpack::manifest_path()),
+            _ => None,
+        };
+
+        (container, details.ok().flatten(), online, modpack)
     } else {
-        (ContainerStatus::NotUp, OnlinePeople::Unknown)
+        (ContainerStatus::NotUp, None, OnlinePeople::Unknown, None)
     };
 
-    Some(ServerStatus {
-        host,
-        container,
-        online,
+    Some(Polled {
+        status: ServerStatus {
+            host,
+            container,
+            online,
+        },
+        container: details,
+        modpack,
+        polled_at,
     })
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Ec2Error> {
+    // `deploy service` runs a new binary with it on the host before switching over to it
+    if std::env::args().nth(1).as_deref() == Some("--version") {
+        println!("sentinel {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
     dotenv::dotenv().ok();
 
     tracing_subscriber::registry()
@@ -357,41 +619,71 @@ async fn main() -> Result<(), Ec2Error> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // let auth = RequireAuthorizationLayer::bearer("elfiscute");
-
-    let (tx, rx) = tokio::sync::watch::channel::<Option<ServerStatus>>(None);
+    let (tx, rx) = tokio::sync::watch::channel::<Option<Polled>>(None);
+    let tx = Arc::new(tx);
 
     // todo: handle log streaming. Can't be done with watch because no log must be lost.
 
     // ap-east-1 is Hong Kong
     let shared_config = aws_config::from_env().region("ap-east-1").load().await;
 
-    let con_notify = Arc::new(tokio::sync::Notify::new());
-    let con_count = Arc::new(Mutex::new(0u16));
+    let sessions = Sessions::new();
 
     let client = Client::new(&shared_config);
 
-    let poll_client = client.clone();
+    let limits = Limits::from_env();
+    let auth_guard = Arc::new(AuthGuard::new(limits.clone()));
+    let cadence = Cadence::from_env();
+    // a poll can take a while on top of the wait before it
+    let stale_after = cadence.slowest() * 2;
+    let poller = Poller::new(cadence);
+    let crashes = CrashWatch::new(CrashPolicy::from_env());
+    let commander = Commander::new(
+        client.clone(),
+        limits.power_cooldown,
+        poller.clone(),
+        crashes.clone(),
+        Compose::from_env(),
+        ConfigFiles::from_env(),
+        RosterKeeper::from_env(),
+        tx.clone(),
+    );
+
+    let poll_client = client;
     let endless_poll = {
-        let con_count = con_count.clone();
-        let con_notify = con_notify.clone();
+        let sessions = sessions.clone();
         async move {
             loop {
-                tracing::info!("waiting for connection to start polling server");
-                con_notify.notified().await;
-
-                while con_count.lock().await.gt(&0) {
-                    tracing::trace!("polling the server");
-                    let status = poll_server_status(&poll_client)
-                        .await
-                        .unwrap_or(ServerStatus {
-                            host: "unknown".to_string(),
-                            container: ContainerStatus::Unknown,
-                            online: OnlinePeople::Unknown,
-                        });
-                    tx.send(Some(status)).ok();
-                    tokio::time::sleep(Duration::from_secs(5)).await;
+                if !crashes.unattended() {
+                    tracing::info!("waiting for connection to start polling server");
+                    sessions.occupied().await;
                 }
+
+                let poll_client = &poll_client;
+                poller
+                    .run(
+                        || async move {
+                            tracing::trace!("polling the server");
+                            poll_server_status(poll_client)
+                                .await
+                                .unwrap_or(Polled {
+                                    status: ServerStatus {
+                                        host: "unknown".to_string(),
+                                        container: ContainerStatus::Unknown,
+                                        online: OnlinePeople::Unknown,
+                                    },
+                                    container: None,
+                                    modpack: None,
+                                    polled_at: SystemTime::now(),
+                                })
+                        },
+                        |polled: Polled| {
+                            crashes.observe(&polled.status);
+                            tx.send(Some(polled)).ok();
+                        },
+                        || crashes.unattended() || !sessions.is_empty(),
+                    )
+                    .await;
                 tracing::info!("no websocket connection remains");
             }
         }
@@ -400,12 +692,27 @@ async fn main() -> Result<(), Ec2Error> {
     let ws_router = Router::new()
         .route("/", get(ws_handler))
         .layer(Extension(rx))
-        .layer(Extension(client))
-        .layer(Extension(con_count))
-        .layer(Extension(con_notify));
+        .layer(Extension(commander.clone()))
+        .layer(Extension(auth_guard))
+        .layer(Extension(sessions.clone()));
 
     let app = Router::new().nest("/ws", ws_router);
 
+    let app = app.merge(metrics::router(
+        commander.clone(),
+        sessions,
+        dotenv::var("METRICS_TOKEN").ok().as_deref(),
+        stale_after,
+    ));
+
+    let app = match dotenv::var("API_TOKEN") {
+        Ok(token) => app.nest("/api", api::router(commander.clone(), &token)),
+        Err(_) => {
+            tracing::info!("API_TOKEN is not set, the rest api is disabled");
+            app
+        }
+    };
+
     let config = RustlsConfig::from_pem_file(
         dotenv::var("CERT_FILE").unwrap(),
         dotenv::var("KEY_FILE").unwrap(),
@@ -416,18 +723,26 @@ async fn main() -> Result<(), Ec2Error> {
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     println!("https listening on {}", addr);
     let server = axum_server::bind_rustls(addr, config)
-        .serve(app.into_make_service());
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>());
 
 
     // let server =
     //     axum::Server::bind(&"0.0.0.0:3000".parse().unwrap()).serve(app.into_make_service());
 
+    #[cfg(feature = "discord")]
+    let discord_bot = discord::run(commander);
+    #[cfg(not(feature = "discord"))]
+    let discord_bot = futures::future::pending::<()>();
+
     tokio::select! {
         _ = endless_poll =>{
 
         }
         _ = server => {
 
+        }
+        _ = discord_bot => {
+
         }
     }
 
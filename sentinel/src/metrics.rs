@@ -0,0 +1,293 @@
+//! `/healthz` and a Prometheus `/metrics` endpoint, so sentinel and the server it watches can be
+//! alerted on.
+
+use crate::command::Commander;
+use crate::sessions::Sessions;
+use axum::{http::header, response::IntoResponse, routing::get, Extension, Router};
+use common::{ContainerStatus, OnlinePeople, ServerStatus};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tower_http::auth::RequireAuthorizationLayer;
+
+/// Upper bounds of the poll duration histogram buckets, in seconds.
+const POLL_BUCKETS: [f64; 7] = [0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0];
+
+pub static METRICS: Metrics = Metrics::new();
+
+struct Recorded {
+    poll_buckets: [u64; POLL_BUCKETS.len()],
+    poll_count: u64,
+    poll_seconds: f64,
+    ec2_errors: u64,
+    ssh_errors: u64,
+    auth_failures: u64,
+    auth_lockouts: u64,
+    last_poll: Option<(Instant, SystemTime)>,
+}
+
+pub struct Metrics(Mutex<Recorded>);
+
+impl Metrics {
+    const fn new() -> Self {
+        Self(Mutex::new(Recorded {
+            poll_buckets: [0; POLL_BUCKETS.len()],
+            poll_count: 0,
+            poll_seconds: 0.0,
+            ec2_errors: 0,
+            ssh_errors: 0,
+            auth_failures: 0,
+            auth_lockouts: 0,
+            last_poll: None,
+        }))
+    }
+
+    /// A poll finished after `took`.
+    pub fn polled(&self, took: Duration) {
+        let mut r = self.0.lock().unwrap();
+        let seconds = took.as_secs_f64();
+        for (bucket, bound) in r.poll_buckets.iter_mut().zip(POLL_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        r.poll_count += 1;
+        r.poll_seconds += seconds;
+        r.last_poll = Some((Instant::now(), SystemTime::now()));
+    }
+
+    pub fn ec2_error(&self) {
+        self.0.lock().unwrap().ec2_errors += 1;
+    }
+
+    pub fn ssh_error(&self) {
+        self.0.lock().unwrap().ssh_errors += 1;
+    }
+
+    /// A wrong password.
+    pub fn auth_failed(&self) {
+        self.0.lock().unwrap().auth_failures += 1;
+    }
+
+    /// A password attempt refused because of a lockout.
+    pub fn auth_locked_out(&self) {
+        self.0.lock().unwrap().auth_lockouts += 1;
+    }
+
+    /// Everything in the Prometheus text format, with `status` as it was last polled.
+    /// The polls are stale once the last one is older than `stale_after`.
+    pub fn render(
+        &self,
+        sessions: usize,
+        status: Option<&ServerStatus>,
+        stale_after: Duration,
+    ) -> String {
+        let r = self.0.lock().unwrap();
+        let mut out = String::new();
+
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            writeln!(out, "# HELP {name} {help}").unwrap();
+            writeln!(out, "# TYPE {name} {kind}").unwrap();
+            for (labels, value) in samples {
+                writeln!(out, "{name}{labels} {value}").unwrap();
+            }
+        };
+        let plain = |value: String| [(String::new(), value)];
+
+        metric(
+            "sentinel_websocket_sessions",
+            "gauge",
+            "Live websocket connections.",
+            &plain(sessions.to_string()),
+        );
+
+        let mut buckets: Vec<_> = POLL_BUCKETS
+            .iter()
+            .zip(r.poll_buckets)
+            .map(|(bound, count)| (format!("_bucket{{le=\"{bound}\"}}"), count.to_string()))
+            .collect();
+        buckets.push(("_bucket{le=\"+Inf\"}".to_string(), r.poll_count.to_string()));
+        buckets.push(("_sum".to_string(), r.poll_seconds.to_string()));
+        buckets.push(("_count".to_string(), r.poll_count.to_string()));
+        metric(
+            "sentinel_poll_duration_seconds",
+            "histogram",
+            "How long polling the host, the container and the players took.",
+            &buckets,
+        );
+
+        metric(
+            "sentinel_poll_errors_total",
+            "counter",
+            "Polls that failed to reach aws or to ssh into the host.",
+            &[
+                ("{source=\"ec2\"}".to_string(), r.ec2_errors.to_string()),
+                ("{source=\"ssh\"}".to_string(), r.ssh_errors.to_string()),
+            ],
+        );
+
+        if let Some((_, at)) = r.last_poll {
+            let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+            metric(
+                "sentinel_last_poll_timestamp_seconds",
+                "gauge",
+                "When the server was last polled.",
+                &plain(since_epoch.as_secs().to_string()),
+            );
+        }
+        let stale = match r.last_poll {
+            None => true,
+            Some((at, _)) => at.elapsed() > stale_after,
+        };
+        metric(
+            "sentinel_poll_stale",
+            "gauge",
+            "Whether the last poll of the server is older than sentinel ever waits between two.",
+            &plain(u8::from(stale).to_string()),
+        );
+
+        if let Some(status) = status {
+            metric(
+                "sentinel_host_state",
+                "gauge",
+                "The last known ec2 state of the host, 1 for the current one.",
+                &[(format!("{{state=\"{}\"}}", status.host), "1".to_string())],
+            );
+            let up = matches!(status.container, ContainerStatus::Up(_));
+            metric(
+                "sentinel_container_up",
+                "gauge",
+                "Whether the minecraft container was last seen up.",
+                &plain(u8::from(up).to_string()),
+            );
+            if let Some(players) = players_online(&status.online) {
+                metric(
+                    "sentinel_players_online",
+                    "gauge",
+                    "Players last seen online.",
+                    &plain(players.to_string()),
+                );
+            }
+        }
+
+        metric(
+            "sentinel_auth_failures_total",
+            "counter",
+            "Wrong passwords received.",
+            &plain(r.auth_failures.to_string()),
+        );
+        metric(
+            "sentinel_auth_lockouts_total",
+            "counter",
+            "Password attempts refused because of a lockout.",
+            &plain(r.auth_lockouts.to_string()),
+        );
+
+        out
+    }
+}
+
+/// Reads the count from `There are 1 of a max of 20 players online: Madoshakalaka`.
+fn players_online(online: &OnlinePeople) -> Option<u32> {
+    match online {
+        OnlinePeople::Unknown => None,
+        OnlinePeople::Known(s) => s.split_whitespace().nth(2)?.parse().ok(),
+    }
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn metrics(
+    Extension(commander): Extension<Commander>,
+    Extension(sessions): Extension<Arc<Sessions>>,
+    Extension(StaleAfter(stale_after)): Extension<StaleAfter>,
+) -> impl IntoResponse {
+    // nothing else polls while nobody is watching, the scrape has to for alerts to work
+    let polled = commander.status().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(
+            sessions.len(),
+            polled.as_ref().map(|polled| &polled.status),
+            stale_after,
+        ),
+    )
+}
+
+#[derive(Clone, Copy)]
+struct StaleAfter(Duration);
+
+/// `/metrics` requires `token` as a bearer token if there is one, `/healthz` never does.
+///
+/// A scrape polls the server through `commander` if the last poll is older than the poller would
+/// have waited, so however often it's scraped, the server is polled no more often than while
+/// someone is watching. The polls are stale once the last one is older than `stale_after`.
+pub fn router(
+    commander: Commander,
+    sessions: Arc<Sessions>,
+    token: Option<&str>,
+    stale_after: Duration,
+) -> Router {
+    let metrics = Router::new().route("/metrics", get(metrics));
+    let metrics = match token {
+        Some(token) => metrics.route_layer(RequireAuthorizationLayer::bearer(token)),
+        None => metrics,
+    };
+
+    Router::new()
+        .route("/healthz", get(healthz))
+        .merge(metrics)
+        .layer(Extension(commander))
+        .layer(Extension(sessions))
+        .layer(Extension(StaleAfter(stale_after)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        let stale_after = Duration::from_secs(120);
+        assert!(metrics
+            .render(0, None, stale_after)
+            .lines()
+            .any(|l| l == "sentinel_poll_stale 1"));
+
+        metrics.polled(Duration::from_millis(500));
+        metrics.polled(Duration::from_secs(3));
+        metrics.ssh_error();
+        metrics.auth_failed();
+
+        let status = ServerStatus {
+            host: "running".to_string(),
+            container: ContainerStatus::Up("Up 2 hours (healthy)".to_string()),
+            online: OnlinePeople::Known(
+                "There are 1 of a max of 20 players online: Madoshakalaka".to_string(),
+            ),
+        };
+        let rendered = metrics.render(2, Some(&status), stale_after);
+        for line in [
+            "sentinel_websocket_sessions 2",
+            "sentinel_poll_duration_seconds_bucket{le=\"0.25\"} 0",
+            "sentinel_poll_duration_seconds_bucket{le=\"0.5\"} 1",
+            "sentinel_poll_duration_seconds_bucket{le=\"5\"} 2",
+            "sentinel_poll_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "sentinel_poll_duration_seconds_sum 3.5",
+            "sentinel_poll_duration_seconds_count 2",
+            "sentinel_poll_errors_total{source=\"ec2\"} 0",
+            "sentinel_poll_errors_total{source=\"ssh\"} 1",
+            "sentinel_poll_stale 0",
+            "sentinel_host_state{state=\"running\"} 1",
+            "sentinel_container_up 1",
+            "sentinel_players_online 1",
+            "sentinel_auth_failures_total 1",
+            "# TYPE sentinel_poll_duration_seconds histogram",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {line}");
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Which modpack the server runs, from the manifest `deploy pack` puts in every pack.
+
+use crate::ssh;
+use common::Modpack;
+
+/// Where the manifest ends up once the image unpacked the pack, see the README.
+pub fn manifest_path() -> String {
+    dotenv::var("MODPACK_MANIFEST").unwrap_or_else(|_| {
+        let data_dir = dotenv::var("MC_DATA_DIR").unwrap_or_else(|_| "/opt/mc".to_string());
+        format!("{data_dir}/modpack.json")
+    })
+}
+
+/// Reads the manifest with an ssh session to the host. Blocking.
+///
+/// `None` if the pack has none or it can't be read.
+pub fn read(sess: &ssh2::Session, path: &str) -> Option<Modpack> {
+    let json = ssh::run(
+        sess,
+        &format!("cat {} 2>/dev/null || true", ssh::quote(path)),
+    )
+    .map_err(|e| tracing::warn!("failed to read the modpack manifest: {e}"))
+    .ok()?;
+    parse(&json, path)
+}
+
+fn parse(json: &str, path: &str) -> Option<Modpack> {
+    if json.trim().is_empty() {
+        return None;
+    }
+    serde_json::from_str(json)
+        .map_err(|e| tracing::warn!("the modpack manifest at {path} is broken: {e}"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let json = r#"{
+  "version": "3fa2c1d9e07b",
+  "minecraft": "1.18.1",
+  "forge": null,
+  "built": 1659348000,
+  "url": "https://siyuanyan.net/commupack/commupack-3fa2c1d9e07b.zip",
+  "files": [
+    {
+      "path": "mods/jei-1.18.1-9.1.2.jar",
+      "size": 4,
+      "sha256": "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+    }
+  ]
+}"#;
+        let pack = parse(json, "modpack.json").unwrap();
+        assert_eq!(pack.version, "3fa2c1d9e07b");
+        assert_eq!(pack.forge, None);
+        assert_eq!(pack.files[0].size, 4);
+
+        assert_eq!(parse("", "modpack.json"), None);
+        assert_eq!(parse("{", "modpack.json"), None);
+    }
+}
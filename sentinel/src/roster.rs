@@ -0,0 +1,518 @@
+//! The whitelist, ops and bans: read from the server's json files, edited through rcon while the
+//! server runs and in the files themselves while it doesn't.
+//!
+//! The image also fills the whitelist and the ops from the compose environment on every start, so
+//! edits are mirrored there too or the next restart would undo them.
+
+use crate::compose;
+use crate::config::{ComposeEnv, ConfigFiles, COMPOSE_EDIT};
+use crate::ssh;
+use common::{ListedPlayer, PlayerList, Roster, RosterEdit};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Looks up a player's uuid by name.
+const MOJANG: &str = "https://api.mojang.com/users/profiles/minecraft";
+
+/// How long a looked up uuid is trusted, names change hands.
+const REMEMBER_FOR: Duration = Duration::from_secs(30 * 24 * 3600);
+
+const LISTS: [PlayerList; 3] = [PlayerList::Whitelist, PlayerList::Ops, PlayerList::Bans];
+
+pub fn describe(list: PlayerList) -> &'static str {
+    match list {
+        PlayerList::Whitelist => "whitelist",
+        PlayerList::Ops => "ops",
+        PlayerList::Bans => "bans",
+    }
+}
+
+fn file(list: PlayerList) -> &'static str {
+    match list {
+        PlayerList::Whitelist => "whitelist.json",
+        PlayerList::Ops => "ops.json",
+        PlayerList::Bans => "banned-players.json",
+    }
+}
+
+/// The compose environment variable the image fills `list` from, comma separated names or uuids.
+fn env_key(list: PlayerList) -> Option<&'static str> {
+    match list {
+        PlayerList::Whitelist => Some("WHITELIST"),
+        PlayerList::Ops => Some("OPS"),
+        PlayerList::Bans => None,
+    }
+}
+
+/// Checks what can be checked without asking anyone.
+pub fn check(edit: &RosterEdit) -> Result<(), String> {
+    let name = &edit.name;
+    if name.is_empty()
+        || name.len() > 16
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!("`{name}` can't be a minecraft name"));
+    }
+    match &edit.reason {
+        Some(_) if !(edit.list == PlayerList::Bans && edit.add) => {
+            Err("only new bans have a reason".to_string())
+        }
+        Some(reason) if reason.contains('\n') || reason.chars().count() > 200 => {
+            Err("the reason has to be a single line of at most 200 characters".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The console command behind `edit`.
+fn rcon_command(edit: &RosterEdit) -> String {
+    let name = &edit.name;
+    match (edit.list, edit.add) {
+        (PlayerList::Whitelist, true) => format!("whitelist add {name}"),
+        (PlayerList::Whitelist, false) => format!("whitelist remove {name}"),
+        (PlayerList::Ops, true) => format!("op {name}"),
+        (PlayerList::Ops, false) => format!("deop {name}"),
+        (PlayerList::Bans, true) => match &edit.reason {
+            Some(reason) => format!("ban {name} {reason}"),
+            None => format!("ban {name}"),
+        },
+        (PlayerList::Bans, false) => format!("pardon {name}"),
+    }
+}
+
+/// `id` with the dashes the server's files use.
+fn dashed(id: &str) -> String {
+    let id = id.replace('-', "").to_lowercase();
+    if id.len() != 32 {
+        return id;
+    }
+    format!(
+        "{}-{}-{}-{}-{}",
+        &id[..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..]
+    )
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A player whose uuid we know.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Known {
+    /// With dashes.
+    uuid: String,
+    name: String,
+    /// When we learned it, in seconds since the unix epoch.
+    at: u64,
+}
+
+/// Names to uuids, kept in a json file so Mojang is asked about everyone only once a while.
+struct PlayerCache {
+    path: String,
+    known: Mutex<HashMap<String, Known>>,
+}
+
+impl PlayerCache {
+    fn load(path: String) -> Self {
+        let known = match std::fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                tracing::warn!("ignoring the player cache at {path}: {e}");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path,
+            known: Mutex::new(known),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<Known> {
+        let known = self.known.lock().unwrap();
+        let player = known.get(&name.to_lowercase())?;
+        (now().saturating_sub(player.at) < REMEMBER_FOR.as_secs()).then(|| player.clone())
+    }
+
+    /// Remembers `players`, writing the cache down if any of them is news.
+    fn remember(&self, players: impl IntoIterator<Item = Known>) {
+        let mut known = self.known.lock().unwrap();
+        let mut news = false;
+        for player in players {
+            let key = player.name.to_lowercase();
+            if known.get(&key).map(|k| &k.uuid) != Some(&player.uuid) {
+                news = true;
+            }
+            known.insert(key, player);
+        }
+        if news {
+            let written = serde_json::to_string(&*known)
+                .map_err(io::Error::from)
+                .and_then(|s| std::fs::write(&self.path, s));
+            if let Err(e) = written {
+                tracing::warn!("failed to write the player cache to {}: {e}", self.path);
+            }
+        }
+    }
+}
+
+/// An entry of one of the server's list files, the fields we don't read are kept as they are.
+#[derive(Serialize, Deserialize, Debug)]
+struct FileEntry {
+    uuid: String,
+    name: String,
+    #[serde(flatten)]
+    rest: Map<String, Value>,
+}
+
+fn parse(json: &str) -> io::Result<Vec<FileEntry>> {
+    if json.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_str(json)?)
+}
+
+fn listed(json: &str, list: PlayerList) -> io::Result<Vec<ListedPlayer>> {
+    Ok(parse(json)?
+        .into_iter()
+        .map(|e| ListedPlayer {
+            detail: match list {
+                PlayerList::Whitelist => None,
+                PlayerList::Ops => e.rest.get("level").map(Value::to_string),
+                PlayerList::Bans => e
+                    .rest
+                    .get("reason")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+            },
+            uuid: e.uuid,
+            name: e.name,
+        })
+        .collect())
+}
+
+/// `json` with `player` added to or removed from it, as the server would write it.
+///
+/// Bans need the server to write them, they carry its timestamps.
+fn edited(json: &str, list: PlayerList, player: &Known, add: bool) -> io::Result<String> {
+    let mut entries = parse(json)?;
+    entries.retain(|e| e.uuid != player.uuid);
+    if add {
+        let mut rest = Map::new();
+        if list == PlayerList::Ops {
+            rest.insert("level".to_string(), 4.into());
+            rest.insert("bypassesPlayerLimit".to_string(), false.into());
+        }
+        entries.push(FileEntry {
+            uuid: player.uuid.clone(),
+            name: player.name.clone(),
+            rest,
+        });
+    }
+    Ok(serde_json::to_string_pretty(&entries)?)
+}
+
+/// The comma separated `value` of an environment variable like `OPS`, with `player` added to or
+/// removed from it. `None` if it already agrees.
+fn synced(value: &str, player: &Known, add: bool) -> Option<String> {
+    let is_player =
+        |entry: &str| entry.eq_ignore_ascii_case(&player.name) || dashed(entry) == player.uuid;
+    let dashless = player.uuid.replace('-', "");
+    let mut entries: Vec<&str> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .collect();
+    let listed = entries.iter().any(|e| is_player(e));
+    match (add, listed) {
+        (true, false) => entries.push(&dashless),
+        (false, true) => entries.retain(|e| !is_player(e)),
+        _ => return None,
+    }
+    Some(entries.join(","))
+}
+
+/// See the module docs and the README for the env vars.
+pub struct RosterKeeper {
+    data_dir: String,
+    container: String,
+    config: ConfigFiles,
+    cache: PlayerCache,
+}
+
+impl RosterKeeper {
+    pub fn from_env() -> Self {
+        Self {
+            data_dir: dotenv::var("MC_DATA_DIR").unwrap_or_else(|_| "/opt/mc".to_string()),
+            container: dotenv::var("MC_CONTAINER").unwrap_or_else(|_| "root-mc-1".to_string()),
+            config: ConfigFiles::from_env(),
+            cache: PlayerCache::load(
+                dotenv::var("PLAYER_CACHE").unwrap_or_else(|_| "players.json".to_string()),
+            ),
+        }
+    }
+
+    fn path(&self, list: PlayerList) -> String {
+        format!("{}/{}", self.data_dir, file(list))
+    }
+
+    /// The file behind `list`, empty if the server hasn't written it yet. Blocking.
+    fn cat(&self, sess: &ssh2::Session, list: PlayerList) -> io::Result<String> {
+        let path = ssh::quote(&self.path(list));
+        ssh::run(sess, &format!("cat {path} 2>/dev/null || true"))
+    }
+
+    /// Blocking.
+    fn load(&self, sess: &ssh2::Session) -> io::Result<Roster> {
+        let mut roster = Roster::default();
+        for list in LISTS {
+            let players = listed(&self.cat(sess, list)?, list)?;
+            let at = now();
+            self.cache.remember(players.iter().map(|p| Known {
+                uuid: p.uuid.clone(),
+                name: p.name.clone(),
+                at,
+            }));
+            match list {
+                PlayerList::Whitelist => roster.whitelist = players,
+                PlayerList::Ops => roster.ops = players,
+                PlayerList::Bans => roster.bans = players,
+            }
+        }
+        Ok(roster)
+    }
+
+    /// The player called `name`, from the cache or Mojang. Blocking.
+    ///
+    /// Mojang is asked from the host, sentinel has no business on the internet otherwise.
+    fn resolve(&self, sess: &ssh2::Session, name: &str) -> io::Result<Known> {
+        if let Some(known) = self.cache.get(name) {
+            return Ok(known);
+        }
+        let answer = ssh::run(
+            sess,
+            &format!("curl -sS --max-time 5 -w '\\n%{{http_code}}' '{MOJANG}/{name}'"),
+        )?;
+        let (body, code) = answer.trim_end().rsplit_once('\n').unwrap_or(("", &answer));
+        match code.trim() {
+            "200" => {
+                #[derive(Deserialize)]
+                struct Profile {
+                    id: String,
+                    name: String,
+                }
+                let profile: Profile = serde_json::from_str(body)?;
+                let known = Known {
+                    uuid: dashed(&profile.id),
+                    name: profile.name,
+                    at: now(),
+                };
+                self.cache.remember([known.clone()]);
+                Ok(known)
+            }
+            "204" | "404" => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no minecraft player is called {name}"),
+            )),
+            code => Err(io::Error::other(format!("mojang answered with {code}"))),
+        }
+    }
+
+    /// Mirrors the edit to the compose environment, if it has the list. Blocking.
+    fn sync_env(
+        &self,
+        sess: &ssh2::Session,
+        list: PlayerList,
+        player: &Known,
+        add: bool,
+    ) -> io::Result<()> {
+        let key = match env_key(list) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let _editing = COMPOSE_EDIT.lock().unwrap();
+        let compose = ssh::run(sess, &format!("cat {}", ssh::quote(&self.config.compose)))?;
+        let mut env = ComposeEnv::parse(&compose, &self.config.service)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // without it the image leaves the list alone
+        if let Some(synced) = env.get(key).and_then(|v| synced(&v, player, add)) {
+            env.set(key, &synced);
+            ssh::write(sess, &self.config.compose, &env.render())?;
+        }
+        Ok(())
+    }
+
+    /// Every list as the server has them.
+    pub async fn read(self: &Arc<Self>) -> io::Result<Roster> {
+        let keeper = self.clone();
+        tokio::task::spawn_blocking(move || keeper.load(&ssh::connect()?))
+            .await
+            .expect("reading the roster doesn't panic")
+    }
+
+    /// Carries out an edit that passed [`check`].
+    ///
+    /// Returns what the server said. Fails with [`io::ErrorKind::NotFound`] if nobody has the
+    /// name.
+    pub async fn edit(self: &Arc<Self>, edit: RosterEdit) -> io::Result<String> {
+        let keeper = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let sess = ssh::connect()?;
+            let player = keeper.resolve(&sess, &edit.name)?;
+            let rcon = format!(
+                "docker exec {} rcon-cli {}",
+                keeper.container,
+                ssh::quote(&rcon_command(&edit))
+            );
+            let said = match ssh::run(&sess, &rcon) {
+                Ok(said) => String::from_utf8(strip_ansi_escapes::strip(said)?)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+                Err(e) if edit.list == PlayerList::Bans && edit.add => {
+                    return Err(io::Error::other(format!(
+                        "the server has to be running to ban someone: {e}"
+                    )))
+                }
+                Err(e) => {
+                    // a running server would write over the file on its next save
+                    if compose::running(&sess, &keeper.container)? {
+                        return Err(io::Error::other(format!(
+                            "the server is running but rcon failed: {e}"
+                        )));
+                    }
+                    // the server is down, it reads the file when it comes back
+                    tracing::info!("rcon failed, editing {} instead: {e}", file(edit.list));
+                    let path = keeper.path(edit.list);
+                    let json = keeper.cat(&sess, edit.list)?;
+                    ssh::write(&sess, &path, &edited(&json, edit.list, &player, edit.add)?)?;
+                    format!(
+                        "the server is down, sentinel edited {} instead",
+                        file(edit.list)
+                    )
+                }
+            };
+            keeper.sync_env(&sess, edit.list, &player, edit.add)?;
+            Ok(said)
+        })
+        .await
+        .expect("editing the roster doesn't panic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPS: &str = r#"[
+  {
+    "uuid": "25130343-44e0-4f09-9e8e-8ac79b8b27f5",
+    "name": "Madoshakalaka",
+    "level": 4,
+    "bypassesPlayerLimit": false
+  }
+]"#;
+
+    const BANS: &str = r#"[{"uuid":"1123d822-9e6f-4fbc-87d0-665549e729e7","name":"griefer_42","created":"2022-08-01 10:00:00 +0000","source":"Server","expires":"forever","reason":"lava in the town hall"}]"#;
+
+    fn known(name: &str, uuid: &str) -> Known {
+        Known {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            at: 0,
+        }
+    }
+
+    fn edit(list: PlayerList, name: &str, add: bool, reason: Option<&str>) -> RosterEdit {
+        RosterEdit {
+            list,
+            name: name.to_string(),
+            add,
+            reason: reason.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_listed() {
+        assert_eq!(
+            listed(OPS, PlayerList::Ops).unwrap(),
+            vec![ListedPlayer {
+                uuid: "25130343-44e0-4f09-9e8e-8ac79b8b27f5".to_string(),
+                name: "Madoshakalaka".to_string(),
+                detail: Some("4".to_string()),
+            }]
+        );
+        assert_eq!(
+            listed(BANS, PlayerList::Bans).unwrap()[0].detail.as_deref(),
+            Some("lava in the town hall")
+        );
+        assert!(listed("", PlayerList::Whitelist).unwrap().is_empty());
+        assert!(listed("{", PlayerList::Whitelist).is_err());
+    }
+
+    #[test]
+    fn test_edited_keeps_what_it_doesnt_know() {
+        let steve = known("Steve", "8667ba71-b85a-4004-af54-457a9734eed7");
+        let added = edited(OPS, PlayerList::Ops, &steve, true).unwrap();
+        let players = listed(&added, PlayerList::Ops).unwrap();
+        assert_eq!(players.len(), 2);
+        assert_eq!(players[1].name, "Steve");
+        assert!(added.contains("\"bypassesPlayerLimit\": false"));
+
+        let matt = known("Madoshakalaka", "25130343-44e0-4f09-9e8e-8ac79b8b27f5");
+        let removed = edited(&added, PlayerList::Ops, &matt, false).unwrap();
+        assert_eq!(listed(&removed, PlayerList::Ops).unwrap()[0].name, "Steve");
+
+        let whitelisted = edited("", PlayerList::Whitelist, &steve, true).unwrap();
+        assert!(!whitelisted.contains("level"));
+    }
+
+    #[test]
+    fn test_synced() {
+        let matt = known("Madoshakalaka", "25130343-44e0-4f09-9e8e-8ac79b8b27f5");
+        let ops = "2513034344e04f099e8e8ac79b8b27f5,1123d8229e6f4fbc87d0665549e729e7";
+        assert_eq!(synced(ops, &matt, true), None);
+        assert_eq!(
+            synced(ops, &matt, false).as_deref(),
+            Some("1123d8229e6f4fbc87d0665549e729e7")
+        );
+        assert_eq!(
+            synced("madoshakalaka, Steve", &matt, false).as_deref(),
+            Some("Steve")
+        );
+        assert_eq!(
+            synced("", &matt, true).as_deref(),
+            Some("2513034344e04f099e8e8ac79b8b27f5")
+        );
+    }
+
+    #[test]
+    fn test_check_and_rcon_command() {
+        let ban = edit(PlayerList::Bans, "griefer_42", true, Some("lava, again"));
+        assert_eq!(check(&ban), Ok(()));
+        assert_eq!(rcon_command(&ban), "ban griefer_42 lava, again");
+        assert_eq!(ssh::quote("it's"), r"'it'\''s'",);
+
+        assert!(check(&edit(PlayerList::Ops, "x; rm -rf /", true, None)).is_err());
+        assert!(check(&edit(PlayerList::Ops, "Steve", true, Some("why"))).is_err());
+        assert!(check(&edit(PlayerList::Bans, "Steve", true, Some("a\nb"))).is_err());
+        assert_eq!(
+            rcon_command(&edit(PlayerList::Whitelist, "Steve", false, None)),
+            "whitelist remove Steve"
+        );
+        assert_eq!(
+            dashed("2513034344E04F099E8E8AC79B8B27F5"),
+            "25130343-44e0-4f09-9e8e-8ac79b8b27f5"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Every live websocket connection, so the poller knows when to stop and admins can see who is
+//! watching.
+
+use common::{Role, Session};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+
+struct Live {
+    who: String,
+    ip: IpAddr,
+    role: Option<Role>,
+    since: Instant,
+    last_heard: Instant,
+}
+
+pub struct Sessions {
+    next_id: AtomicU64,
+    live: Mutex<BTreeMap<u64, Live>>,
+    joined: Notify,
+    published: watch::Sender<Vec<Session>>,
+}
+
+impl Sessions {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            next_id: AtomicU64::new(0),
+            live: Mutex::new(BTreeMap::new()),
+            joined: Notify::new(),
+            published: watch::channel(Vec::new()).0,
+        })
+    }
+
+    /// Registers a connection until the returned guard is dropped.
+    pub fn join(self: &Arc<Self>, who: String, ip: IpAddr) -> SessionGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        {
+            let mut live = self.live.lock().unwrap();
+            live.insert(
+                id,
+                Live {
+                    who,
+                    ip,
+                    role: None,
+                    since: now,
+                    last_heard: now,
+                },
+            );
+            self.publish(&live);
+        }
+        self.joined.notify_waiters();
+
+        SessionGuard {
+            sessions: self.clone(),
+            id,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live.lock().unwrap().is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.live.lock().unwrap().len()
+    }
+
+    /// Returns once there is at least one session.
+    pub async fn occupied(&self) {
+        loop {
+            let joined = self.joined.notified();
+            if !self.is_empty() {
+                return;
+            }
+            joined.await;
+        }
+    }
+
+    /// Updated whenever a session comes, goes or authenticates.
+    pub fn subscribe(&self) -> watch::Receiver<Vec<Session>> {
+        self.published.subscribe()
+    }
+
+    fn publish(&self, live: &BTreeMap<u64, Live>) {
+        let sessions = live
+            .iter()
+            .map(|(id, l)| Session {
+                id: *id,
+                who: l.who.clone(),
+                ip: l.ip.to_string(),
+                role: l.role,
+                connected_secs: l.since.elapsed().as_secs(),
+            })
+            .collect();
+        self.published.send_replace(sessions);
+    }
+}
+
+/// A connection's place in [`Sessions`], given up on drop however the connection ends.
+pub struct SessionGuard {
+    sessions: Arc<Sessions>,
+    id: u64,
+}
+
+impl SessionGuard {
+    fn with<T>(&self, f: impl FnOnce(&mut Live) -> T) -> T {
+        let mut live = self.sessions.live.lock().unwrap();
+        f(live.get_mut(&self.id).expect("a guarded session is live"))
+    }
+
+    /// Anything at all arrived from the client.
+    pub fn heard(&self) {
+        self.with(|l| l.last_heard = Instant::now());
+    }
+
+    pub fn silent_for(&self) -> Duration {
+        self.with(|l| l.last_heard.elapsed())
+    }
+
+    pub fn authenticated(&self, role: Role) {
+        let mut live = self.sessions.live.lock().unwrap();
+        if let Some(l) = live.get_mut(&self.id) {
+            l.role = Some(role);
+        }
+        self.sessions.publish(&live);
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let mut live = self.sessions.live.lock().unwrap();
+        live.remove(&self.id);
+        self.sessions.publish(&live);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sessions_come_and_go() {
+        let sessions = Sessions::new();
+        let published = sessions.subscribe();
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let phone = sessions.join("phone".to_string(), ip);
+        let laptop = sessions.join("laptop".to_string(), ip);
+        tokio::time::timeout(Duration::from_secs(1), sessions.occupied())
+            .await
+            .unwrap();
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        laptop.heard();
+        assert_eq!(phone.silent_for(), Duration::from_secs(10));
+        assert_eq!(laptop.silent_for(), Duration::ZERO);
+
+        phone.authenticated(Role::Member);
+        assert_eq!(published.borrow()[0].role, Some(Role::Member));
+        assert_eq!(published.borrow()[0].connected_secs, 10);
+
+        drop(phone);
+        assert_eq!(published.borrow().len(), 1);
+        assert_eq!(published.borrow()[0].who, "laptop");
+
+        drop(laptop);
+        assert!(sessions.is_empty());
+        assert!(published.borrow().is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+//! Running commands on the minecraft host.
+
+use ssh2::{Channel, Session};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A session as root on `MC_HOST`. Blocking.
+pub fn connect() -> io::Result<Session> {
+    let ip = IpAddr::from_str(&dotenv::var("MC_HOST").unwrap())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let tcp = TcpStream::connect_timeout(&SocketAddr::new(ip, 22), Duration::from_secs(3))?;
+    let mut sess = Session::new()?;
+    sess.set_tcp_stream(tcp);
+    sess.handshake()?;
+    sess.userauth_pubkey_file(
+        "root",
+        None,
+        dotenv::var("PRIVATE_KEY").unwrap().as_ref(),
+        None,
+    )?;
+    sess.set_keepalive(false, 40);
+    Ok(sess)
+}
+
+/// Runs `command` and returns its stdout, or its stderr as the error if it exits non-zero.
+/// Blocking.
+pub fn run(sess: &Session, command: &str) -> io::Result<String> {
+    let mut channel = sess.channel_session()?;
+    channel.exec(command)?;
+    sess.set_blocking(false);
+    let drained = drain(&channel);
+    sess.set_blocking(true);
+    let (out, err) = drained?;
+    channel.wait_close()?;
+    match channel.exit_status()? {
+        0 => Ok(out),
+        code => Err(io::Error::other(format!(
+            "`{command}` exited with {code}: {}",
+            err.trim()
+        ))),
+    }
+}
+
+/// Reads stdout and stderr of `channel` to their ends together, since a command can't go on
+/// writing to one while the other is full. The session has to be non-blocking.
+fn drain(channel: &Channel) -> io::Result<(String, String)> {
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let mut streams = [(channel.stream(0), &mut out), (channel.stderr(), &mut err)];
+    let mut buf = [0; 8192];
+    loop {
+        let mut read = false;
+        for (stream, into) in streams.iter_mut() {
+            match stream.read(&mut buf) {
+                Ok(0) => {}
+                Ok(n) => {
+                    into.extend_from_slice(&buf[..n]);
+                    read = true;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if !read {
+            if channel.eof() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+    Ok((
+        String::from_utf8_lossy(&out).into_owned(),
+        String::from_utf8_lossy(&err).into_owned(),
+    ))
+}
+
+/// Quotes `s` as a single shell word.
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Replaces the file at `path` with `contents`, keeping the old one as `<path>.bak`. Blocking.
+///
+/// The new file is uploaded next to the old one first, given its owner and mode, and moved over
+/// it, so the old one is never half written.
+pub fn write(sess: &Session, path: &str, contents: &str) -> io::Result<()> {
+    let upload = format!("{path}.sentinel-new");
+    let mut remote = sess.scp_send(Path::new(&upload), 0o644, contents.len() as u64, None)?;
+    remote.write_all(contents.as_bytes())?;
+    remote.send_eof()?;
+    remote.wait_eof()?;
+    remote.close()?;
+    remote.wait_close()?;
+    let (path, backup, upload) = (quote(path), quote(&format!("{path}.bak")), quote(&upload));
+    run(
+        sess,
+        &format!(
+            "if [ -e {path} ]; then cp -p {path} {backup} \
+             && chown --reference={path} {upload} && chmod --reference={path} {upload}; fi \
+             && mv {upload} {path}"
+        ),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,210 @@
+//! Talking to one websocket client in whichever protocol version it understands,
+//! see `common::protocol`.
+
+use axum::extract::ws::{Message, WebSocket};
+use bincode::Decode;
+use common::protocol::{Envelope, Hello};
+use common::{ClientOpt, ConfigEdit, Newspeak, Oldspeak, RosterEdit};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// How often sentinel pings every client.
+pub const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A client that said nothing for this long, not even a pong, is assumed gone.
+pub const SILENCE_LIMIT: Duration = Duration::from_secs(45);
+
+pub enum Dialect {
+    /// Version 0, no handshake and no envelopes.
+    Legacy,
+    /// Version 1 and later, with what both sides agreed on in the handshake.
+    Framed(Hello),
+}
+
+/// What the client said, whatever the dialect.
+pub enum Heard {
+    Password(String),
+    /// `id` is the envelope id to respond to, always 0 for version 0 clients.
+    Opt {
+        id: u64,
+        opt: ClientOpt,
+    },
+    /// Wants a [`Newspeak::Heartbeat`] back.
+    Heartbeat,
+    ReadConfig,
+    ReviewConfig {
+        edits: Vec<ConfigEdit>,
+        apply: bool,
+    },
+    ReadRoster,
+    EditRoster(RosterEdit),
+    Close,
+    /// Pings, pongs and anything we don't understand.
+    Nothing,
+}
+
+pub struct Speaker {
+    sender: Mutex<SplitSink<WebSocket, Message>>,
+    dialect: Dialect,
+    next_id: AtomicU64,
+}
+
+fn decode<T: Decode>(bytes: &[u8]) -> Option<T> {
+    bincode::decode_from_slice(bytes, bincode::config::standard())
+        .ok()
+        .map(|(t, _)| t)
+}
+
+/// Waits for the client's first message, which tells its dialect, and answers a hello.
+///
+/// Version 0 consoles stay silent until someone types the password, which they send as text,
+/// while later ones open with a binary hello. However slow either is, the client is pinged
+/// meanwhile and let go once it stops answering.
+///
+/// Returns the speaker and, for a version 0 client, the message it sent, which still has to be
+/// handled. Returns `None` if the client left.
+pub async fn handshake(
+    mut sender: SplitSink<WebSocket, Message>,
+    receiver: &mut SplitStream<WebSocket>,
+) -> Option<(Speaker, Option<Message>)> {
+    let mut pings = tokio::time::interval(PING_INTERVAL);
+    let mut heard = Instant::now();
+    let first = loop {
+        tokio::select! {
+            message = receiver.next() => match message {
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => heard = Instant::now(),
+                Some(Ok(m)) => break m,
+                _ => return None,
+            },
+            _ = pings.tick() => {
+                if heard.elapsed() > SILENCE_LIMIT {
+                    sender.send(Message::Close(None)).await.ok();
+                    return None;
+                }
+                sender.send(Message::Ping(Vec::new())).await.ok()?;
+            }
+        }
+    };
+
+    if let Message::Binary(d) = &first {
+        if let Some(Envelope {
+            body: Oldspeak::Hello(hello),
+            ..
+        }) = decode::<Envelope<Oldspeak>>(d)
+        {
+            let agreed = Hello::ours().agree(&hello);
+            tracing::debug!(
+                "client speaks version {}, agreed on {agreed:?}",
+                hello.version
+            );
+            let speaker = Speaker::new(sender, Dialect::Framed(agreed.clone()));
+            speaker.say(Newspeak::Hello(agreed)).await.ok()?;
+            return Some((speaker, None));
+        }
+    }
+
+    Some((Speaker::new(sender, Dialect::Legacy), Some(first)))
+}
+
+impl Speaker {
+    fn new(sender: SplitSink<WebSocket, Message>, dialect: Dialect) -> Self {
+        Self {
+            sender: Mutex::new(sender),
+            dialect,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the client agreed on an optional `capability` in the handshake.
+    pub fn can(&self, capability: &str) -> bool {
+        match &self.dialect {
+            Dialect::Legacy => false,
+            Dialect::Framed(agreed) => agreed.has(capability),
+        }
+    }
+
+    pub async fn say(&self, newspeak: Newspeak) -> Result<(), axum::Error> {
+        let config = bincode::config::standard();
+        let encoded = match &self.dialect {
+            Dialect::Legacy => bincode::encode_to_vec(newspeak, config),
+            Dialect::Framed(_) => bincode::encode_to_vec(
+                Envelope {
+                    id: self.next_id.fetch_add(1, Ordering::Relaxed),
+                    body: newspeak,
+                },
+                config,
+            ),
+        }
+        .expect("newspeak always encodes");
+
+        self.sender
+            .lock()
+            .await
+            .send(Message::Binary(encoded))
+            .await
+    }
+
+    pub fn hear(&self, message: Message) -> Heard {
+        match (message, &self.dialect) {
+            (Message::Close(_), _) => Heard::Close,
+            (Message::Text(p), Dialect::Legacy) => Heard::Password(p),
+            (Message::Binary(d), Dialect::Legacy) => {
+                decode::<ClientOpt>(&d).map_or(Heard::Nothing, |opt| Heard::Opt { id: 0, opt })
+            }
+            (Message::Binary(d), Dialect::Framed(_)) => match decode::<Envelope<Oldspeak>>(&d) {
+                Some(Envelope {
+                    body: Oldspeak::Password(p),
+                    ..
+                }) => Heard::Password(p),
+                Some(Envelope {
+                    id,
+                    body: Oldspeak::Opt(opt),
+                }) => Heard::Opt { id, opt },
+                Some(Envelope {
+                    body: Oldspeak::Heartbeat,
+                    ..
+                }) => Heard::Heartbeat,
+                Some(Envelope {
+                    body: Oldspeak::ReadConfig,
+                    ..
+                }) => Heard::ReadConfig,
+                Some(Envelope {
+                    body: Oldspeak::ReviewConfig { edits, apply },
+                    ..
+                }) => Heard::ReviewConfig { edits, apply },
+                Some(Envelope {
+                    body: Oldspeak::ReadRoster,
+                    ..
+                }) => Heard::ReadRoster,
+                Some(Envelope {
+                    body: Oldspeak::EditRoster(edit),
+                    ..
+                }) => Heard::EditRoster(edit),
+                _ => Heard::Nothing,
+            },
+            _ => Heard::Nothing,
+        }
+    }
+
+    /// The client's websocket implementation answers with a pong, browsers do so on their own.
+    pub async fn ping(&self) -> Result<(), axum::Error> {
+        self.sender
+            .lock()
+            .await
+            .send(Message::Ping(Vec::new()))
+            .await
+    }
+
+    pub async fn close(&self) {
+        self.sender
+            .lock()
+            .await
+            .send(Message::Close(None))
+            .await
+            .ok();
+    }
+}